@@ -1,29 +1,132 @@
-use crate::agent::ChatRespStream;
-use crate::model::{Message, Model, ModelConfig, Response};
-use std::collections::VecDeque;
+use crate::agent::{
+    history_token_budget, AgentDocument, ChatRespStream, HistoryPolicy, LongTermMemory, Memory,
+    MemoryContext, SlidingWindowMemory, Tool, UsageLedger, AGENT_DOCUMENT_VERSION,
+};
+use crate::knowledge::{knowledge_message, Retriever};
+use crate::model::tokenizer::{tokenizer_for_model, Tokenizer};
+use crate::prompt::{PromptEnv, PromptTemplate};
+use crate::store::ConversationStore;
+use crate::model::content::ContentPart;
+use crate::model::retry::{RetryModel, RetryPolicy};
+use crate::model::{
+    FinishReason, Message, Model, ModelConfig, Response, ResponseEvent, ToolCall, Usage,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicI8, Ordering};
 use std::sync::Arc;
+use tokio::sync::Notify;
 use wd_tools::PFErr;
 use wd_tools::sync::Am;
 
+const AGENT_SAVE_DIR: &str = "AGENT_SAVE_DIR";
+const AGENT_DEFAULT_SAVE_DIR: &str = "./data/agent";
+
+#[repr(i8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentStatus {
+    /// 可用
+    Usable = 1,
+    /// 回复中
+    Replying = 2,
+    /// 终止回复
+    Canceling = 3,
+}
+impl From<i8> for AgentStatus {
+    fn from(value: i8) -> Self {
+        match value {
+            2 => AgentStatus::Replying,
+            3 => AgentStatus::Canceling,
+            _ => AgentStatus::Usable,
+        }
+    }
+}
+impl Display for AgentStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AgentStatus::Usable => write!(f, "usable"),
+            AgentStatus::Replying => write!(f, "replying"),
+            AgentStatus::Canceling => write!(f, "canceling"),
+        }
+    }
+}
+
+/// 取消回复时，如何处理本轮对话
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelPolicy {
+    /// 丢弃本轮的提问和回复
+    #[default]
+    Rollback,
+    /// 保留提问和已经生成的部分回复，没有生成任何文本时与Rollback相同
+    KeepPartial,
+}
+
 pub struct SingleAgent {
-    //1:可用 2:回复中 3:终止回复
+    /// 持久化时的文档id
+    pub id: String,
+    /// 持久化文档的目录，默认读取env[AGENT_SAVE_DIR]
+    pub save_dir: PathBuf,
     status: Arc<AtomicI8>,
+    //当前回复的取消信号，没有在回复时为None
+    cancel: Arc<Am<Option<Arc<Notify>>>>,
+    /// 提示词模板，每次chat时用prompt_env渲染
     pub prompt: String,
+    pub prompt_env: PromptEnv,
     pub model_config: ModelConfig,
-    pub model: Box<dyn Model + Sync>,
+    pub model: Arc<dyn Model + Sync>,
     pub history: Arc<Am<VecDeque<Message>>>,
     pub max_history: usize,
+    pub history_policy: HistoryPolicy,
+    /// 为空时按模型名选择
+    pub tokenizer: Option<Arc<dyn Tokenizer>>,
+    /// 记忆策略，默认为滑动窗口
+    pub memory: Arc<dyn Memory>,
+    /// 跨会话的长期记忆，提问前检索注入，回复完成后写入
+    pub long_term: Option<Arc<LongTermMemory>>,
+    /// 知识库检索器，提问前检索资料注入到请求中，并要求模型标注引用来源
+    pub knowledge: Option<Arc<dyn Retriever>>,
+    pub knowledge_top_k: usize,
+    pub tools: HashMap<String, Arc<dyn Tool>>,
+    //单次回复中最多进行几轮工具调用
+    pub max_tool_rounds: usize,
+    pub cancel_policy: CancelPolicy,
+    /// 挂载后每一轮完成的对话都会以id为会话id写入
+    pub store: Option<Arc<dyn ConversationStore>>,
+    /// 当前会话累计的token用量，包括被回滚的轮次
+    pub usage: Arc<Am<Usage>>,
+    /// 按天汇总的用量，可以在多个agent之间共享
+    pub ledger: Option<Arc<UsageLedger>>,
 }
 impl SingleAgent {
     pub fn new<M:Model+Sync+'static>(model:M)->Self{
+        let save_dir = std::env::var(AGENT_SAVE_DIR).unwrap_or(AGENT_DEFAULT_SAVE_DIR.to_string());
         Self{
-            status:Arc::new(AtomicI8::new(1)),
+            id: wd_tools::uuid::v4(),
+            save_dir: save_dir.into(),
+            status:Arc::new(AtomicI8::new(AgentStatus::Usable as i8)),
+            cancel: Arc::new(Am::new(None)),
             prompt:"".into(),
+            prompt_env: PromptEnv::default(),
             model_config: Default::default(),
-            model: Box::new(model),
+            model: Arc::new(model),
             history: Arc::new(Am::new(VecDeque::new())),
             max_history: 30,
+            history_policy: HistoryPolicy::default(),
+            tokenizer: None,
+            memory: Arc::new(SlidingWindowMemory),
+            long_term: None,
+            knowledge: None,
+            knowledge_top_k: 4,
+            tools: HashMap::new(),
+            max_tool_rounds: 8,
+            cancel_policy: CancelPolicy::default(),
+            store: None,
+            usage: Arc::new(Am::new(Usage::default())),
+            ledger: None,
         }
     }
     pub fn cove_chat_history(mut self,msg_list:VecDeque<Message>)->Self{
@@ -32,58 +135,311 @@ impl SingleAgent {
     pub fn set_prompt<P:Into<String>>(mut self,prompt:P)->Self{
         self.prompt = prompt.into();self
     }
+    /// 从文件读取提示词模板，模板语法错误时返回错误
+    pub fn load_prompt<P:AsRef<Path>>(mut self,path:P)->anyhow::Result<Self>{
+        let prompt = std::fs::read_to_string(path)?;
+        PromptTemplate::parse(prompt.as_str())?;
+        self.prompt = prompt;
+        Ok(self)
+    }
+    pub fn set_prompt_var<K:Into<String>,V:Into<String>>(mut self,k:K,v:V)->Self{
+        self.prompt_env = self.prompt_env.set_var(k,v);self
+    }
+    pub fn set_prompt_partial<K:Into<String>,V:Into<String>>(mut self,name:K,source:V)->Self{
+        self.prompt_env = self.prompt_env.set_partial(name,source);self
+    }
+    pub fn cove_prompt_env(mut self,env:PromptEnv)->Self{
+        self.prompt_env = env;self
+    }
+    /// 用当前的变量渲染提示词，内置的日期、剪贴板等变量每次都会重新求值
+    pub fn render_prompt(&self)->anyhow::Result<String>{
+        if !self.prompt.contains("{{") {
+            return Ok(self.prompt.clone());
+        }
+        PromptTemplate::parse(self.prompt.as_str())?.render(&self.prompt_env)
+    }
     pub fn set_max_history(mut self,max:usize)->Self{
         self.max_history = max;self
     }
+    pub fn set_history_policy(mut self,policy:HistoryPolicy)->Self{
+        self.history_policy = policy;self
+    }
+    pub fn set_tokenizer<T:Tokenizer+'static>(mut self,tokenizer:T)->Self{
+        self.tokenizer = Some(Arc::new(tokenizer));self
+    }
+    pub fn set_memory<M:Memory+'static>(mut self,memory:M)->Self{
+        self.memory = Arc::new(memory);self
+    }
+    pub fn set_long_term_memory<M:Into<Arc<LongTermMemory>>>(mut self,memory:M)->Self{
+        self.long_term = Some(memory.into());self
+    }
+    pub fn set_knowledge<R:Retriever+'static>(mut self,retriever:R)->Self{
+        self.knowledge = Some(Arc::new(retriever));self
+    }
+    pub fn set_knowledge_top_k(mut self,k:usize)->Self{
+        self.knowledge_top_k = k;self
+    }
     pub fn cove_model_config<C:Into<ModelConfig>>(mut self,cfg:C)->Self{
         self.model_config =cfg.into();self
     }
     pub fn set_model_config(mut self,handle:impl FnOnce(&mut ModelConfig))->Self{
         handle(&mut self.model_config);self
     }
+    /// 限流、网络和服务端错误在收到第一个事件之前按策略重试
+    pub fn set_retry_policy(mut self,policy:RetryPolicy)->Self{
+        self.model = Arc::new(RetryModel::new(self.model.clone(),policy));self
+    }
+    pub fn register_tool<T:Tool+'static>(mut self,tool:T)->Self{
+        self.tools.insert(tool.define().name,Arc::new(tool));self
+    }
+    pub fn set_max_tool_rounds(mut self,max:usize)->Self{
+        self.max_tool_rounds = max;self
+    }
+    pub fn set_cancel_policy(mut self,policy:CancelPolicy)->Self{
+        self.cancel_policy = policy;self
+    }
+    pub fn set_store<S:ConversationStore+'static>(mut self,store:S)->Self{
+        self.store = Some(Arc::new(store));self
+    }
+    pub fn set_usage_ledger(mut self,ledger:Arc<UsageLedger>)->Self{
+        self.ledger = Some(ledger);self
+    }
+    /// 从挂载的store中读取会话，覆盖当前的历史，会话的用量按消息重新累计
+    pub async fn load_history(&self)->anyhow::Result<()>{
+        let Some(ref store) = self.store else {
+            return Ok(());
+        };
+        let list = store.load(self.id.as_str()).await?;
+        *self.usage.lock().await = list.iter().filter_map(|x| x.usage).sum();
+        *self.history.lock().await = list.into();
+        Ok(())
+    }
+    /// 当前会话累计的token用量
+    pub async fn usage(&self)->Usage{
+        *self.usage.lock().await
+    }
+    pub fn set_id<S:Into<String>>(mut self,id:S)->Self{
+        self.id = id.into();self
+    }
+    pub fn set_save_dir<P:Into<PathBuf>>(mut self,dir:P)->Self{
+        self.save_dir = dir.into();self
+    }
+    /// 从文档恢复，模型和工具不在文档中，需要调用方重新提供
+    pub fn from_document<M:Model+Sync+'static>(model:M,doc:AgentDocument)->Self{
+        let usage = doc.history.iter().filter_map(|x| x.usage).sum::<Usage>();
+        let mut agent = Self::new(model)
+            .set_id(doc.id)
+            .set_prompt(doc.prompt)
+            .cove_prompt_env(PromptEnv{vars:doc.prompt_vars,partials:doc.prompt_partials,..Default::default()})
+            .cove_model_config(doc.model_config)
+            .set_max_history(doc.max_history)
+            .cove_chat_history(doc.history.into());
+        agent.usage = Arc::new(Am::new(usage));
+        agent
+    }
+    /// 读取 `{dir}/{id}.json` 恢复agent
+    pub async fn load<M:Model+Sync+'static,P:AsRef<Path>>(model:M,dir:P,id:&str)->anyhow::Result<Self>{
+        let doc = AgentDocument::read(dir.as_ref(),id).await?;
+        Ok(Self::from_document(model,doc).set_save_dir(dir.as_ref()))
+    }
+    pub async fn to_document(&self)->AgentDocument{
+        AgentDocument{
+            version: AGENT_DOCUMENT_VERSION,
+            id: self.id.clone(),
+            prompt: self.prompt.clone(),
+            prompt_vars: self.prompt_env.vars.clone(),
+            prompt_partials: self.prompt_env.partials.clone(),
+            model_config: self.model_config.clone(),
+            max_history: self.max_history,
+            history: self.history.lock().await.iter().cloned().collect(),
+        }
+    }
     pub fn status_is_usable(&self)->bool{
-        self.status.load(Ordering::Relaxed) == 1
+        self.get_status() == AgentStatus::Usable
     }
-    pub fn get_status(&self)->i8{
-        self.status.load(Ordering::Relaxed)
+    pub fn get_status(&self)->AgentStatus{
+        AgentStatus::from(self.status.load(Ordering::Relaxed))
     }
 }
 struct ChatHistoryWatch {
+    id: String,
+    store: Option<Arc<dyn ConversationStore>>,
     status: Arc<AtomicI8>,
+    cancel: Arc<Am<Option<Arc<Notify>>>>,
     history: Arc<Am<VecDeque<Message>>>,
+    max_history: usize,
+    memory: Arc<dyn Memory>,
+    long_term: Option<Arc<LongTermMemory>>,
+    model: Arc<dyn Model + Sync>,
+    tools: HashMap<String, Arc<dyn Tool>>,
+    max_tool_rounds: usize,
+    cancel_policy: CancelPolicy,
+    usage: Arc<Am<Usage>>,
+    ledger: Option<Arc<UsageLedger>>,
 }
 impl ChatHistoryWatch {
-    pub async fn watch(self, query: String, mut resp: Response, crs: ChatRespStream) {
-        self.status.store(2, Ordering::Relaxed);
+    /// 每次请求的用量到达时就计入，回滚的轮次也已经产生了费用
+    async fn record_usage(&self, model: &str, usage: Usage) {
+        *self.usage.lock().await += usage;
+        let Some(ref ledger) = self.ledger else {
+            return;
+        };
+        if let Err(e) = ledger.record(model, usage).await {
+            wd_log::log_field("id", self.id.as_str())
+                .field("error", e.to_string())
+                .error("ChatHistoryWatch.record_usage write ledger failed");
+        }
+    }
+    async fn call_tool(&self, call: &ToolCall) -> String {
+        let Some(tool) = self.tools.get(call.name.as_str()) else {
+            return format!("error: tool[{}] not found", call.name);
+        };
+        match tool.call(call.arguments.clone()).await {
+            Ok(o) => o,
+            Err(e) => {
+                wd_log::log_field("tool", call.name.as_str())
+                    .field("error", e.to_string())
+                    .warn("ChatHistoryWatch.call_tool failed");
+                format!("error: {e}")
+            }
+        }
+    }
+    /// 消费模型的回复并转发给调用方，out中是当前这一轮已经生成的内容；
+    /// 如果模型要求调用工具，执行后把结果回传给模型，直到得到最终回复
+    async fn reply(
+        &self,
+        cfg: &ModelConfig,
+        request: &mut Vec<Message>,
+        resp: &mut Response,
+        crs: &ChatRespStream,
+        out: &mut TurnOutput,
+    ) -> anyhow::Result<FinishReason> {
+        let mut round = 0;
+        loop {
+            *out = TurnOutput::default();
+            let mut tool_calls = vec![];
+            //经过FallbackModel/RouterModel时以实际使用的模型记录用量
+            let mut model = cfg.name.clone();
+            let reason = loop {
+                let Some(event) = resp.next().await else {
+                    return anyhow::anyhow!("response stream closed before finish").err();
+                };
+                match event {
+                    ResponseEvent::Text(s) => {
+                        out.text.push_str(s.as_str());
+                        crs.push(ResponseEvent::Text(s));
+                    }
+                    ResponseEvent::Reasoning(s) => {
+                        out.reasoning.push_str(s.as_str());
+                        crs.push(ResponseEvent::Reasoning(s));
+                    }
+                    ResponseEvent::ToolCall(call) => tool_calls.push(call),
+                    //用量在回复结束时按整轮合计后再发给调用方
+                    ResponseEvent::Usage(u) => {
+                        out.usage += u;
+                        self.record_usage(model.as_str(), u).await;
+                    }
+                    ResponseEvent::Model(name) => model = name,
+                    //工具调用轮次的结束不转发，调用方只会收到最终的Finish
+                    ResponseEvent::Finish(reason) => break reason,
+                    ResponseEvent::Error(e) => return Err(e),
+                }
+            };
+            if tool_calls.is_empty() {
+                return Ok(reason);
+            }
+            round += 1;
+            if round > self.max_tool_rounds {
+                return anyhow::anyhow!("tool call rounds exceed the limit[{}]", self.max_tool_rounds).err();
+            }
+            let mut append = vec![std::mem::take(out).into_message(Message::new_tool_calls(tool_calls.clone()))];
+            for call in tool_calls.iter() {
+                let output = self.call_tool(call).await;
+                append.push(Message::new_tool(call.id.as_str(), output));
+            }
+            self.history.lock().await.extend(append.clone());
+            request.extend(append);
+            *resp = self.model.chat(cfg, request.as_slice()).await?;
+        }
+    }
+    /// 将保留下来的一轮对话写入store，失败只记录日志，不影响回复
+    async fn write_through(&self, turn: Vec<Message>) {
+        let Some(ref store) = self.store else {
+            return;
+        };
+        if turn.is_empty() {
+            return;
+        }
+        if let Err(e) = store.append(self.id.as_str(), turn.as_slice()).await {
+            wd_log::log_field("id", self.id.as_str())
+                .field("error", e.to_string())
+                .error("ChatHistoryWatch.write_through append to store failed");
+        }
+    }
+    /// 在后台处理回复，结束、出错或被取消时更新历史记录
+    pub async fn watch(self, user: Message, cfg: ModelConfig, mut request: Vec<Message>, mut resp: Response, crs: ChatRespStream) {
+        self.status.store(AgentStatus::Replying as i8, Ordering::Relaxed);
+        let notify = Arc::new(Notify::new());
+        *self.cancel.lock().await = Some(notify.clone());
         let mut lock = self.history.lock().await;
-        lock.push_back(Message::new_user(query));
+        let base = lock.len();
+        let query = user.content.clone();
+        lock.push_back(user);
         drop(lock);
         tokio::spawn(async move {
-            let mut over = false;
-            let mut res = String::new();
-            while let result = resp.next().await {
-                match result {
-                    Ok(o) => {
-                        over = o.content.is_empty();
-                        res.push_str(o.content.as_str());
-                        crs.push(o.content);
-                        if over {
-                            break
-                        }
-                    }
-                    Err(e) => {
-                        crs.push_err(e);
-                        let mut lock = self.history.lock().await;
-                        let _ = lock.pop_back();
-                        return;
+            let mut out = TurnOutput::default();
+            let result = tokio::select! {
+                r = self.reply(&cfg, &mut request, &mut resp, &crs, &mut out) => r.map(Some),
+                _ = notify.notified() => Ok(None),
+            };
+            *self.cancel.lock().await = None;
+            let mut lock = self.history.lock().await;
+            let mut answer = None;
+            let event = match result {
+                Ok(Some(reason)) => {
+                    answer = Some(out.text.clone());
+                    lock.push_back(out.into_message(Message::new_assistant("")));
+                    ResponseEvent::Finish(reason)
+                }
+                Ok(None) => {
+                    resp.abort();
+                    if self.cancel_policy == CancelPolicy::KeepPartial && !out.text.is_empty() {
+                        lock.push_back(out.into_message(Message::new_assistant("")));
+                    } else {
+                        lock.truncate(base);
                     }
+                    ResponseEvent::Finish(FinishReason::Canceled)
+                }
+                Err(e) => {
+                    lock.truncate(base);
+                    ResponseEvent::Error(e)
                 }
+            };
+            let turn = lock.iter().skip(base).cloned().collect::<Vec<_>>();
+            drop(lock);
+            let total = turn.iter().filter_map(|x| x.usage).sum::<Usage>();
+            self.write_through(turn).await;
+            if matches!(event, ResponseEvent::Finish(_)) && !total.is_empty() {
+                crs.push(ResponseEvent::Usage(total));
             }
-            let mut lock = self.history.lock().await;
-            if self.status.load(Ordering::Relaxed) == 3 {
-                let _ = lock.pop_back();
-            } else {
-                lock.push_back(Message::new_assistant(res))
+            crs.push(event);
+            if let (Some(long_term), Some(answer)) = (self.long_term.as_ref(), answer) {
+                if let Err(e) = long_term.remember_turn(self.id.as_str(), query.as_str(), answer.as_str()).await {
+                    wd_log::log_field("id", self.id.as_str())
+                        .field("error", e.to_string())
+                        .error("ChatHistoryWatch.watch remember long term memory failed");
+                }
+            }
+            //整理历史时保持回复状态，避免和新的提问交错
+            let ctx = MemoryContext {
+                id: self.id.as_str(),
+                max_history: self.max_history,
+                budget: None,
+            };
+            if let Err(e) = self.memory.commit(&self.history, &ctx).await {
+                wd_log::log_field("id", self.id.as_str())
+                    .field("error", e.to_string())
+                    .error("ChatHistoryWatch.watch memory commit failed");
             }
         });
     }
@@ -91,68 +447,152 @@ impl ChatHistoryWatch {
 impl From<&SingleAgent> for ChatHistoryWatch {
     fn from(value: &SingleAgent) -> Self {
         Self {
+            id: value.id.clone(),
+            store: value.store.clone(),
             status: value.status.clone(),
+            cancel: value.cancel.clone(),
             history: value.history.clone(),
+            max_history: value.max_history,
+            memory: value.memory.clone(),
+            long_term: value.long_term.clone(),
+            model: value.model.clone(),
+            tools: value.tools.clone(),
+            max_tool_rounds: value.max_tool_rounds,
+            cancel_policy: value.cancel_policy,
+            usage: value.usage.clone(),
+            ledger: value.ledger.clone(),
         }
     }
 }
+/// 模型一次回复中累计的文本、思考过程和用量
+#[derive(Default)]
+struct TurnOutput {
+    text: String,
+    reasoning: String,
+    usage: Usage,
+}
+impl TurnOutput {
+    fn into_message(self, mut msg: Message) -> Message {
+        msg.content = self.text;
+        msg.reasoning = self.reasoning;
+        msg.usage = Some(self.usage).filter(|x| !x.is_empty());
+        msg
+    }
+}
 impl Drop for ChatHistoryWatch {
     fn drop(&mut self) {
-        self.status.store(1, Ordering::Relaxed);
+        self.status.store(AgentStatus::Usable as i8, Ordering::Relaxed);
     }
 }
 
 #[async_trait::async_trait]
 impl super::Agent for SingleAgent {
     async fn chat(&self, query: String) -> anyhow::Result<ChatRespStream> {
+        self.chat_with_parts(query, vec![]).await
+    }
+    async fn chat_with_parts(&self, query: String, parts: Vec<ContentPart>) -> anyhow::Result<ChatRespStream> {
         //检查状态
         if !self.status_is_usable() {
             return anyhow::anyhow!("SingleAgent.status check failed,please wait status usable").err()
         }
         //组装请求
-        let mut chat_history = VecDeque::new();
-        if self.max_history > 0 {
-            let lock = self.history.synchronize();
-            for (index, msg) in lock.iter().rev().enumerate() {
-                if index == self.max_history {
-                    break;
-                }
-                chat_history.push_front(msg.clone());
+        let prompt = self.render_prompt()?;
+        let mut cfg = self.model_config.clone();
+        cfg.tools.extend(self.tools.values().map(|x| x.define()));
+        let tokenizer = match self.tokenizer {
+            Some(ref t) => t.clone(),
+            None => tokenizer_for_model(cfg.name.as_str()),
+        };
+        //长期记忆和知识库检索失败不影响本次对话
+        let mut context = vec![];
+        if let Some(ref lt) = self.long_term {
+            match lt.recall_message(query.as_str()).await {
+                Ok(o) => context.extend(o),
+                Err(e) => wd_log::log_field("id", self.id.as_str())
+                    .field("error", e.to_string())
+                    .warn("SingleAgent.chat recall long term memory failed"),
+            }
+        }
+        if let Some(ref kb) = self.knowledge {
+            match kb.retrieve(query.as_str(), self.knowledge_top_k).await {
+                Ok(o) => context.extend(knowledge_message(o.as_slice())),
+                Err(e) => wd_log::log_field("id", self.id.as_str())
+                    .field("error", e.to_string())
+                    .warn("SingleAgent.chat retrieve knowledge failed"),
             }
-            drop(lock);
         }
-        if !self.prompt.is_empty() {
-            chat_history.push_front(Message::new_system(self.prompt.as_str()));
+        let user = Message {
+            parts,
+            ..Message::new_user(query)
         };
-        chat_history.push_back(Message::new_user(query.as_str()));
-
-        let chat_history = chat_history.into_iter().collect::<Vec<_>>();
+        let budget = match self.history_policy {
+            HistoryPolicy::MessageCount => None,
+            HistoryPolicy::TokenBudget => Some(history_token_budget(
+                &cfg,
+                tokenizer.as_ref(),
+                prompt.as_str(),
+                &user,
+            )?
+            .saturating_sub(context.iter().map(|x| tokenizer.count_message(x)).sum())),
+        };
+        let ctx = MemoryContext {
+            id: self.id.as_str(),
+            max_history: self.max_history,
+            budget: budget.map(|x| (x, tokenizer.as_ref())),
+        };
+        let lock = self.history.lock().await;
+        let recall = self.memory.recall(&lock, &ctx).await?;
+        drop(lock);
+        let mut chat_history = vec![];
+        if !prompt.is_empty() {
+            chat_history.push(Message::new_system(prompt));
+        };
+        chat_history.extend(context);
+        chat_history.extend(recall);
+        chat_history.push(user.clone());
 
         //请求大脑
         let resp = self
             .model
-            .chat(&self.model_config, chat_history.as_slice())
+            .chat(&cfg, chat_history.as_slice())
             .await?;
         let crs = ChatRespStream::new();
 
         //记忆
         ChatHistoryWatch::from(self)
-            .watch(query, resp, crs.clone())
+            .watch(user, cfg, chat_history, resp, crs.clone())
             .await;
 
         Ok(crs)
     }
 
+    async fn cancel(&self) {
+        let Some(notify) = self.cancel.lock().await.clone() else {
+            return;
+        };
+        self.status.store(AgentStatus::Canceling as i8, Ordering::Relaxed);
+        notify.notify_one();
+    }
+
     async fn clear_chat_history(&self) {
-        todo!()
+        self.history.lock().await.clear();
+        *self.usage.lock().await = Usage::default();
+        if let Some(ref store) = self.store {
+            if let Err(e) = store.delete(self.id.as_str()).await {
+                wd_log::log_field("id", self.id.as_str())
+                    .field("error", e.to_string())
+                    .error("SingleAgent.clear_chat_history delete from store failed");
+            }
+        }
     }
 
-    async fn save(&self) -> String {
-        todo!()
+    async fn save(&self) -> anyhow::Result<String> {
+        self.to_document().await.write(self.save_dir.as_path()).await?;
+        Ok(self.id.clone())
     }
 
-    async fn delete(&self) {
-        todo!()
+    async fn delete(&self) -> anyhow::Result<()> {
+        AgentDocument::remove(self.save_dir.as_path(), self.id.as_str()).await
     }
 }
 
@@ -161,8 +601,41 @@ mod test{
     use std::io::Write;
     use std::time::Duration;
     use crate::agent::Agent;
-    use crate::agent::agent::SingleAgent;
+    use crate::agent::agent::{AgentStatus, CancelPolicy, SingleAgent};
+    use crate::agent::FnTool;
+    use crate::model::content::ContentPart;
+    use crate::model::mock::{MockModel, MockReply};
+    use crate::model::fallback::ModelTarget;
+    use crate::model::router::{RouteRule, RouterModel};
     use crate::model::qwen::QwenModel;
+    use crate::agent::ChatRespStream;
+    use crate::model::{FinishReason, MessageType, ResponseEvent, ToolCall, ToolDefine, Usage};
+    use crate::agent::UsageLedger;
+    use crate::agent::{HistoryPolicy, LongTermMemory, SummaryMemory};
+    use crate::model::embedding::HashEmbedding;
+    use crate::store::FlatIndex;
+    use crate::knowledge::{Document, DocumentKind, KnowledgeBase};
+    use crate::model::tokenizer::EstimateTokenizer;
+    use crate::model::Message;
+    use crate::store::{ConversationStore, JsonFileStore};
+    use std::sync::Arc;
+    use futures::StreamExt;
+
+    async fn wait_answer(mut answer: ChatRespStream) -> (String, FinishReason) {
+        let mut res = String::new();
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(3), answer.next())
+                .await
+                .expect("wait answer timeout");
+            match event {
+                Some(ResponseEvent::Text(s)) => res.push_str(s.as_str()),
+                Some(ResponseEvent::Finish(reason)) => return (res, reason),
+                Some(ResponseEvent::Error(e)) => panic!("message stream error: {e}"),
+                Some(_) => {}
+                None => panic!("answer stream closed before finish"),
+            }
+        }
+    }
 
     // cargo test --lib pkg::pkg::test::test_single_agent -- --nocapture
     #[tokio::test]
@@ -180,25 +653,386 @@ mod test{
             if buf.is_empty() {
                 continue
             }
-            let answer = agent.chat(buf.clone()).await.expect("pkg chat error:");
+            let mut answer = agent.chat(buf.clone()).await.expect("pkg chat error:");
             buf = String::new();
             print!("ASSISTANT: ");
             std::io::stdout().flush().unwrap();
-            while let stream = answer.next().expect("message stream error:") {
-                if let Some(msg) = stream {
-                    if msg.is_empty() {
-                        print!("\nUSER: ");
-                        std::io::stdout().flush().unwrap();
-                        break
-                    }else{
+            while let Some(event) = answer.next().await {
+                match event {
+                    ResponseEvent::Text(msg) => {
                         print!("{}",msg);
                         std::io::stdout().flush().unwrap();
                     }
-                }else{
-                    //fixme: 用ui刷新，不要忙等，这里仅做测试
-                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    ResponseEvent::Error(e) => panic!("message stream error: {e}"),
+                    _ => {}
                 }
             }
+            print!("\nUSER: ");
+            std::io::stdout().flush().unwrap();
         }
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_single_agent_mock(){
+        let model = MockModel::new().reply_text("I am a mock");
+        let agent = SingleAgent::new(model.clone())
+            .set_model_config(|cfg|cfg.name = "mock".into())
+            .set_prompt("## role: mock")
+            .set_max_history(10);
+
+        let answer = agent.chat("who are you?".into()).await.expect("chat error");
+        let (res, reason) = wait_answer(answer).await;
+        assert_eq!(reason, FinishReason::Stop);
+        assert_eq!(res, "I am a mock");
+        wait_usable(&agent).await;
+
+        let _ = agent.chat("again".into()).await.expect("chat error");
+        let record = model.last_record().expect("no record");
+        assert_eq!(record.config.name, "mock");
+        let roles = record.messages.iter().map(|x|x.role.to_string()).collect::<Vec<_>>();
+        assert_eq!(roles, vec!["system","user","assistant","user"]);
+        assert!(matches!(record.messages[0].role, MessageType::SYSTEM));
+        assert_eq!(record.messages[2].content, "I am a mock");
+        assert_eq!(record.messages[3].content, "again");
+    }
+
+    #[tokio::test]
+    async fn test_single_agent_tool_call(){
+        let model = MockModel::new()
+            .reply(MockReply::ToolCalls(vec![ToolCall{
+                id: "call_1".into(),
+                name: "add".into(),
+                arguments: r#"{"a":1,"b":2}"#.into(),
+            }]))
+            .reply_text("1+2=3");
+        let add = FnTool::new(
+            ToolDefine::new("add","add two number",serde_json::json!({"type":"object"})),
+            |args:String| async move {
+                let v: serde_json::Value = serde_json::from_str(args.as_str())?;
+                Ok((v["a"].as_i64().unwrap_or(0) + v["b"].as_i64().unwrap_or(0)).to_string())
+            },
+        );
+        let agent = SingleAgent::new(model.clone()).register_tool(add);
+
+        let answer = agent.chat("1+2=?".into()).await.expect("chat error");
+        let (res, reason) = wait_answer(answer).await;
+        assert_eq!(reason, FinishReason::Stop);
+        assert_eq!(res, "1+2=3");
+
+        let records = model.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].config.tools[0].name, "add");
+        let last = records[1].messages.last().unwrap();
+        assert!(matches!(last.role, MessageType::TOOL));
+        assert_eq!(last.call_id.as_deref(), Some("call_1"));
+        assert_eq!(last.content, "3");
+
+        let history = agent.history.synchronize();
+        let roles = history.iter().map(|x|x.role.to_string()).collect::<Vec<_>>();
+        assert_eq!(roles, vec!["user","assistant","tool","assistant"]);
+    }
+
+    async fn wait_usable(agent: &SingleAgent) {
+        while !agent.status_is_usable() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_single_agent_cancel(){
+        for policy in [CancelPolicy::Rollback, CancelPolicy::KeepPartial] {
+            let model = MockModel::new()
+                .reply(MockReply::chunks(["a", "b", "c"], Duration::from_millis(200)));
+            let agent = SingleAgent::new(model).set_cancel_policy(policy);
+
+            let mut answer = agent.chat("abc".into()).await.expect("chat error");
+            assert_eq!(agent.get_status(), AgentStatus::Replying);
+            assert!(matches!(answer.next().await, Some(ResponseEvent::Text(s)) if s == "a"));
+            agent.cancel().await;
+            let (res, reason) = wait_answer(answer).await;
+            assert_eq!(res, "");
+            assert_eq!(reason, FinishReason::Canceled);
+            wait_usable(&agent).await;
+
+            let history = agent.history.synchronize();
+            let list = history.iter().map(|x|x.content.as_str()).collect::<Vec<_>>();
+            match policy {
+                CancelPolicy::Rollback => assert!(list.is_empty()),
+                CancelPolicy::KeepPartial => assert_eq!(list, vec!["abc","a"]),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_single_agent_save_load(){
+        let dir = std::env::temp_dir().join(wd_tools::uuid::v4());
+        let agent = SingleAgent::new(MockModel::new().reply_text("hello"))
+            .set_save_dir(&dir)
+            .set_prompt("## role: mock")
+            .set_model_config(|cfg|cfg.name = "mock".into());
+        let (res, _) = wait_answer(agent.chat("hi".into()).await.expect("chat error")).await;
+        assert_eq!(res, "hello");
+        wait_usable(&agent).await;
+
+        let id = agent.save().await.expect("save error");
+        assert_eq!(id, agent.id);
+        let load = SingleAgent::load(MockModel::new(), &dir, id.as_str()).await.expect("load error");
+        assert_eq!(load.prompt, "## role: mock");
+        assert_eq!(load.model_config.name, "mock");
+        let history = load.history.synchronize().iter().map(|x|x.content.clone()).collect::<Vec<_>>();
+        assert_eq!(history, vec!["hi","hello"]);
+
+        load.clear_chat_history().await;
+        assert!(load.history.synchronize().is_empty());
+        load.delete().await.expect("delete error");
+        assert!(SingleAgent::load(MockModel::new(), &dir, id.as_str()).await.is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_single_agent_store(){
+        let dir = std::env::temp_dir().join(wd_tools::uuid::v4());
+        let store = Arc::new(JsonFileStore::new(&dir));
+        let model = MockModel::new()
+            .reply(MockReply::ToolCalls(vec![ToolCall{
+                id: "call_1".into(),
+                name: "now".into(),
+                arguments: "{}".into(),
+            }]))
+            .reply_text("12:00")
+            .reply(MockReply::error(["partial"], "boom"));
+        let now = FnTool::new(
+            ToolDefine::new("now","current time",serde_json::json!({"type":"object"})),
+            |_:String| async move { Ok("12:00".to_string()) },
+        );
+        let agent = SingleAgent::new(model)
+            .set_id("store-test")
+            .set_store(store.clone())
+            .register_tool(now);
+
+        let (res, _) = wait_answer(agent.chat("what time is it?".into()).await.expect("chat error")).await;
+        assert_eq!(res, "12:00");
+        wait_usable(&agent).await;
+        let mut answer = agent.chat("again".into()).await.expect("chat error");
+        while let Some(event) = answer.next().await {
+            if let ResponseEvent::Error(e) = event {
+                assert_eq!(e.to_string(), "boom");
+            }
+        }
+        wait_usable(&agent).await;
+
+        //失败的一轮不会写入
+        let roles = store.load("store-test").await.unwrap().iter().map(|x|x.role.to_string()).collect::<Vec<_>>();
+        assert_eq!(roles, vec!["user","assistant","tool","assistant"]);
+
+        let restore = SingleAgent::new(MockModel::new()).set_id("store-test").set_store(store.clone());
+        restore.load_history().await.expect("load history error");
+        assert_eq!(restore.history.synchronize().len(), 4);
+        restore.clear_chat_history().await;
+        assert!(store.list().await.unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_single_agent_token_budget(){
+        let model = MockModel::new();
+        let agent = SingleAgent::new(model.clone())
+            .set_history_policy(HistoryPolicy::TokenBudget)
+            .set_tokenizer(EstimateTokenizer)
+            .set_model_config(|cfg|{
+                cfg.context_size = 1024;
+                cfg.max_output_token = 256;
+            })
+            .cove_chat_history(vec![
+                Message::new_user("log ".repeat(2000)),
+                Message::new_assistant("ok"),
+                Message::new_user("hi"),
+                Message::new_assistant("hello"),
+            ].into());
+
+        let _ = wait_answer(agent.chat("again".into()).await.expect("chat error")).await;
+        let record = model.last_record().expect("no record");
+        let list = record.messages.iter().map(|x|x.content.as_str()).collect::<Vec<_>>();
+        assert_eq!(list, vec!["ok","hi","hello","again"]);
+
+    }
+
+    #[tokio::test]
+    async fn test_single_agent_token_budget_parts(){
+        let recall = |parts: Vec<ContentPart>| async move {
+            let model = MockModel::new();
+            let agent = SingleAgent::new(model.clone())
+                .set_history_policy(HistoryPolicy::TokenBudget)
+                .set_tokenizer(EstimateTokenizer)
+                .set_model_config(|cfg|{
+                    cfg.context_size = 1600;
+                    cfg.max_output_token = 256;
+                })
+                .cove_chat_history(vec![
+                    Message::new_user("word ".repeat(300)),
+                    Message::new_assistant("ok"),
+                    Message::new_user("hi"),
+                    Message::new_assistant("hello"),
+                ].into());
+            let _ = wait_answer(agent.chat_with_parts("look".into(), parts).await.expect("chat error")).await;
+            let record = model.last_record().expect("no record");
+            record.messages.iter().map(|x|x.content.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(recall(vec![]).await.len(), 5);
+        //图片按固定开销计入预算，较早的长消息被挤掉
+        let list = recall(vec![ContentPart::image_url("https://example.com/a.png")]).await;
+        assert_eq!(list, vec!["ok","hi","hello","look"]);
+    }
+
+    #[tokio::test]
+    async fn test_single_agent_summary(){
+        let model = MockModel::new().reply_text("a2").reply_text("a3");
+        let summary = MockModel::new().reply_text("user asked q0 and q1");
+        let agent = SingleAgent::new(model.clone())
+            .set_prompt("## role: mock")
+            .set_memory(SummaryMemory::new(summary.clone()).set_threshold(4).set_keep(2))
+            .cove_chat_history(vec![
+                Message::new_user("q0"),
+                Message::new_assistant("a0"),
+                Message::new_user("q1"),
+                Message::new_assistant("a1"),
+            ].into());
+
+        let _ = wait_answer(agent.chat("q2".into()).await.expect("chat error")).await;
+        wait_usable(&agent).await;
+        assert_eq!(summary.records().len(), 1);
+        assert_eq!(agent.history.synchronize().len(), 3);
+
+        let _ = wait_answer(agent.chat("q3".into()).await.expect("chat error")).await;
+        let record = model.last_record().expect("no record");
+        let roles = record.messages.iter().map(|x|x.role.to_string()).collect::<Vec<_>>();
+        assert_eq!(roles, vec!["system","system","user","assistant","user"]);
+        assert!(record.messages[1].content.ends_with("user asked q0 and q1"));
+        assert_eq!(record.messages[2].content, "q2");
+    }
+
+    #[tokio::test]
+    async fn test_single_agent_long_term(){
+        let memory = Arc::new(LongTermMemory::new(HashEmbedding::default(), FlatIndex::new()).set_min_score(0.3));
+        let agent = SingleAgent::new(MockModel::new().reply_text("ok, your cat is named tom"))
+            .set_long_term_memory(memory.clone());
+        let _ = wait_answer(agent.chat("my cat is named tom".into()).await.expect("chat error")).await;
+        wait_usable(&agent).await;
+        assert_eq!(memory.len().await, 1);
+
+        //新的会话中没有历史，但可以检索到长期记忆
+        let model = MockModel::new();
+        let agent = SingleAgent::new(model.clone())
+            .set_prompt("## role: mock")
+            .set_long_term_memory(memory.clone());
+        let _ = wait_answer(agent.chat("what is my cat named?".into()).await.expect("chat error")).await;
+        let record = model.last_record().expect("no record");
+        let roles = record.messages.iter().map(|x|x.role.to_string()).collect::<Vec<_>>();
+        assert_eq!(roles, vec!["system","system","user"]);
+        assert!(record.messages[1].content.contains("my cat is named tom"));
+    }
+
+    #[tokio::test]
+    async fn test_single_agent_knowledge(){
+        let kb = Arc::new(KnowledgeBase::new());
+        kb.add_document(Document::new("faq.md", DocumentKind::Markdown, "# Reset\n\nhold the power button for ten seconds"))
+            .await
+            .unwrap();
+        let model = MockModel::new();
+        let agent = SingleAgent::new(model.clone()).set_knowledge(kb.clone());
+        let _ = wait_answer(agent.chat("how to reset the power?".into()).await.expect("chat error")).await;
+        let record = model.last_record().expect("no record");
+        assert_eq!(record.messages.len(), 2);
+        assert!(matches!(record.messages[0].role, MessageType::SYSTEM));
+        assert!(record.messages[0].content.contains("[1] 来源：faq.md > Reset\nhold the power button"));
+    }
+
+    #[tokio::test]
+    async fn test_single_agent_prompt_template(){
+        let model = MockModel::new();
+        let agent = SingleAgent::new(model.clone())
+            .set_prompt("## role: {{> role}}\n{{#if lang}}\nanswer in {{lang}}\n{{/if}}\ntoday is {{date}}")
+            .set_prompt_partial("role", "{{name}}")
+            .set_prompt_var("name", "mock")
+            .set_prompt_var("lang", "rust");
+        let _ = wait_answer(agent.chat("hi".into()).await.expect("chat error")).await;
+        let record = model.last_record().expect("no record");
+        let prompt = record.messages[0].content.as_str();
+        assert!(prompt.starts_with("## role: mock\nanswer in rust\ntoday is 20"), "{prompt}");
+        wait_usable(&agent).await;
+
+        let agent = agent.set_prompt("{{#if lang}}");
+        assert!(agent.chat("hi".into()).await.is_err());
+    }
+    #[tokio::test]
+    async fn test_single_agent_usage(){
+        let model = MockModel::new()
+            .set_usage(Usage::new(10, 2))
+            .reply(MockReply::ToolCalls(vec![ToolCall{
+                id: "call_1".into(),
+                name: "now".into(),
+                arguments: "{}".into(),
+            }]));
+        let now = FnTool::new(
+            ToolDefine::new("now","current time",serde_json::json!({"type":"object"})),
+            |_:String| async move { Ok("12:00".to_string()) },
+        );
+        let ledger = Arc::new(UsageLedger::new());
+        let agent = SingleAgent::new(model.clone())
+            .set_model_config(|cfg| cfg.name = "mock".into())
+            .register_tool(now)
+            .set_usage_ledger(ledger.clone());
+
+        let events = agent.chat("time?".into()).await.expect("chat error").collect::<Vec<_>>().await;
+        let turn = events.iter().find_map(|x| match x { ResponseEvent::Usage(u) => Some(*u), _ => None });
+        assert_eq!(turn, Some(Usage::new(20, 4)));
+        assert!(matches!(events.last(), Some(ResponseEvent::Finish(FinishReason::Stop))));
+        wait_usable(&agent).await;
+
+        let usage = agent.history.synchronize().iter().map(|x| x.usage).collect::<Vec<_>>();
+        assert_eq!(usage, vec![None, Some(Usage::new(10, 2)), None, Some(Usage::new(10, 2))]);
+        assert_eq!(agent.usage().await, Usage::new(20, 4));
+        assert_eq!(ledger.day(UsageLedger::today().as_str()).await["mock"], Usage::new(20, 4));
+        agent.clear_chat_history().await;
+        assert!(agent.usage().await.is_empty());
+
+        //经过路由时按实际使用的模型记账
+        let coder = MockModel::new().set_usage(Usage::new(5, 1));
+        let router = RouterModel::new(MockModel::new())
+            .route(RouteRule::MinChars(1), ModelTarget::new(coder).set_name("coder"));
+        let agent = SingleAgent::new(router)
+            .set_model_config(|cfg| cfg.name = "mock".into())
+            .set_usage_ledger(ledger.clone());
+        agent.chat("hi".into()).await.expect("chat error").collect::<Vec<_>>().await;
+        wait_usable(&agent).await;
+        let day = ledger.day(UsageLedger::today().as_str()).await;
+        assert_eq!(day["coder"], Usage::new(5, 1));
+        assert_eq!(day["mock"], Usage::new(20, 4));
+    }
+    #[tokio::test]
+    async fn test_single_agent_reasoning(){
+        let model = MockModel::new().reply(MockReply::Reasoning {
+            reasoning: "1+2=3".into(),
+            text: "3".into(),
+        });
+        let agent = SingleAgent::new(model.clone());
+        let events = agent.chat("1+2=?".into()).await.expect("chat error").collect::<Vec<_>>().await;
+        assert!(matches!(events.first(), Some(ResponseEvent::Reasoning(s)) if s == "1+2=3"));
+        wait_usable(&agent).await;
+
+        let answer = agent.history.synchronize().back().cloned().expect("no answer");
+        assert_eq!((answer.content.as_str(), answer.reasoning.as_str()), ("3", "1+2=3"));
+    }
+    #[tokio::test]
+    async fn test_single_agent_chat_with_parts(){
+        let model = MockModel::new().reply_text("a cat");
+        let agent = SingleAgent::new(model.clone());
+        let parts = vec![ContentPart::image_url("https://example.com/cat.png")];
+        let (res, _) = wait_answer(agent.chat_with_parts("what is it?".into(), parts.clone()).await.expect("chat error")).await;
+        assert_eq!(res, "a cat");
+        let record = model.last_record().expect("no record");
+        assert_eq!(record.messages.last().unwrap().parts, parts);
+        wait_usable(&agent).await;
+        assert_eq!(agent.history.synchronize()[0].parts, parts);
+    }
+}
@@ -1 +1,736 @@
-pub struct AgentBuilder {}
+use crate::agent::{CancelPolicy, HistoryPolicy, LongTermMemory, SingleAgent, SummaryMemory, Tool};
+use crate::knowledge::Retriever;
+use crate::model::define::{read_key_env, GlobalModel, ModelProvider, ProviderDefine};
+use crate::model::fallback::{FallbackModel, ModelTarget};
+use crate::model::retry::{RetryModel, RetryPolicy};
+use crate::model::embedding::{DashScopeEmbedding, EmbeddingModel, HashEmbedding, OpenAICompatEmbedding};
+use crate::model::{Model, ModelConfig};
+use crate::prompt::{PromptEnv, PromptTemplate};
+use crate::store::{check_id, ConversationStore, HnswIndex, JsonFileStore};
+use crate::utils::{from_toml, from_yaml, invalid, load_config, CONFIG_EXTENSIONS};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use wd_tools::PFErr;
+
+/// 模型和请求参数，密钥只能通过环境变量引用，不写在文件里
+///
+/// 可以用instance引用GlobalModel中注册的实例，此时不能再配置provider等连接信息
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelDefine {
+    pub instance: Option<String>,
+    pub provider: Option<ModelProvider>,
+    pub name: String,
+    pub base_url: Option<String>,
+    /// 保存密钥的环境变量名，为空时使用provider默认的环境变量
+    pub api_key_env: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_output_token: Option<usize>,
+    pub context_size: Option<usize>,
+    pub stream: Option<bool>,
+    #[serde(default)]
+    pub extend: HashMap<String, String>,
+    /// 不配置时不重试，配置了fallback时包在整个降级链的外层
+    pub retry: Option<RetryDefine>,
+    /// 当前模型返回分类的错误时，按顺序改用的GlobalModel实例
+    #[serde(default)]
+    pub fallback: Vec<FallbackDefine>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FallbackDefine {
+    pub instance: String,
+    /// 为空时沿用model.name
+    pub name: Option<String>,
+}
+
+/// 未配置的参数取RetryPolicy的默认值
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryDefine {
+    pub max_retries: Option<usize>,
+    pub base_delay_ms: Option<u64>,
+    pub max_delay_ms: Option<u64>,
+}
+
+impl RetryDefine {
+    pub fn policy(&self) -> RetryPolicy {
+        let mut policy = RetryPolicy::default();
+        if let Some(n) = self.max_retries {
+            policy.max_retries = n;
+        }
+        if let Some(ms) = self.base_delay_ms {
+            policy.base_delay = Duration::from_millis(ms);
+        }
+        if let Some(ms) = self.max_delay_ms {
+            policy.max_delay = Duration::from_millis(ms);
+        }
+        policy
+    }
+    pub fn validate(&self, key: &str) -> anyhow::Result<()> {
+        let policy = self.policy();
+        if policy.base_delay > policy.max_delay {
+            return invalid(format!("{key}.base_delay_ms"), "must not be greater than max_delay_ms").err();
+        }
+        Ok(())
+    }
+}
+
+impl ModelDefine {
+    pub fn validate(&self, key: &str) -> anyhow::Result<()> {
+        if self.name.trim().is_empty() {
+            return invalid(format!("{key}.name"), "must not be empty").err();
+        }
+        if let Some(t) = self.temperature {
+            if !(0.0..=2.0).contains(&t) {
+                return invalid(format!("{key}.temperature"), "must be in [0, 2]").err();
+            }
+        }
+        if let Some(p) = self.top_p {
+            if p <= 0.0 || p > 1.0 {
+                return invalid(format!("{key}.top_p"), "must be in (0, 1]").err();
+            }
+        }
+        if self.max_output_token == Some(0) {
+            return invalid(format!("{key}.max_output_token"), "must be greater than 0").err();
+        }
+        if let Some(ref retry) = self.retry {
+            retry.validate(format!("{key}.retry").as_str())?;
+        }
+        if self.instance.is_some() {
+            let connection = [
+                ("provider", self.provider.is_some()),
+                ("base_url", self.base_url.is_some()),
+                ("api_key_env", self.api_key_env.is_some()),
+                ("headers", !self.headers.is_empty()),
+            ];
+            if let Some((field, _)) = connection.iter().find(|x| x.1) {
+                return invalid(format!("{key}.{field}"), format!("conflicts with {key}.instance")).err();
+            }
+            return Ok(());
+        }
+        match self.provider_define() {
+            Some(define) => define.validate(key),
+            None => invalid(format!("{key}.provider"), "either provider or instance is required").err(),
+        }
+    }
+    fn provider_define(&self) -> Option<ProviderDefine> {
+        Some(ProviderDefine {
+            provider: self.provider?,
+            base_url: self.base_url.clone(),
+            api_key_env: self.api_key_env.clone(),
+            headers: self.headers.clone(),
+        })
+    }
+    /// 未配置的参数取ModelConfig的默认值
+    pub fn model_config(&self) -> ModelConfig {
+        let mut cfg = ModelConfig::default().set_name(self.name.as_str());
+        if let Some(t) = self.temperature {
+            cfg.temperature = t;
+        }
+        cfg.top_p = self.top_p;
+        if let Some(n) = self.max_output_token {
+            cfg.max_output_token = n;
+        }
+        if let Some(n) = self.context_size {
+            cfg.context_size = n;
+        }
+        if let Some(s) = self.stream {
+            cfg.stream = s;
+        }
+        cfg.extend = self.extend.clone();
+        cfg
+    }
+    /// key为该定义在文件中的位置，用于错误提示
+    pub fn build(&self, key: &str) -> anyhow::Result<Arc<dyn Model + Sync>> {
+        self.validate(key)?;
+        let model = match (&self.instance, self.provider_define()) {
+            (Some(name), _) => GlobalModel::get(name).map_err(|e| invalid(format!("{key}.instance"), e))?,
+            (None, Some(define)) => define.build(key)?,
+            (None, None) => return invalid(format!("{key}.provider"), "either provider or instance is required").err(),
+        };
+        let model = if self.fallback.is_empty() {
+            model
+        } else {
+            let mut chain = FallbackModel::new().push_target(ModelTarget::shared(model));
+            for (i, define) in self.fallback.iter().enumerate() {
+                let fallback = GlobalModel::get(define.instance.as_str())
+                    .map_err(|e| invalid(format!("{key}.fallback[{i}].instance"), e))?;
+                let mut target = ModelTarget::shared(fallback);
+                target.name = define.name.clone();
+                chain = chain.push_target(target);
+            }
+            Arc::new(chain)
+        };
+        match self.retry {
+            Some(ref retry) => Ok(Arc::new(RetryModel::new(model, retry.policy()))),
+            None => Ok(model),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PromptDefine {
+    /// 模板原文，与file二选一
+    pub template: Option<String>,
+    /// 模板文件，相对路径以定义文件所在的目录为准
+    pub file: Option<PathBuf>,
+    /// 目录下的文件按文件名作为partial加载
+    pub partials_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    /// 与partials_dir同名时以这里为准
+    #[serde(default)]
+    pub partials: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreKind {
+    /// path为目录
+    Json,
+    /// path为数据库文件
+    Sqlite,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StoreDefine {
+    pub kind: StoreKind,
+    pub path: PathBuf,
+}
+
+impl StoreDefine {
+    fn build(&self, base: &Path) -> anyhow::Result<Arc<dyn ConversationStore>> {
+        let path = base.join(&self.path);
+        match self.kind {
+            StoreKind::Json => Ok(Arc::new(JsonFileStore::new(path))),
+            #[cfg(feature = "sqlite")]
+            StoreKind::Sqlite => {
+                let store = crate::store::SqliteStore::open(&path).map_err(|e| invalid("history.store.path", e))?;
+                Ok(Arc::new(store))
+            }
+            #[cfg(not(feature = "sqlite"))]
+            StoreKind::Sqlite => invalid("history.store.kind", "sqlite feature is not enabled").err(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryDefine {
+    #[serde(default)]
+    pub policy: HistoryPolicy,
+    /// 最多保留的历史条数
+    pub max: Option<usize>,
+    #[serde(default)]
+    pub cancel_policy: CancelPolicy,
+    pub store: Option<StoreDefine>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryStrategy {
+    #[default]
+    SlidingWindow,
+    Summary,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingProvider {
+    Openai,
+    Dashscope,
+    /// 本地哈希向量，不需要网络
+    #[default]
+    Hash,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmbeddingDefine {
+    #[serde(default)]
+    pub provider: EmbeddingProvider,
+    pub name: Option<String>,
+    pub base_url: Option<String>,
+    pub api_key_env: Option<String>,
+    pub dimension: Option<usize>,
+}
+
+impl EmbeddingDefine {
+    fn build(&self, key: &str) -> anyhow::Result<Arc<dyn EmbeddingModel>> {
+        let api_key = read_key_env(format!("{key}.api_key_env").as_str(), &self.api_key_env)?;
+        let model: Arc<dyn EmbeddingModel> = match self.provider {
+            EmbeddingProvider::Openai => {
+                let mut model = OpenAICompatEmbedding::default();
+                if let Some(ref url) = self.base_url {
+                    model.base_url = url.clone();
+                }
+                if let Some(key) = api_key {
+                    model.api_key = key;
+                }
+                if let Some(ref name) = self.name {
+                    model.model = name.clone();
+                }
+                model.dimensions = self.dimension;
+                Arc::new(model)
+            }
+            EmbeddingProvider::Dashscope => {
+                if self.base_url.is_some() {
+                    return invalid(format!("{key}.base_url"), "not supported by provider[dashscope]").err();
+                }
+                let mut model = api_key.map(DashScopeEmbedding::new).unwrap_or_default();
+                if let Some(ref name) = self.name {
+                    model = model.set_model(name);
+                }
+                if let Some(n) = self.dimension {
+                    model = model.set_dimensions(n);
+                }
+                Arc::new(model)
+            }
+            EmbeddingProvider::Hash => Arc::new(self.dimension.map(HashEmbedding::new).unwrap_or_default()),
+        };
+        Ok(model)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LongTermDefine {
+    /// 向量索引文件，不存在时新建
+    pub path: PathBuf,
+    #[serde(default)]
+    pub embedding: EmbeddingDefine,
+    pub top_k: Option<usize>,
+    pub min_score: Option<f32>,
+    pub min_chars: Option<usize>,
+}
+
+impl LongTermDefine {
+    fn build(&self, base: &Path) -> anyhow::Result<LongTermMemory> {
+        let embedding = self.embedding.build("memory.long_term.embedding")?;
+        let path = base.join(&self.path);
+        let index = if path.exists() {
+            HnswIndex::open(&path).map_err(|e| invalid("memory.long_term.path", e))?
+        } else {
+            HnswIndex::default()
+        };
+        let mut memory = LongTermMemory::new(embedding, index).set_path(path);
+        if let Some(k) = self.top_k {
+            memory = memory.set_top_k(k);
+        }
+        if let Some(s) = self.min_score {
+            memory = memory.set_min_score(s);
+        }
+        if let Some(n) = self.min_chars {
+            memory = memory.set_min_chars(n);
+        }
+        Ok(memory)
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryDefine {
+    #[serde(default)]
+    pub strategy: MemoryStrategy,
+    /// 以下三项只对summary策略有效
+    pub threshold: Option<usize>,
+    pub keep: Option<usize>,
+    /// 生成摘要的模型名，为空时与对话模型相同
+    pub summary_model: Option<String>,
+    pub long_term: Option<LongTermDefine>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KnowledgeDefine {
+    pub top_k: Option<usize>,
+}
+
+/// agent的定义文件，可以是toml或yaml
+///
+/// ```toml
+/// id = "coder"
+/// tools = ["now"]
+///
+/// [model]
+/// provider = "qwen"
+/// name = "qwen-plus"
+/// api_key_env = "DASHSCOPE_API_KEY"
+/// temperature = 0.3
+/// retry = { max_retries = 3, base_delay_ms = 500 }
+/// fallback = [{ instance = "local-llama", name = "qwen2.5" }]
+///
+/// [prompt]
+/// file = "coder.md"
+///
+/// [history]
+/// policy = "token_budget"
+/// store = { kind = "json", path = "../conversation" }
+///
+/// [memory]
+/// strategy = "summary"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentDefine {
+    pub id: String,
+    pub model: ModelDefine,
+    #[serde(default)]
+    pub prompt: PromptDefine,
+    #[serde(default)]
+    pub history: HistoryDefine,
+    #[serde(default)]
+    pub memory: MemoryDefine,
+    /// 引用AgentBuilder中注册的工具
+    #[serde(default)]
+    pub tools: Vec<String>,
+    pub max_tool_rounds: Option<usize>,
+    /// 配置后使用AgentBuilder提供的知识库
+    pub knowledge: Option<KnowledgeDefine>,
+}
+
+impl AgentDefine {
+    pub fn from_toml(source: &str) -> anyhow::Result<Self> {
+        from_toml(source)
+    }
+    pub fn from_yaml(source: &str) -> anyhow::Result<Self> {
+        from_yaml(source)
+    }
+    /// 按扩展名选择格式，错误信息带上文件路径
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        load_config(path)
+    }
+    /// 不依赖环境变量和文件系统的检查
+    pub fn validate(&self) -> anyhow::Result<()> {
+        check_id(self.id.as_str()).map_err(|e| invalid("id", e))?;
+        self.model.validate("model")?;
+        if self.prompt.template.is_some() && self.prompt.file.is_some() {
+            return invalid("prompt.file", "conflicts with prompt.template").err();
+        }
+        if let Some(ref template) = self.prompt.template {
+            PromptTemplate::parse(template).map_err(|e| invalid("prompt.template", e))?;
+        }
+        if self.history.max == Some(0) {
+            return invalid("history.max", "must be greater than 0").err();
+        }
+        let memory = &self.memory;
+        if memory.strategy == MemoryStrategy::SlidingWindow {
+            let summary_only = [
+                ("threshold", memory.threshold.is_some()),
+                ("keep", memory.keep.is_some()),
+                ("summary_model", memory.summary_model.is_some()),
+            ];
+            if let Some((field, _)) = summary_only.iter().find(|x| x.1) {
+                return invalid(format!("memory.{field}"), "only used by the summary strategy").err();
+            }
+        }
+        if let (Some(threshold), Some(keep)) = (memory.threshold, memory.keep) {
+            if keep >= threshold {
+                return invalid("memory.keep", "must be less than memory.threshold").err();
+            }
+        }
+        if let Some(ref lt) = memory.long_term {
+            if lt.top_k == Some(0) {
+                return invalid("memory.long_term.top_k", "must be greater than 0").err();
+            }
+            if let Some(s) = lt.min_score {
+                if !(-1.0..=1.0).contains(&s) {
+                    return invalid("memory.long_term.min_score", "must be in [-1, 1]").err();
+                }
+            }
+            if lt.embedding.dimension == Some(0) {
+                return invalid("memory.long_term.embedding.dimension", "must be greater than 0").err();
+            }
+        }
+        for (i, name) in self.tools.iter().enumerate() {
+            if self.tools[..i].contains(name) {
+                return invalid(format!("tools[{i}]"), format!("duplicate tool[{name}]")).err();
+            }
+        }
+        if let Some(KnowledgeDefine { top_k: Some(0) }) = self.knowledge {
+            return invalid("knowledge.top_k", "must be greater than 0").err();
+        }
+        Ok(())
+    }
+}
+
+/// 从目录加载agent的结果，单个文件失败不影响其他文件
+#[derive(Default)]
+pub struct AgentLoadReport {
+    pub agents: Vec<SingleAgent>,
+    /// 加载失败的文件和原因
+    pub failed: Vec<(String, String)>,
+}
+
+/// 根据定义构建agent，定义中只能引用这里注册的工具和知识库
+#[derive(Default, Clone)]
+pub struct AgentBuilder {
+    tools: HashMap<String, Arc<dyn Tool>>,
+    knowledge: Option<Arc<dyn Retriever>>,
+}
+
+impl AgentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn register_tool<T: Tool + 'static>(mut self, tool: T) -> Self {
+        self.tools.insert(tool.define().name, Arc::new(tool));
+        self
+    }
+    pub fn set_knowledge<R: Retriever + 'static>(mut self, retriever: R) -> Self {
+        self.knowledge = Some(Arc::new(retriever));
+        self
+    }
+    /// 定义中的相对路径以当前目录为准
+    pub fn build(&self, define: AgentDefine) -> anyhow::Result<SingleAgent> {
+        self.build_in(define, ".")
+    }
+    /// 定义中的相对路径以base为准
+    pub fn build_in<P: AsRef<Path>>(&self, define: AgentDefine, base: P) -> anyhow::Result<SingleAgent> {
+        define.validate()?;
+        let base = base.as_ref();
+        let cfg = define.model.model_config();
+        let model = define.model.build("model")?;
+        let mut agent = SingleAgent::new(model.clone())
+            .set_id(define.id.as_str())
+            .cove_model_config(cfg.clone())
+            .set_history_policy(define.history.policy)
+            .set_cancel_policy(define.history.cancel_policy);
+        if let Some(max) = define.history.max {
+            agent = agent.set_max_history(max);
+        }
+        if let Some(max) = define.max_tool_rounds {
+            agent = agent.set_max_tool_rounds(max);
+        }
+        if let Some(ref store) = define.history.store {
+            agent.store = Some(store.build(base)?);
+        }
+
+        let prompt = define.prompt;
+        let template = match (prompt.template, prompt.file) {
+            (Some(template), _) => template,
+            (None, Some(file)) => {
+                let path = base.join(file);
+                let template = std::fs::read_to_string(&path)
+                    .map_err(|e| invalid("prompt.file", format!("read {} failed: {e}", path.display())))?;
+                PromptTemplate::parse(template.as_str()).map_err(|e| invalid("prompt.file", e))?;
+                template
+            }
+            (None, None) => String::new(),
+        };
+        let mut env = PromptEnv::new();
+        if let Some(dir) = prompt.partials_dir {
+            env = env.load_partials(base.join(dir)).map_err(|e| invalid("prompt.partials_dir", e))?;
+        }
+        env.vars = prompt.vars;
+        env.partials.extend(prompt.partials);
+        agent = agent.set_prompt(template).cove_prompt_env(env);
+
+        for (i, name) in define.tools.iter().enumerate() {
+            let Some(tool) = self.tools.get(name) else {
+                return invalid(format!("tools[{i}]"), format!("tool[{name}] is not registered")).err();
+            };
+            agent.tools.insert(name.clone(), tool.clone());
+        }
+
+        let memory = define.memory;
+        if memory.strategy == MemoryStrategy::Summary {
+            let mut summary = SummaryMemory::new(model).cove_model_config(cfg);
+            if let Some(name) = memory.summary_model {
+                summary = summary.set_model_config(|x| x.name = name);
+            }
+            if let Some(n) = memory.threshold {
+                summary = summary.set_threshold(n);
+            }
+            if let Some(n) = memory.keep {
+                summary = summary.set_keep(n);
+            }
+            agent = agent.set_memory(summary);
+        }
+        if let Some(lt) = memory.long_term {
+            agent = agent.set_long_term_memory(lt.build(base)?);
+        }
+
+        if let Some(knowledge) = define.knowledge {
+            let Some(ref retriever) = self.knowledge else {
+                return invalid("knowledge", "no knowledge base is provided").err();
+            };
+            agent.knowledge = Some(retriever.clone());
+            if let Some(k) = knowledge.top_k {
+                agent = agent.set_knowledge_top_k(k);
+            }
+        }
+        Ok(agent)
+    }
+    /// 读取定义文件并构建，文件中的相对路径以文件所在目录为准
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<SingleAgent> {
+        let path = path.as_ref();
+        let define = AgentDefine::load(path)?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        self.build_in(define, base)
+            .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))
+    }
+    /// 加载目录下所有的定义文件，按文件名排序；id重复时后加载的文件视为失败
+    pub fn load_dir<P: AsRef<Path>>(&self, dir: P) -> anyhow::Result<AgentLoadReport> {
+        let mut files = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let ext = path.extension().and_then(|x| x.to_str()).unwrap_or_default();
+            if path.is_file() && CONFIG_EXTENSIONS.contains(&ext) {
+                files.push(path);
+            }
+        }
+        files.sort();
+        let mut report = AgentLoadReport::default();
+        for path in files {
+            let name = path.display().to_string();
+            match self.load(&path) {
+                Ok(agent) if report.agents.iter().any(|x| x.id == agent.id) => {
+                    report.failed.push((name, format!("id[{}] is already defined", agent.id)));
+                }
+                Ok(agent) => report.agents.push(agent),
+                Err(e) => report.failed.push((name, e.to_string())),
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::agent::{Agent, AgentBuilder, AgentDefine, FnTool, HistoryPolicy};
+    use crate::model::define::GlobalModel;
+    use crate::model::error::ModelError;
+    use crate::model::mock::{MockModel, MockReply};
+    use crate::model::{ResponseEvent, ToolDefine};
+    use futures::StreamExt;
+
+    const CODER_TOML: &str = r#"
+id = "coder"
+tools = ["now"]
+max_tool_rounds = 3
+
+[model]
+instance = "test-builder-echo"
+name = "mock-1"
+temperature = 0.3
+max_output_token = 1024
+
+[prompt]
+template = "you are {{name}}."
+vars = { name = "coder" }
+
+[history]
+policy = "token_budget"
+max = 10
+"#;
+
+    //配置中不能选择mock，测试的模型都通过GlobalModel注册后用instance引用
+    fn builder() -> AgentBuilder {
+        GlobalModel::register("test-builder-echo", MockModel::new());
+        let define = ToolDefine::new("now", "current time", serde_json::json!({"type":"object"}));
+        AgentBuilder::new().register_tool(FnTool::new(define, |_| async { Ok("12:00".to_string()) }))
+    }
+
+    #[tokio::test]
+    async fn test_agent_builder() {
+        let agent = builder().build(AgentDefine::from_toml(CODER_TOML).unwrap()).unwrap();
+        assert_eq!(agent.id, "coder");
+        assert_eq!(agent.model_config.name, "mock-1");
+        assert_eq!(agent.model_config.temperature, 0.3);
+        assert_eq!(agent.max_history, 10);
+        assert_eq!(agent.max_tool_rounds, 3);
+        assert_eq!(agent.history_policy, HistoryPolicy::TokenBudget);
+        assert!(agent.tools.contains_key("now"));
+        assert_eq!(agent.render_prompt().unwrap(), "you are coder.");
+
+        let text = agent.chat("hi".into()).await.unwrap().collect::<Vec<_>>().await;
+        assert!(!text.is_empty());
+
+        GlobalModel::register("test-builder-mock", MockModel::new().reply_text("from instance"));
+        let source = "id: shared\nmodel:\n  instance: test-builder-mock\n  name: m\n";
+        let agent = builder().build(AgentDefine::from_yaml(source).unwrap()).unwrap();
+        let text = agent.chat("hi".into()).await.unwrap().collect::<Vec<_>>().await;
+        assert!(matches!(&text[0], ResponseEvent::Text(s) if s == "from instance"));
+
+        let down = MockModel::new().reply(MockReply::Fail(ModelError::Network("reset".into())));
+        GlobalModel::register("test-builder-down", down);
+        GlobalModel::register("test-builder-backup", MockModel::new().reply_text("from backup"));
+        let source = "id: chain
+model:
+  instance: test-builder-down
+  name: m
+  fallback:
+    - instance: test-builder-backup
+";
+        let agent = builder().build(AgentDefine::from_yaml(source).unwrap()).unwrap();
+        let text = agent.chat("hi".into()).await.unwrap().collect::<Vec<_>>().await;
+        assert!(matches!(&text[0], ResponseEvent::Text(s) if s == "from backup"));
+
+        let yaml = "id: helper\nmodel:\n  instance: test-builder-echo\n  name: mock-2\nmemory:\n  strategy: summary\n  threshold: 10\n  keep: 4\n";
+        let agent = builder().build(AgentDefine::from_yaml(yaml).unwrap()).unwrap();
+        assert_eq!(agent.id, "helper");
+        assert_eq!(agent.model_config.name, "mock-2");
+    }
+
+    #[test]
+    fn test_agent_define_error() {
+        let err = |source: &str| match AgentDefine::from_toml(source).and_then(|x| builder().build(x)) {
+            Ok(_) => panic!("should be invalid: {source}"),
+            Err(e) => e.to_string(),
+        };
+        let model = "[model]\ninstance = \"test-builder-echo\"\nname = \"m\"\n";
+        let qwen = "[model]\nprovider = \"qwen\"\nname = \"m\"\n";
+        assert!(err(&format!("id = \"a\"\n{model}temperature = \"hot\"")).starts_with("model.temperature:"));
+        assert!(err(&format!("id = \"a\"\n{model}temperature = 3.0")).starts_with("model.temperature:"));
+        assert!(err(&format!("id = \"a\"\n{model}api_key = \"sk\"")).starts_with("model.api_key:"));
+        assert!(err(&format!("id = \"a b\"\n{model}")).starts_with("id:"));
+        assert!(err(&format!("id = \"a\"\ntools = [\"now\", \"search\"]\n{model}")).starts_with("tools[1]:"));
+        assert!(err(&format!("id = \"a\"\n{qwen}api_key_env = \"AGENT_BUILDER_TEST_MISSING_KEY\"")).starts_with("model.api_key_env:"));
+        assert!(err(&format!("id = \"a\"\n{model}api_key_env = \"KEY\"")).starts_with("model.api_key_env:"));
+        assert!(err(&format!("id = \"a\"\n{model}\n[memory]\nkeep = 2")).starts_with("memory.keep:"));
+        assert!(err(&format!("id = \"a\"\n{model}retry = {{ base_delay_ms = 20000 }}")).starts_with("model.retry.base_delay_ms:"));
+        assert!(err(&format!("id = \"a\"\n{model}retry = {{ times = 2 }}")).starts_with("model.retry.times:"));
+        assert!(err(&format!("id = \"a\"\n{model}fallback = [{{ instance = \"test-builder-none\" }}]")).starts_with("model.fallback[0].instance:"));
+        assert!(err(&format!("id = \"a\"\n{model}\n[history]\npolicy = \"all\"")).starts_with("history.policy:"));
+        assert!(err(&format!("id = \"a\"\n{model}\n[knowledge]\ntop_k = 2")).starts_with("knowledge:"));
+        assert!(err("id = \"a\"\n[model]\nname = \"m\"").starts_with("model.provider:"));
+        assert!(err(&format!("id = \"a\"\n{qwen}instance = \"x\"")).starts_with("model.provider:"));
+        assert!(err("id = \"a\"\n[model]\nprovider = \"mock\"\nname = \"m\"").starts_with("model.provider:"));
+        assert!(err("id = \"a\"\n[model]\ninstance = \"test-builder-none\"\nname = \"m\"").starts_with("model.instance:"));
+
+        let yaml = AgentDefine::from_yaml("id: a\nmodel:\n  provider: gpt\n  name: m\n").unwrap_err();
+        assert!(yaml.to_string().starts_with("model.provider:"));
+    }
+
+    #[test]
+    fn test_agent_builder_load_dir() {
+        let dir = std::env::temp_dir().join(wd_tools::uuid::v4());
+        std::fs::create_dir_all(dir.join("prompt")).unwrap();
+        std::fs::write(dir.join("prompt/coder.md"), "{{> rules}}").unwrap();
+        std::fs::write(dir.join("prompt/rules.md"), "be brief").unwrap();
+        let coder = CODER_TOML.replace("template = \"you are {{name}}.\"", "file = \"prompt/coder.md\"\npartials_dir = \"prompt\"");
+        std::fs::write(dir.join("a.toml"), coder.as_str()).unwrap();
+        std::fs::write(dir.join("b.yaml"), "id: coder\nmodel:\n  instance: test-builder-echo\n  name: m\n").unwrap();
+        std::fs::write(dir.join("c.yml"), "id: c\n").unwrap();
+        std::fs::write(dir.join("readme.md"), "").unwrap();
+
+        let report = builder().load_dir(&dir).unwrap();
+        assert_eq!(report.agents.len(), 1);
+        assert_eq!(report.agents[0].render_prompt().unwrap(), "be brief");
+        assert_eq!(report.failed.len(), 2);
+        assert!(report.failed[0].1.contains("already defined"));
+        assert!(report.failed[1].1.contains("c.yml"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
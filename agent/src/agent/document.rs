@@ -0,0 +1,97 @@
+use crate::model::{Message, ModelConfig};
+use crate::store::check_id;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use wd_tools::PFErr;
+
+/// 文档格式的版本，字段有不兼容的变化时递增
+pub const AGENT_DOCUMENT_VERSION: u32 = 1;
+
+/// SingleAgent 持久化后的json文档，模型本身不保存，恢复时由调用方提供
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentDocument {
+    pub version: u32,
+    pub id: String,
+    /// 提示词模板原文
+    pub prompt: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub prompt_vars: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub prompt_partials: HashMap<String, String>,
+    pub model_config: ModelConfig,
+    pub max_history: usize,
+    pub history: Vec<Message>,
+}
+
+impl AgentDocument {
+    /// 文档保存在 `{dir}/{id}.json`
+    pub fn path<P: AsRef<Path>>(dir: P, id: &str) -> anyhow::Result<PathBuf> {
+        check_id(id)?;
+        Ok(dir.as_ref().join(format!("{id}.json")))
+    }
+    /// 先写临时文件再重命名，避免写到一半时留下损坏的文档
+    pub async fn write<P: AsRef<Path>>(&self, dir: P) -> anyhow::Result<()> {
+        let path = Self::path(dir.as_ref(), self.id.as_str())?;
+        tokio::fs::create_dir_all(dir.as_ref()).await?;
+        let body = serde_json::to_vec_pretty(self)?;
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, body).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+    pub async fn read<P: AsRef<Path>>(dir: P, id: &str) -> anyhow::Result<Self> {
+        let path = Self::path(dir, id)?;
+        let body = tokio::fs::read(&path).await?;
+        let doc = serde_json::from_slice::<Self>(body.as_slice())?;
+        if doc.version > AGENT_DOCUMENT_VERSION {
+            return anyhow::anyhow!(
+                "agent document version[{}] is newer than supported[{}]",
+                doc.version,
+                AGENT_DOCUMENT_VERSION
+            )
+            .err();
+        }
+        Ok(doc)
+    }
+    /// 文档不存在时视为删除成功
+    pub async fn remove<P: AsRef<Path>>(dir: P, id: &str) -> anyhow::Result<()> {
+        let path = Self::path(dir, id)?;
+        match tokio::fs::remove_file(&path).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::agent::{AgentDocument, AGENT_DOCUMENT_VERSION};
+    use crate::model::{Message, ModelConfig};
+
+    #[tokio::test]
+    async fn test_agent_document() {
+        let dir = std::env::temp_dir().join(wd_tools::uuid::v4());
+        let doc = AgentDocument {
+            version: AGENT_DOCUMENT_VERSION,
+            id: "doc-1".into(),
+            prompt: "sys".into(),
+            prompt_vars: Default::default(),
+            prompt_partials: Default::default(),
+            model_config: ModelConfig::default().set_name("mock"),
+            max_history: 10,
+            history: vec![Message::new_user("hi"), Message::new_assistant("hello")],
+        };
+        doc.write(&dir).await.unwrap();
+        let load = AgentDocument::read(&dir, "doc-1").await.unwrap();
+        assert_eq!(load.model_config.name, "mock");
+        assert_eq!(load.history[1].content, "hello");
+
+        AgentDocument::remove(&dir, "doc-1").await.unwrap();
+        assert!(AgentDocument::read(&dir, "doc-1").await.is_err());
+        assert!(AgentDocument::remove(&dir, "doc-1").await.is_ok());
+        assert!(AgentDocument::read(&dir, "../doc-1").await.is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::model::tokenizer::{context_size, Tokenizer};
+use crate::model::{Message, MessageType, ModelConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use wd_tools::PFErr;
+
+/// SingleAgent 组装请求时如何截取历史
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryPolicy {
+    /// 保留最近的 max_history 条
+    #[default]
+    MessageCount,
+    /// 在token预算内保留尽量多的近期消息，同时不超过 max_history 条
+    TokenBudget,
+}
+
+/// 留给历史消息的token数：上下文长度扣除输出、system、本次的用户消息(含附件)和工具定义
+pub fn history_token_budget(
+    cfg: &ModelConfig,
+    tokenizer: &dyn Tokenizer,
+    system: &str,
+    user: &Message,
+) -> anyhow::Result<usize> {
+    let mut used = cfg.max_output_token + tokenizer.count_message(user);
+    if !system.is_empty() {
+        used += tokenizer.count_message(&Message::new_system(system));
+    }
+    for i in cfg.tools.iter() {
+        used += tokenizer.count(i.name.as_str())
+            + tokenizer.count(i.description.as_str())
+            + tokenizer.count(i.parameters.to_string().as_str());
+    }
+    let size = context_size(cfg);
+    if used > size {
+        return anyhow::anyhow!("prompt and query need {used} tokens, exceed the context size[{size}]")
+            .err();
+    }
+    Ok(size - used)
+}
+
+/// 从末尾开始截取最多max条历史，budget不为空时总token数不超过预算
+///
+/// 截断后开头的工具结果失去了对应的调用，会一并丢弃
+pub fn window_history(
+    history: &VecDeque<Message>,
+    max: usize,
+    budget: Option<(usize, &dyn Tokenizer)>,
+) -> VecDeque<Message> {
+    let mut list = VecDeque::new();
+    let mut used = 0;
+    for msg in history.iter().rev().take(max) {
+        if let Some((budget, tokenizer)) = budget {
+            used += tokenizer.count_message(msg);
+            if used > budget {
+                break;
+            }
+        }
+        list.push_front(msg.clone());
+    }
+    while matches!(list.front(), Some(m) if matches!(m.role, MessageType::TOOL)) {
+        list.pop_front();
+    }
+    list
+}
+
+#[cfg(test)]
+mod test {
+    use crate::agent::{history_token_budget, window_history};
+    use crate::model::tokenizer::EstimateTokenizer;
+    use crate::model::{Message, ModelConfig};
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_window_history() {
+        let history = VecDeque::from(vec![
+            Message::new_user("a long pasted log ".repeat(100)),
+            Message::new_assistant("ok"),
+            Message::new_tool("call_1", "12:00"),
+            Message::new_user("hi"),
+            Message::new_assistant("hello"),
+        ]);
+        let list = window_history(&history, 3, None);
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].content, "hi");
+
+        let t = EstimateTokenizer;
+        let list = window_history(&history, 10, Some((100, &t)));
+        assert_eq!(list.len(), 4);
+        assert_eq!(list[0].content, "ok");
+        let list = window_history(&history, 10, Some((1000, &t)));
+        assert_eq!(list.len(), 5);
+    }
+
+    #[test]
+    fn test_history_token_budget() {
+        let t = EstimateTokenizer;
+        let cfg = ModelConfig::default().set_context_size(1024);
+        let budget = history_token_budget(&cfg, &t, "system", &Message::new_user("hello world")).unwrap();
+        //512 output + (4+2) system + (4+4) query
+        assert_eq!(budget, 1024 - 512 - 6 - 8);
+        let query = "x ".repeat(600);
+        assert!(history_token_budget(&cfg, &t, "", &Message::new_user(query)).is_err());
+    }
+}
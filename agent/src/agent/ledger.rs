@@ -0,0 +1,101 @@
+use crate::model::Usage;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use wd_tools::sync::Am;
+
+/// 日期(本地时间 `YYYY-MM-DD`) -> 模型名 -> 用量
+pub type DailyUsage = BTreeMap<String, HashMap<String, Usage>>;
+
+/// 按天、按模型累计的token用量，设置了文件时每次记录后写入
+///
+/// 可以被多个agent共享，统计整个应用的花费
+pub struct UsageLedger {
+    path: Option<PathBuf>,
+    days: Am<DailyUsage>,
+}
+
+impl Default for UsageLedger {
+    fn default() -> Self {
+        Self {
+            path: None,
+            days: Am::new(DailyUsage::new()),
+        }
+    }
+}
+
+impl UsageLedger {
+    /// 只在内存中累计
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// 读取已有的记录，文件不存在时从空记录开始
+    pub fn open<P: Into<PathBuf>>(path: P) -> anyhow::Result<Self> {
+        let path = path.into();
+        let days = match std::fs::read(path.as_path()) {
+            Ok(body) => serde_json::from_slice::<DailyUsage>(body.as_slice())?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => DailyUsage::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: Some(path),
+            days: Am::new(days),
+        })
+    }
+    pub fn today() -> String {
+        chrono::Local::now().format("%Y-%m-%d").to_string()
+    }
+    /// 记到今天
+    pub async fn record(&self, model: &str, usage: Usage) -> anyhow::Result<()> {
+        self.record_on(Self::today().as_str(), model, usage).await
+    }
+    pub async fn record_on(&self, day: &str, model: &str, usage: Usage) -> anyhow::Result<()> {
+        let mut lock = self.days.lock().await;
+        *lock
+            .entry(day.to_string())
+            .or_default()
+            .entry(model.to_string())
+            .or_default() += usage;
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        //持有锁写入，避免并发记录时旧的内容覆盖新的
+        Self::write(path, &lock).await
+    }
+    async fn write(path: &Path, days: &DailyUsage) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(days)?).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+    /// 某一天各个模型的用量
+    pub async fn day(&self, day: &str) -> HashMap<String, Usage> {
+        self.days.lock().await.get(day).cloned().unwrap_or_default()
+    }
+    pub async fn all(&self) -> DailyUsage {
+        self.days.lock().await.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::agent::UsageLedger;
+    use crate::model::Usage;
+
+    #[tokio::test]
+    async fn test_usage_ledger() {
+        let path = std::env::temp_dir().join(wd_tools::uuid::v4()).join("usage.json");
+        let ledger = UsageLedger::open(&path).unwrap();
+        ledger.record_on("2024-10-01", "qwen-turbo", Usage::new(10, 5)).await.unwrap();
+        ledger.record_on("2024-10-01", "qwen-turbo", Usage::new(1, 1)).await.unwrap();
+        ledger.record_on("2024-10-02", "qwen-plus", Usage::new(3, 3)).await.unwrap();
+
+        let ledger = UsageLedger::open(&path).unwrap();
+        assert_eq!(ledger.day("2024-10-01").await["qwen-turbo"], Usage::new(11, 6));
+        assert_eq!(ledger.all().await.len(), 2);
+        assert!(ledger.day("2024-10-03").await.is_empty());
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::model::embedding::EmbeddingModel;
+use crate::model::Message;
+use crate::store::{VectorHit, VectorIndex, VectorRecord};
+use std::path::PathBuf;
+use std::sync::Arc;
+use wd_tools::sync::Am;
+
+/// 注入到请求中的长期记忆前缀
+const LONG_TERM_HEAD: &str = "以下是与当前问题可能相关的历史记忆，仅在确实相关时参考：";
+
+/// 跨会话的长期记忆：每轮对话结束后写入向量索引，提问前检索最相关的几条注入到请求中
+///
+/// 同一个实例可以被多个agent共享
+pub struct LongTermMemory {
+    pub embedding: Arc<dyn EmbeddingModel>,
+    index: Am<Box<dyn VectorIndex>>,
+    /// 设置后每次写入都会持久化到该文件
+    pub path: Option<PathBuf>,
+    /// 最多注入几条
+    pub top_k: usize,
+    /// 相似度低于该值的记忆不注入
+    pub min_score: f32,
+    /// 问答合计少于该字符数的对话不写入，过滤"你好""谢谢"之类的闲聊
+    pub min_chars: usize,
+}
+
+impl LongTermMemory {
+    pub fn new<E: EmbeddingModel + 'static, I: VectorIndex + 'static>(embedding: E, index: I) -> Self {
+        Self {
+            embedding: Arc::new(embedding),
+            index: Am::new(Box::new(index)),
+            path: None,
+            top_k: 3,
+            min_score: 0.5,
+            min_chars: 8,
+        }
+    }
+    pub fn set_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+    pub fn set_top_k(mut self, k: usize) -> Self {
+        self.top_k = k;
+        self
+    }
+    pub fn set_min_score(mut self, score: f32) -> Self {
+        self.min_score = score;
+        self
+    }
+    pub fn set_min_chars(mut self, chars: usize) -> Self {
+        self.min_chars = chars;
+        self
+    }
+    pub async fn len(&self) -> usize {
+        self.index.lock().await.len()
+    }
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+    /// 写入一条记忆，返回记忆id
+    pub async fn remember(&self, text: &str, source: &str) -> anyhow::Result<String> {
+        let vector = self.embedding.embed_one(text).await?;
+        let id = wd_tools::uuid::v4();
+        let record = VectorRecord::new(id.as_str(), text, vector).set_metadata("source", source);
+        let mut index = self.index.lock().await;
+        index.insert(record)?;
+        if let Some(ref path) = self.path {
+            index.save(path.as_path())?;
+        }
+        Ok(id)
+    }
+    pub async fn forget(&self, id: &str) -> anyhow::Result<bool> {
+        let mut index = self.index.lock().await;
+        let removed = index.remove(id);
+        if let (true, Some(path)) = (removed, self.path.as_ref()) {
+            index.save(path.as_path())?;
+        }
+        Ok(removed)
+    }
+    /// 检索与query相关的记忆，已经按阈值过滤
+    pub async fn recall(&self, query: &str) -> anyhow::Result<Vec<VectorHit>> {
+        if self.top_k == 0 || self.is_empty().await {
+            return Ok(vec![]);
+        }
+        let vector = self.embedding.embed_one(query).await?;
+        let hits = self.index.lock().await.search(&vector, self.top_k);
+        Ok(hits.into_iter().filter(|x| x.score >= self.min_score).collect())
+    }
+    /// 把检索结果组装成一条SYSTEM消息，没有相关记忆时返回None
+    pub async fn recall_message(&self, query: &str) -> anyhow::Result<Option<Message>> {
+        let hits = self.recall(query).await?;
+        if hits.is_empty() {
+            return Ok(None);
+        }
+        let mut content = LONG_TERM_HEAD.to_string();
+        for i in hits.iter() {
+            content.push_str(format!("\n- {}", i.record.text.replace('\n', " ")).as_str());
+        }
+        Ok(Some(Message::new_system(content)))
+    }
+    /// 一轮问答写入为一条记忆，过短的对话直接忽略
+    pub async fn remember_turn(&self, source: &str, query: &str, answer: &str) -> anyhow::Result<()> {
+        if query.chars().count() + answer.chars().count() < self.min_chars {
+            return Ok(());
+        }
+        self.remember(format!("user: {query}\nassistant: {answer}").as_str(), source)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::agent::LongTermMemory;
+    use crate::model::embedding::HashEmbedding;
+    use crate::store::FlatIndex;
+
+    #[tokio::test]
+    async fn test_long_term_memory() {
+        let path = std::env::temp_dir().join(format!("{}.json", wd_tools::uuid::v4()));
+        let memory = LongTermMemory::new(HashEmbedding::default(), FlatIndex::new())
+            .set_path(&path)
+            .set_min_score(0.3);
+        memory
+            .remember_turn("a1", "my favorite language is rust", "noted, rust it is")
+            .await
+            .unwrap();
+        memory.remember_turn("a1", "hi", "hello").await.unwrap();
+        memory
+            .remember_turn("a1", "the weather in beijing is sunny", "great")
+            .await
+            .unwrap();
+        assert_eq!(memory.len().await, 2);
+
+        let hits = memory.recall("which language is my favorite?").await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].record.text.contains("rust"));
+        assert_eq!(hits[0].record.metadata["source"], "a1");
+        assert!(memory.recall_message("quantum physics").await.unwrap().is_none());
+
+        //持久化后可以在新的实例中恢复
+        let load = LongTermMemory::new(HashEmbedding::default(), FlatIndex::open(&path).unwrap())
+            .set_min_score(0.3);
+        let msg = load.recall_message("which language is my favorite?").await.unwrap().unwrap();
+        assert!(msg.content.contains("my favorite language is rust"));
+        assert!(load.forget(hits[0].record.id.as_str()).await.unwrap());
+        assert!(load.recall("which language is my favorite?").await.unwrap().is_empty());
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::agent::window_history;
+use crate::model::tokenizer::Tokenizer;
+use crate::model::{Message, MessageType, Model, ModelConfig};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use wd_tools::sync::Am;
+
+const SUMMARY_PROMPT: &str = "你负责压缩对话记录。请把已有摘要和新的对话合并成一段简洁的摘要，\
+保留用户的目标、偏好、关键事实、已经得出的结论和尚未完成的事项，不要编造对话中没有的内容。";
+/// 注入到请求中的摘要前缀
+const SUMMARY_HEAD: &str = "以下是更早之前对话的摘要：\n";
+
+/// 组装请求和整理历史时agent提供的参数
+pub struct MemoryContext<'a> {
+    pub id: &'a str,
+    pub max_history: usize,
+    /// HistoryPolicy::TokenBudget 时留给历史的token数
+    pub budget: Option<(usize, &'a dyn Tokenizer)>,
+}
+
+/// SingleAgent 的记忆策略：决定每次请求携带哪些历史，以及一轮对话结束后如何整理历史
+#[async_trait::async_trait]
+pub trait Memory: Send + Sync {
+    /// 返回的消息位于system prompt之后、本次提问之前
+    async fn recall(
+        &self,
+        history: &VecDeque<Message>,
+        ctx: &MemoryContext<'_>,
+    ) -> anyhow::Result<Vec<Message>>;
+    /// 一轮对话结束后调用，此时agent仍处于回复状态，不会有新的提问
+    async fn commit(
+        &self,
+        _history: &Am<VecDeque<Message>>,
+        _ctx: &MemoryContext<'_>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// 默认策略：只截取最近的历史，超出窗口的消息不再发送给模型
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SlidingWindowMemory;
+
+#[async_trait::async_trait]
+impl Memory for SlidingWindowMemory {
+    async fn recall(
+        &self,
+        history: &VecDeque<Message>,
+        ctx: &MemoryContext<'_>,
+    ) -> anyhow::Result<Vec<Message>> {
+        Ok(window_history(history, ctx.max_history, ctx.budget).into())
+    }
+}
+
+/// 滚动摘要：历史超过threshold条时，把较早的对话交给模型压缩成摘要，只保留最近keep条原文
+///
+/// 摘要以SYSTEM消息的形式放在历史开头，随历史一起保存；
+/// 被压缩的原文在agent挂载了store时已经逐轮写入，这里只从内存中移除
+pub struct SummaryMemory {
+    pub model: Arc<dyn Model + Sync>,
+    pub model_config: ModelConfig,
+    pub prompt: String,
+    pub threshold: usize,
+    pub keep: usize,
+}
+
+impl SummaryMemory {
+    /// 可以使用比对话更便宜的模型
+    pub fn new<M: Model + Sync + 'static>(model: M) -> Self {
+        Self {
+            model: Arc::new(model),
+            model_config: Default::default(),
+            prompt: SUMMARY_PROMPT.into(),
+            threshold: 20,
+            keep: 6,
+        }
+    }
+    pub fn cove_model_config<C: Into<ModelConfig>>(mut self, cfg: C) -> Self {
+        self.model_config = cfg.into();
+        self
+    }
+    pub fn set_model_config(mut self, handle: impl FnOnce(&mut ModelConfig)) -> Self {
+        handle(&mut self.model_config);
+        self
+    }
+    pub fn set_prompt<P: Into<String>>(mut self, prompt: P) -> Self {
+        self.prompt = prompt.into();
+        self
+    }
+    pub fn set_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+    pub fn set_keep(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+    /// 历史开头的SYSTEM消息就是摘要，agent本身不会把system prompt放进历史
+    pub fn summary(history: &VecDeque<Message>) -> Option<&str> {
+        match history.front() {
+            Some(m) if matches!(m.role, MessageType::SYSTEM) => {
+                Some(m.content.strip_prefix(SUMMARY_HEAD).unwrap_or(m.content.as_str()))
+            }
+            _ => None,
+        }
+    }
+    fn transcript(summary: Option<&str>, list: &[Message]) -> String {
+        let mut buf = String::new();
+        if let Some(summary) = summary {
+            buf.push_str(format!("已有摘要：\n{summary}\n\n").as_str());
+        }
+        buf.push_str("新的对话：\n");
+        for i in list.iter() {
+            buf.push_str(format!("{}: {}", i.role, i.text_with_placeholder()).as_str());
+            for call in i.tool_calls.iter() {
+                buf.push_str(format!(" [调用工具 {}({})]", call.name, call.arguments).as_str());
+            }
+            buf.push('\n');
+        }
+        buf
+    }
+}
+
+#[async_trait::async_trait]
+impl Memory for SummaryMemory {
+    async fn recall(
+        &self,
+        history: &VecDeque<Message>,
+        ctx: &MemoryContext<'_>,
+    ) -> anyhow::Result<Vec<Message>> {
+        if Self::summary(history).is_none() {
+            return SlidingWindowMemory.recall(history, ctx).await;
+        }
+        let summary = &history[0];
+        let rest = history.iter().skip(1).cloned().collect::<VecDeque<_>>();
+        let budget = ctx
+            .budget
+            .map(|(b, t)| (b.saturating_sub(t.count_message(summary)), t));
+        let mut list = vec![summary.clone()];
+        list.extend(window_history(&rest, ctx.max_history, budget));
+        Ok(list)
+    }
+
+    async fn commit(
+        &self,
+        history: &Am<VecDeque<Message>>,
+        ctx: &MemoryContext<'_>,
+    ) -> anyhow::Result<()> {
+        let lock = history.lock().await;
+        let skip = Self::summary(&lock).is_some() as usize;
+        if lock.len() - skip <= self.threshold {
+            return Ok(());
+        }
+        //从keep条之前开始找一条USER消息作为切分点，保证保留的原文是完整的轮次
+        let Some(split) = (skip + 1..=lock.len().saturating_sub(self.keep))
+            .rev()
+            .find(|i| matches!(lock[*i].role, MessageType::User))
+        else {
+            return Ok(());
+        };
+        let summary = Self::summary(&lock).map(|x| x.to_string());
+        //连同旧摘要一起记下，写回前用来确认历史没有被替换
+        let folded = lock.range(..split).cloned().collect::<Vec<_>>();
+        let fold = &folded[skip..];
+        drop(lock);
+
+        let request = vec![
+            Message::new_system(self.prompt.as_str()),
+            Message::new_user(Self::transcript(summary.as_deref(), fold)),
+        ];
+        let (msg, _) = self
+            .model
+            .chat(&self.model_config, request.as_slice())
+            .await?
+            .collect()
+            .await?;
+
+        let mut lock = history.lock().await;
+        //压缩期间历史被清空或替换过，放弃本次结果
+        if lock.len() < split || !lock.range(..split).eq(folded.iter()) {
+            wd_log::log_field("id", ctx.id).warn("SummaryMemory.commit history changed, drop summary");
+            return Ok(());
+        }
+        lock.drain(..split);
+        lock.push_front(Message::new_system(format!("{SUMMARY_HEAD}{}", msg.content)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::agent::{Memory, MemoryContext, SummaryMemory};
+    use crate::model::mock::{MockModel, MockReply};
+    use crate::model::{Message, MessageType};
+    use std::collections::VecDeque;
+    use std::time::Duration;
+    use wd_tools::sync::Am;
+
+    fn turns(start: usize, n: usize) -> VecDeque<Message> {
+        (start..start + n)
+            .flat_map(|i| [Message::new_user(format!("q{i}")), Message::new_assistant(format!("a{i}"))])
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_summary_memory() {
+        let model = MockModel::new().reply_text("s1").reply_text("s2");
+        let memory = SummaryMemory::new(model.clone()).set_threshold(6).set_keep(2);
+        let ctx = MemoryContext { id: "test", max_history: 10, budget: None };
+        let history = Am::new(turns(0, 3));
+
+        //未超过阈值
+        memory.commit(&history, &ctx).await.unwrap();
+        assert_eq!(history.synchronize().len(), 6);
+        assert!(model.records().is_empty());
+
+        history.synchronize().extend(turns(3, 1));
+        memory.commit(&history, &ctx).await.unwrap();
+        let list = memory.recall(&history.synchronize(), &ctx).await.unwrap();
+        let list = list.iter().map(|x| x.content.as_str()).collect::<Vec<_>>();
+        assert_eq!(list, vec!["以下是更早之前对话的摘要：\ns1", "q3", "a3"]);
+        let record = model.last_record().unwrap();
+        assert!(record.messages[1].content.contains("user: q0"));
+        assert!(!record.messages[1].content.contains("q3"));
+
+        //再次压缩时带上已有摘要
+        history.synchronize().extend(turns(4, 3));
+        memory.commit(&history, &ctx).await.unwrap();
+        let lock = history.synchronize();
+        assert!(matches!(lock[0].role, MessageType::SYSTEM));
+        assert_eq!(SummaryMemory::summary(&lock), Some("s2"));
+        assert_eq!(lock.len(), 3);
+        let record = model.last_record().unwrap();
+        assert!(record.messages[1].content.contains("已有摘要：\ns1"));
+    }
+
+    #[tokio::test]
+    async fn test_summary_memory_history_replaced() {
+        let model = MockModel::new().reply(MockReply::chunks(["s", "1"], Duration::from_millis(100)));
+        let memory = SummaryMemory::new(model).set_threshold(6).set_keep(2);
+        let ctx = MemoryContext { id: "test", max_history: 10, budget: None };
+        let history = Am::new(turns(0, 4));
+
+        //压缩期间历史被换成了另一段同样长的对话
+        let replace = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            *history.lock().await = turns(10, 4);
+        };
+        let (result, _) = tokio::join!(memory.commit(&history, &ctx), replace);
+        result.unwrap();
+        assert_eq!(*history.synchronize(), turns(10, 4));
+    }
+}
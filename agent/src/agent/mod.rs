@@ -1,46 +1,135 @@
+use crate::model::content::ContentPart;
+use crate::model::ResponseEvent;
+use async_channel::{Receiver, Sender};
 use async_trait::async_trait;
-use std::collections::VecDeque;
-use std::sync::Arc;
-use wd_tools::sync::Am;
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
 mod agent;
 mod builder;
+mod document;
+mod history;
+mod ledger;
+mod long_term;
+mod memory;
+mod tool;
 
 pub use agent::*;
+pub use builder::*;
+pub use document::*;
+pub use history::*;
+pub use ledger::*;
+pub use long_term::*;
+pub use memory::*;
+pub use tool::*;
 
-#[derive(Debug, Clone)]
+/// agent回复的事件流，收到 Finish 或 Error 之后流结束(返回None)
+///
+/// clone出来的实例共享同一个队列，一个事件只会被其中一个实例收到
+#[derive(Debug)]
 pub struct ChatRespStream {
-    chan: Arc<Am<VecDeque<anyhow::Result<String>>>>,
+    sender: Sender<ResponseEvent>,
+    //Receiver没有实现Unpin，固定在堆上以便直接poll
+    receiver: Pin<Box<Receiver<ResponseEvent>>>,
+    finished: bool,
+}
+impl Clone for ChatRespStream {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            receiver: Box::pin((*self.receiver).clone()),
+            finished: self.finished,
+        }
+    }
+}
+impl Default for ChatRespStream {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl ChatRespStream {
     pub fn new() -> Self {
-        let chan = Arc::new(Am::new(VecDeque::new()));
-        Self { chan }
-    }
-    pub fn next(&self) -> anyhow::Result<Option<String>> {
-        let mut fut = self.chan.synchronize();
-        match fut.pop_back() {
-            None => Ok(None),
-            Some(s) => {
-                let r = s?;
-                Ok(Some(r))
-            }
+        let (sender, receiver) = async_channel::unbounded();
+        Self {
+            sender,
+            receiver: Box::pin(receiver),
+            finished: false,
         }
     }
-    pub fn push<S: Into<String>>(&self, msg: S) {
-        let mut fut = self.chan.synchronize();
-        fut.push_front(Ok(msg.into()));
+    /// 等待下一个事件
+    pub async fn recv(&mut self) -> Option<ResponseEvent> {
+        self.next().await
     }
-    pub fn push_err(&self, err: anyhow::Error) {
-        let mut fut = self.chan.synchronize();
-        fut.push_front(Err(err))
+    /// 不等待，没有新事件时返回None，适合在ui的刷新循环中调用
+    pub fn try_recv(&mut self) -> Option<ResponseEvent> {
+        if self.finished {
+            return None;
+        }
+        let event = self.receiver.try_recv().ok()?;
+        self.finished = event.is_end();
+        Some(event)
+    }
+    /// 回复是否已经结束
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+    pub fn push<E: Into<ResponseEvent>>(&self, event: E) {
+        //unbounded的通道只有在关闭后才会发送失败
+        if let Err(err) = self.sender.try_send(event.into()) {
+            wd_log::log_field("error", err).error("ChatRespStream.push send event failed");
+        }
+    }
+}
+impl Stream for ChatRespStream {
+    type Item = ResponseEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+        let poll = self.receiver.as_mut().poll_next(cx);
+        if let Poll::Ready(ref event) = poll {
+            self.finished = event.as_ref().map(|x| x.is_end()).unwrap_or(true);
+        }
+        poll
     }
 }
 
 #[async_trait::async_trait]
 pub trait Agent {
     async fn chat(&self, query: String) -> anyhow::Result<ChatRespStream>;
+    /// 附带图片、文件等内容的提问，检索记忆和知识库时只使用query
+    async fn chat_with_parts(&self, query: String, parts: Vec<ContentPart>) -> anyhow::Result<ChatRespStream>;
+    /// 终止正在进行的回复，回复流会以 Finish(Canceled) 结束
+    async fn cancel(&self);
     async fn clear_chat_history(&self);
-    async fn save(&self) -> String;
-    async fn delete(&self);
+    /// 持久化当前的设定和历史，返回文档id
+    async fn save(&self) -> anyhow::Result<String>;
+    /// 删除持久化的记录
+    async fn delete(&self) -> anyhow::Result<()>;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::agent::ChatRespStream;
+    use crate::model::{FinishReason, ResponseEvent};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_chat_resp_stream() {
+        let mut stream = ChatRespStream::new();
+        assert!(stream.try_recv().is_none());
+
+        let sender = stream.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            sender.push(ResponseEvent::Text("hello".into()));
+            sender.push(ResponseEvent::Finish(FinishReason::Stop));
+        });
+        assert!(matches!(stream.recv().await, Some(ResponseEvent::Text(s)) if s == "hello"));
+        assert!(matches!(stream.recv().await, Some(ResponseEvent::Finish(FinishReason::Stop))));
+        assert!(stream.is_finished());
+        assert!(stream.recv().await.is_none());
+    }
 }
@@ -0,0 +1,41 @@
+use crate::model::ToolDefine;
+use std::future::Future;
+use std::pin::Pin;
+
+/// 可以被模型调用的本地工具
+#[async_trait::async_trait]
+pub trait Tool: Send + Sync {
+    fn define(&self) -> ToolDefine;
+    /// arguments 为模型生成的json参数，返回值会作为TOOL消息回传给模型
+    async fn call(&self, arguments: String) -> anyhow::Result<String>;
+}
+
+type ToolFuture = Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send>>;
+
+/// 用闭包快速定义一个工具
+pub struct FnTool {
+    define: ToolDefine,
+    handle: Box<dyn Fn(String) -> ToolFuture + Send + Sync>,
+}
+
+impl FnTool {
+    pub fn new<F, Fut>(define: ToolDefine, handle: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<String>> + Send + 'static,
+    {
+        let handle = Box::new(move |args: String| -> ToolFuture { Box::pin(handle(args)) });
+        Self { define, handle }
+    }
+}
+
+#[async_trait::async_trait]
+impl Tool for FnTool {
+    fn define(&self) -> ToolDefine {
+        self.define.clone()
+    }
+
+    async fn call(&self, arguments: String) -> anyhow::Result<String> {
+        (self.handle)(arguments).await
+    }
+}
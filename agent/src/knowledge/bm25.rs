@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 分词：ASCII单词转小写，其他文字(中文等)取单字和相邻两字，不依赖词典
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut list = vec![];
+    let mut word = String::new();
+    let mut last: Option<char> = None;
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            word.push(c.to_ascii_lowercase());
+            last = None;
+            continue;
+        }
+        if !word.is_empty() {
+            list.push(std::mem::take(&mut word));
+        }
+        if c.is_alphanumeric() {
+            list.push(c.to_string());
+            if let Some(l) = last {
+                list.push(format!("{l}{c}"));
+            }
+            last = Some(c);
+        } else {
+            last = None;
+        }
+    }
+    if !word.is_empty() {
+        list.push(word);
+    }
+    list
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Bm25Doc {
+    len: usize,
+    tf: HashMap<String, u32>,
+}
+
+/// 关键词检索，对专有名词、函数名等向量不擅长的查询效果更好
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bm25Index {
+    pub k1: f32,
+    pub b: f32,
+    docs: HashMap<String, Bm25Doc>,
+    df: HashMap<String, usize>,
+    total_len: usize,
+}
+
+impl Default for Bm25Index {
+    fn default() -> Self {
+        Self {
+            k1: 1.2,
+            b: 0.75,
+            docs: HashMap::new(),
+            df: HashMap::new(),
+            total_len: 0,
+        }
+    }
+}
+
+impl Bm25Index {
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+    /// id已存在时覆盖
+    pub fn insert(&mut self, id: &str, text: &str) {
+        self.remove(id);
+        let tokens = tokenize(text);
+        let mut tf = HashMap::new();
+        for i in tokens.iter() {
+            *tf.entry(i.clone()).or_insert(0) += 1;
+        }
+        for i in tf.keys() {
+            *self.df.entry(i.clone()).or_insert(0) += 1;
+        }
+        self.total_len += tokens.len();
+        self.docs.insert(id.to_string(), Bm25Doc { len: tokens.len(), tf });
+    }
+    pub fn remove(&mut self, id: &str) -> bool {
+        let Some(doc) = self.docs.remove(id) else {
+            return false;
+        };
+        self.total_len -= doc.len;
+        for i in doc.tf.keys() {
+            if let Some(n) = self.df.get_mut(i) {
+                *n -= 1;
+                if *n == 0 {
+                    self.df.remove(i);
+                }
+            }
+        }
+        true
+    }
+    /// 按得分从高到低返回最多k条，没有命中任何词的文档不返回
+    pub fn search(&self, query: &str, k: usize) -> Vec<(String, f32)> {
+        if self.docs.is_empty() {
+            return vec![];
+        }
+        let n = self.docs.len() as f32;
+        let avg = self.total_len as f32 / n;
+        let mut terms = tokenize(query);
+        terms.sort();
+        terms.dedup();
+        let mut list = self
+            .docs
+            .iter()
+            .filter_map(|(id, doc)| {
+                let mut score = 0.0;
+                for t in terms.iter() {
+                    let Some(tf) = doc.tf.get(t) else {
+                        continue;
+                    };
+                    let df = self.df.get(t).copied().unwrap_or(0) as f32;
+                    let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+                    let tf = *tf as f32;
+                    let norm = self.k1 * (1.0 - self.b + self.b * doc.len as f32 / avg.max(1.0));
+                    score += idf * tf * (self.k1 + 1.0) / (tf + norm);
+                }
+                (score > 0.0).then(|| (id.clone(), score))
+            })
+            .collect::<Vec<_>>();
+        list.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        list.truncate(k);
+        list
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::knowledge::{tokenize, Bm25Index};
+
+    #[test]
+    fn test_bm25_index() {
+        assert_eq!(tokenize("Hello, rust_lang 知识库"), vec!["hello", "rust_lang", "知", "识", "知识", "库", "识库"]);
+
+        let mut index = Bm25Index::default();
+        index.insert("a", "the quick brown fox");
+        index.insert("b", "the lazy dog sleeps all day, the dog");
+        index.insert("c", "知识库支持markdown文档");
+        let hits = index.search("lazy dog", 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].0, "b");
+        assert_eq!(index.search("知识", 10)[0].0, "c");
+        assert!(index.search("cat", 10).is_empty());
+
+        assert!(index.remove("b"));
+        assert!(index.search("dog", 10).is_empty());
+        assert_eq!(index.len(), 2);
+    }
+}
@@ -0,0 +1,266 @@
+use crate::knowledge::{Document, DocumentKind};
+use serde::{Deserialize, Serialize};
+
+/// 文档切分后的片段，检索和引用的最小单位
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunk {
+    /// `{source}#{序号}`
+    pub id: String,
+    pub source: String,
+    /// 所在章节：markdown为标题路径，代码为顶层定义的首行，纯文本为空
+    pub section: String,
+    pub text: String,
+}
+
+impl Chunk {
+    /// 引用时展示的位置
+    pub fn location(&self) -> String {
+        if self.section.is_empty() {
+            self.source.clone()
+        } else {
+            format!("{} > {}", self.source, self.section)
+        }
+    }
+    /// 建索引用的文本，带上章节提高召回
+    pub fn index_text(&self) -> String {
+        if self.section.is_empty() {
+            self.text.clone()
+        } else {
+            format!("{}\n{}", self.section, self.text)
+        }
+    }
+}
+
+/// 按章节切分文档，同一章节内按段落合并到max_chars以内，相邻片段重叠overlap个字符
+#[derive(Debug, Clone, Copy)]
+pub struct Chunker {
+    pub max_chars: usize,
+    pub overlap: usize,
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Self::new(800, 100)
+    }
+}
+
+struct Section {
+    title: String,
+    paragraphs: Vec<String>,
+}
+
+impl Chunker {
+    pub fn new(max_chars: usize, overlap: usize) -> Self {
+        let max_chars = max_chars.max(16);
+        Self {
+            max_chars,
+            overlap: overlap.min(max_chars / 2),
+        }
+    }
+    pub fn split(&self, doc: &Document) -> Vec<Chunk> {
+        let sections = match doc.kind {
+            DocumentKind::Markdown => markdown_sections(doc.text.as_str()),
+            DocumentKind::Code(_) => code_sections(doc.text.as_str()),
+            DocumentKind::Text | DocumentKind::Pdf => vec![Section {
+                title: String::new(),
+                paragraphs: paragraphs(doc.text.as_str(), false),
+            }],
+        };
+        let mut list = vec![];
+        for section in sections {
+            for text in self.pack(section.paragraphs) {
+                list.push(Chunk {
+                    id: format!("{}#{}", doc.source, list.len()),
+                    source: doc.source.clone(),
+                    section: section.title.clone(),
+                    text,
+                });
+            }
+        }
+        list
+    }
+    /// 把段落合并成不超过max_chars的片段，超长的段落按字符切开
+    fn pack(&self, paragraphs: Vec<String>) -> Vec<String> {
+        let mut list: Vec<String> = vec![];
+        let mut buf = String::new();
+        //buf中只有上一个片段的重叠部分
+        let mut carry = false;
+        for p in paragraphs {
+            let len = p.chars().count();
+            if !buf.is_empty() && buf.chars().count() + len + 2 > self.max_chars {
+                let tail = tail_chars(buf.as_str(), self.overlap);
+                list.push(std::mem::replace(&mut buf, tail));
+                carry = true;
+            }
+            if buf.chars().count() + len > self.max_chars {
+                if !carry && !buf.is_empty() {
+                    list.push(std::mem::take(&mut buf));
+                }
+                let chars = p.chars().collect::<Vec<_>>();
+                let mut start = 0;
+                loop {
+                    let end = (start + self.max_chars).min(chars.len());
+                    let piece = chars[start..end].iter().collect::<String>();
+                    if end == chars.len() {
+                        buf = piece;
+                        break;
+                    }
+                    list.push(piece);
+                    start += self.max_chars - self.overlap;
+                }
+                carry = false;
+                continue;
+            }
+            if !buf.is_empty() {
+                buf.push_str("\n\n");
+            }
+            buf.push_str(p.as_str());
+            carry = false;
+        }
+        if !carry && !buf.trim().is_empty() {
+            list.push(buf);
+        }
+        list
+    }
+}
+
+fn tail_chars(s: &str, n: usize) -> String {
+    let count = s.chars().count();
+    s.chars().skip(count.saturating_sub(n)).collect()
+}
+
+/// 按空行分段，code_fence为true时markdown代码块内部的空行不分段
+fn paragraphs(text: &str, code_fence: bool) -> Vec<String> {
+    let mut list = vec![];
+    let mut buf = String::new();
+    let mut in_fence = false;
+    for line in text.lines() {
+        if code_fence && line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if line.trim().is_empty() && !in_fence {
+            if !buf.trim().is_empty() {
+                list.push(std::mem::take(&mut buf));
+            }
+            buf.clear();
+            continue;
+        }
+        if !buf.is_empty() {
+            buf.push('\n');
+        }
+        buf.push_str(line.trim_end());
+    }
+    if !buf.trim().is_empty() {
+        list.push(buf);
+    }
+    list
+}
+
+/// 以标题切分章节，章节名为完整的标题路径，如 `安装 > Linux`
+fn markdown_sections(text: &str) -> Vec<Section> {
+    let mut sections = vec![];
+    let mut headings: Vec<(usize, String)> = vec![];
+    let mut body = String::new();
+    let mut in_fence = false;
+    let title = |headings: &[(usize, String)]| {
+        headings.iter().map(|x| x.1.as_str()).collect::<Vec<_>>().join(" > ")
+    };
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        let level = line.chars().take_while(|c| *c == '#').count();
+        let is_heading = !in_fence && (1..=6).contains(&level) && line[level..].starts_with(' ');
+        if !is_heading {
+            body.push_str(line);
+            body.push('\n');
+            continue;
+        }
+        sections.push(Section {
+            title: title(&headings),
+            paragraphs: paragraphs(body.as_str(), true),
+        });
+        body.clear();
+        headings.retain(|x| x.0 < level);
+        headings.push((level, line[level..].trim().to_string()));
+    }
+    sections.push(Section {
+        title: title(&headings),
+        paragraphs: paragraphs(body.as_str(), true),
+    });
+    sections.retain(|x| !x.paragraphs.is_empty());
+    sections
+}
+
+/// 顶格的定义(不是注释、属性或右括号)开始一个新章节，章节名为该行内容
+fn code_sections(text: &str) -> Vec<Section> {
+    let mut sections: Vec<Section> = vec![];
+    for p in paragraphs(text, false) {
+        let head = p.lines().find(|l| {
+            let l = l.trim_start();
+            !(l.starts_with("//") || l.starts_with('#') || l.starts_with("/*") || l.starts_with('*'))
+        });
+        let top_level = head.is_some_and(|l| !l.starts_with([' ', '\t', '}', ')', ']']));
+        match (top_level, sections.last_mut()) {
+            (false, Some(last)) => last.paragraphs.push(p),
+            _ => {
+                let title = head
+                    .map(|x| x.trim().trim_end_matches('{').trim().chars().take(80).collect())
+                    .unwrap_or_default();
+                sections.push(Section {
+                    title,
+                    paragraphs: vec![p],
+                });
+            }
+        }
+    }
+    sections
+}
+
+#[cfg(test)]
+mod test {
+    use crate::knowledge::{Chunker, Document, DocumentKind};
+
+    #[test]
+    fn test_chunk_markdown() {
+        let text = "intro\n\n# Install\n\n## Linux\n\nrun `make`\n\n```sh\n# not a heading\n\nmake install\n```\n\n## Windows\n\nuse the installer\n\n# Usage\n\nrun it";
+        let doc = Document::new("README.md", DocumentKind::Markdown, text);
+        let chunks = Chunker::default().split(&doc);
+        let list = chunks.iter().map(|x| (x.section.as_str(), x.text.as_str())).collect::<Vec<_>>();
+        assert_eq!(
+            list,
+            vec![
+                ("", "intro"),
+                ("Install > Linux", "run `make`\n\n```sh\n# not a heading\n\nmake install\n```"),
+                ("Install > Windows", "use the installer"),
+                ("Usage", "run it"),
+            ]
+        );
+        assert_eq!(chunks[1].id, "README.md#1");
+        assert_eq!(chunks[1].location(), "README.md > Install > Linux");
+    }
+
+    #[test]
+    fn test_chunk_code() {
+        let text = "use std::io;\n\n/// add\nfn add(a: i32) -> i32 {\n    a + 1\n\n    // end\n}\n\nstruct A;";
+        let doc = Document::new("lib.rs", DocumentKind::Code("rs".into()), text);
+        let chunks = Chunker::default().split(&doc);
+        let list = chunks.iter().map(|x| x.section.as_str()).collect::<Vec<_>>();
+        assert_eq!(list, vec!["use std::io;", "fn add(a: i32) -> i32", "struct A;"]);
+        assert!(chunks[1].text.ends_with("// end\n}"));
+    }
+
+    #[test]
+    fn test_chunk_overlap() {
+        let text = format!("{}\n\n{}\n\n{}", "a".repeat(30), "b".repeat(30), "c".repeat(50));
+        let doc = Document::new("a.txt", DocumentKind::Text, text);
+        let chunks = Chunker::new(40, 5).split(&doc);
+        let list = chunks.iter().map(|x| x.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(list[0], "a".repeat(30));
+        assert_eq!(list[1], format!("{}\n\n{}", "a".repeat(5), "b".repeat(30)));
+        //超长段落按字符切开，同样带重叠
+        assert_eq!(list[2], "c".repeat(40));
+        assert_eq!(list[3], "c".repeat(15));
+        assert_eq!(list.len(), 4);
+    }
+}
@@ -0,0 +1,134 @@
+use std::path::{Path, PathBuf};
+use wd_tools::PFErr;
+
+const CODE_EXTENSIONS: &[&str] = &[
+    "rs", "go", "py", "js", "jsx", "ts", "tsx", "java", "kt", "c", "h", "cc", "cpp", "hpp", "cs",
+    "swift", "rb", "php", "lua", "sh", "sql", "proto", "toml", "yaml", "yml", "json",
+];
+/// 扫描目录时跳过的目录，隐藏目录也会跳过
+const SKIP_DIRS: &[&str] = &["target", "node_modules", "dist", "build", "vendor"];
+/// 超过该大小的文本文件不导入，大多是生成的文件或数据
+const MAX_TEXT_FILE_SIZE: u64 = 2 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DocumentKind {
+    Markdown,
+    Text,
+    /// 扩展名作为语言
+    Code(String),
+    Pdf,
+}
+
+impl DocumentKind {
+    /// 按扩展名识别，不支持的文件返回None
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "md" | "markdown" => Some(DocumentKind::Markdown),
+            "txt" | "text" | "log" => Some(DocumentKind::Text),
+            "pdf" => Some(DocumentKind::Pdf),
+            _ if CODE_EXTENSIONS.contains(&ext.as_str()) => Some(DocumentKind::Code(ext)),
+            _ => None,
+        }
+    }
+}
+
+/// 导入知识库的原始文档，source一般为文件路径
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub source: String,
+    pub kind: DocumentKind,
+    pub text: String,
+}
+
+impl Document {
+    pub fn new<S: Into<String>, T: Into<String>>(source: S, kind: DocumentKind, text: T) -> Self {
+        Self {
+            source: source.into(),
+            kind,
+            text: text.into(),
+        }
+    }
+    /// 读取文件并提取文本，pdf需要开启pdf特性
+    pub async fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let Some(kind) = DocumentKind::from_path(path) else {
+            return anyhow::anyhow!("unsupported file[{}]", path.display()).err();
+        };
+        let source = path.display().to_string();
+        if kind != DocumentKind::Pdf && tokio::fs::metadata(path).await?.len() > MAX_TEXT_FILE_SIZE {
+            return anyhow::anyhow!("file[{source}] is too large").err();
+        }
+        let body = tokio::fs::read(path).await?;
+        let text = match kind {
+            DocumentKind::Pdf => Self::pdf_text(body).await?,
+            _ => String::from_utf8(body).map_err(|_| anyhow::anyhow!("file[{source}] is not utf-8"))?,
+        };
+        Ok(Self { source, kind, text })
+    }
+    #[cfg(feature = "pdf")]
+    async fn pdf_text(body: Vec<u8>) -> anyhow::Result<String> {
+        let text = tokio::task::spawn_blocking(move || pdf_extract::extract_text_from_mem(body.as_slice()))
+            .await??;
+        Ok(text)
+    }
+    #[cfg(not(feature = "pdf"))]
+    async fn pdf_text(_body: Vec<u8>) -> anyhow::Result<String> {
+        anyhow::anyhow!("pdf support is disabled, enable the pdf feature").err()
+    }
+}
+
+/// 递归列出目录下所有支持的文件，按路径排序
+pub async fn scan_dir<P: AsRef<Path>>(dir: P) -> anyhow::Result<Vec<PathBuf>> {
+    let mut list = vec![];
+    let mut dirs = vec![dir.as_ref().to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                continue;
+            }
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                if !SKIP_DIRS.contains(&name.as_str()) {
+                    dirs.push(path);
+                }
+            } else if file_type.is_file() && DocumentKind::from_path(&path).is_some() {
+                list.push(path);
+            }
+        }
+    }
+    list.sort();
+    Ok(list)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::knowledge::{scan_dir, Document, DocumentKind};
+
+    #[tokio::test]
+    async fn test_load_documents() {
+        assert_eq!(DocumentKind::from_path("a/b.MD"), Some(DocumentKind::Markdown));
+        assert_eq!(DocumentKind::from_path("main.rs"), Some(DocumentKind::Code("rs".into())));
+        assert_eq!(DocumentKind::from_path("a.png"), None);
+
+        let dir = std::env::temp_dir().join(wd_tools::uuid::v4());
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::write(dir.join("README.md"), "# title").unwrap();
+        std::fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.join("src/logo.png"), "png").unwrap();
+        std::fs::write(dir.join("target/gen.rs"), "").unwrap();
+        std::fs::write(dir.join(".git/config.toml"), "").unwrap();
+
+        let list = scan_dir(&dir).await.unwrap();
+        assert_eq!(list, vec![dir.join("README.md"), dir.join("src/main.rs")]);
+        let doc = Document::load(&list[1]).await.unwrap();
+        assert_eq!(doc.text, "fn main() {}");
+        assert!(Document::load(dir.join("src/logo.png")).await.is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
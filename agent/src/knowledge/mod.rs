@@ -0,0 +1,335 @@
+mod bm25;
+mod chunk;
+mod loader;
+
+pub use bm25::*;
+pub use chunk::*;
+pub use loader::*;
+
+use crate::model::embedding::EmbeddingModel;
+use crate::model::Message;
+use crate::store::{read_index, write_index, HnswIndex, VectorIndex, VectorRecord};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use wd_tools::sync::Am;
+
+const KNOWLEDGE_FILE: &str = "knowledge.json";
+const VECTOR_FILE: &str = "vector.json";
+/// 倒数排名融合的平滑常数
+const RRF_K: f32 = 60.0;
+const KNOWLEDGE_HEAD: &str = "回答时优先参考以下资料。引用资料时在句末用[编号]标注，\
+并在回答末尾按“[编号] 来源”列出引用过的来源；资料与问题无关时忽略它们，不要编造来源。";
+
+/// 检索到的片段，score只用于排序，不同检索器之间不可比较
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnowledgeHit {
+    pub chunk: Chunk,
+    pub score: f32,
+}
+
+/// 为agent提供资料的检索器
+#[async_trait::async_trait]
+pub trait Retriever: Send + Sync {
+    /// 按相关度从高到低返回最多k条
+    async fn retrieve(&self, query: &str, k: usize) -> anyhow::Result<Vec<KnowledgeHit>>;
+}
+
+#[async_trait::async_trait]
+impl<T: Retriever + ?Sized> Retriever for Arc<T> {
+    async fn retrieve(&self, query: &str, k: usize) -> anyhow::Result<Vec<KnowledgeHit>> {
+        self.as_ref().retrieve(query, k).await
+    }
+}
+
+/// 把检索结果组装成一条SYSTEM消息，要求模型按编号引用来源，没有结果时返回None
+pub fn knowledge_message(hits: &[KnowledgeHit]) -> Option<Message> {
+    if hits.is_empty() {
+        return None;
+    }
+    let mut content = KNOWLEDGE_HEAD.to_string();
+    for (i, hit) in hits.iter().enumerate() {
+        content.push_str(format!("\n\n[{}] 来源：{}\n{}", i + 1, hit.chunk.location(), hit.chunk.text).as_str());
+    }
+    Some(Message::new_system(content))
+}
+
+/// 一次导入的结果
+#[derive(Debug, Default, Clone)]
+pub struct IngestReport {
+    pub files: usize,
+    pub chunks: usize,
+    /// 导入失败的文件和原因
+    pub failed: Vec<(String, String)>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct KnowledgeData {
+    chunks: HashMap<String, Chunk>,
+    bm25: Bm25Index,
+}
+
+struct KnowledgeInner {
+    data: KnowledgeData,
+    vector: Box<dyn VectorIndex>,
+}
+
+/// 本地知识库：文档切分后同时建立BM25和向量索引，检索时用倒数排名融合两路结果
+///
+/// 没有设置向量模型时只使用BM25
+pub struct KnowledgeBase {
+    pub embedding: Option<Arc<dyn EmbeddingModel>>,
+    pub chunker: Chunker,
+    /// 向量相似度低于该值的片段不参与融合
+    pub min_vector_score: f32,
+    /// 设置后每次导入或删除都会持久化到该目录
+    pub dir: Option<PathBuf>,
+    inner: Am<KnowledgeInner>,
+}
+
+impl Default for KnowledgeBase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KnowledgeBase {
+    pub fn new() -> Self {
+        Self {
+            embedding: None,
+            chunker: Chunker::default(),
+            min_vector_score: 0.3,
+            dir: None,
+            inner: Am::new(KnowledgeInner {
+                data: KnowledgeData::default(),
+                vector: Box::new(HnswIndex::default()),
+            }),
+        }
+    }
+    /// 从目录恢复，目录不存在时返回空的知识库；向量索引固定为HNSW
+    pub fn open<P: AsRef<Path>>(dir: P) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        let data = read_index::<KnowledgeData>(dir.join(KNOWLEDGE_FILE).as_path())?.unwrap_or_default();
+        let vector = HnswIndex::open(dir.join(VECTOR_FILE))?;
+        let mut kb = Self::new();
+        kb.dir = Some(dir.to_path_buf());
+        kb.inner = Am::new(KnowledgeInner {
+            data,
+            vector: Box::new(vector),
+        });
+        Ok(kb)
+    }
+    pub fn set_embedding<E: EmbeddingModel + 'static>(mut self, embedding: E) -> Self {
+        self.embedding = Some(Arc::new(embedding));
+        self
+    }
+    pub fn set_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+    pub fn set_chunker(mut self, chunker: Chunker) -> Self {
+        self.chunker = chunker;
+        self
+    }
+    pub fn set_min_vector_score(mut self, score: f32) -> Self {
+        self.min_vector_score = score;
+        self
+    }
+    /// 替换向量索引，已有的向量不会迁移，需要在导入之前设置
+    pub fn set_vector_index<I: VectorIndex + 'static>(self, index: I) -> Self {
+        self.inner.synchronize().vector = Box::new(index);
+        self
+    }
+    pub async fn len(&self) -> usize {
+        self.inner.lock().await.data.chunks.len()
+    }
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+    /// 已导入的来源，按名称排序
+    pub async fn sources(&self) -> Vec<String> {
+        let lock = self.inner.lock().await;
+        let mut list = lock.data.chunks.values().map(|x| x.source.clone()).collect::<Vec<_>>();
+        list.sort();
+        list.dedup();
+        list
+    }
+    /// 导入一个文档，同一来源已存在时替换，返回片段数
+    pub async fn add_document(&self, doc: Document) -> anyhow::Result<usize> {
+        let n = self.add_document_unsaved(doc).await?;
+        self.save_locked(&*self.inner.lock().await)?;
+        Ok(n)
+    }
+    //只更新内存中的索引，由调用方决定何时持久化
+    async fn add_document_unsaved(&self, doc: Document) -> anyhow::Result<usize> {
+        let chunks = self.chunker.split(&doc);
+        let vectors = match self.embedding {
+            Some(ref e) if !chunks.is_empty() => {
+                let texts = chunks.iter().map(|x| x.index_text()).collect::<Vec<_>>();
+                e.embed(texts.as_slice()).await?
+            }
+            _ => vec![],
+        };
+        let mut lock = self.inner.lock().await;
+        Self::remove_source_locked(&mut lock, doc.source.as_str());
+        for (i, chunk) in chunks.iter().enumerate() {
+            if let Some(v) = vectors.get(i) {
+                let record = VectorRecord::new(chunk.id.as_str(), "", v.clone())
+                    .set_metadata("source", chunk.source.as_str());
+                lock.vector.insert(record)?;
+            }
+            lock.data.bm25.insert(chunk.id.as_str(), chunk.index_text().as_str());
+            lock.data.chunks.insert(chunk.id.clone(), chunk.clone());
+        }
+        Ok(chunks.len())
+    }
+    pub async fn add_file<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<usize> {
+        let doc = Document::load(path).await?;
+        self.add_document(doc).await
+    }
+    /// 递归导入目录下所有支持的文件，单个文件失败不影响其他文件；全部导入后只持久化一次
+    pub async fn add_dir<P: AsRef<Path>>(&self, dir: P) -> anyhow::Result<IngestReport> {
+        let mut report = IngestReport::default();
+        for path in scan_dir(dir).await? {
+            let result = match Document::load(&path).await {
+                Ok(doc) => self.add_document_unsaved(doc).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(n) => {
+                    report.files += 1;
+                    report.chunks += n;
+                }
+                Err(e) => {
+                    wd_log::log_field("path", path.display())
+                        .field("error", e.to_string())
+                        .warn("KnowledgeBase.add_dir skip a file");
+                    report.failed.push((path.display().to_string(), e.to_string()));
+                }
+            }
+        }
+        if report.files > 0 {
+            self.save_locked(&*self.inner.lock().await)?;
+        }
+        Ok(report)
+    }
+    /// 删除一个来源的所有片段，返回删除的片段数
+    pub async fn remove_source(&self, source: &str) -> anyhow::Result<usize> {
+        let mut lock = self.inner.lock().await;
+        let n = Self::remove_source_locked(&mut lock, source);
+        if n > 0 {
+            self.save_locked(&lock)?;
+        }
+        Ok(n)
+    }
+    fn remove_source_locked(inner: &mut KnowledgeInner, source: &str) -> usize {
+        let ids = inner
+            .data
+            .chunks
+            .values()
+            .filter(|x| x.source == source)
+            .map(|x| x.id.clone())
+            .collect::<Vec<_>>();
+        for id in ids.iter() {
+            inner.data.chunks.remove(id);
+            inner.data.bm25.remove(id);
+            inner.vector.remove(id);
+        }
+        ids.len()
+    }
+    fn save_locked(&self, inner: &KnowledgeInner) -> anyhow::Result<()> {
+        let Some(ref dir) = self.dir else {
+            return Ok(());
+        };
+        write_index(dir.join(KNOWLEDGE_FILE).as_path(), &inner.data)?;
+        inner.vector.save(dir.join(VECTOR_FILE).as_path())
+    }
+}
+
+#[async_trait::async_trait]
+impl Retriever for KnowledgeBase {
+    async fn retrieve(&self, query: &str, k: usize) -> anyhow::Result<Vec<KnowledgeHit>> {
+        if k == 0 || self.is_empty().await {
+            return Ok(vec![]);
+        }
+        let vector = match self.embedding {
+            Some(ref e) => Some(e.embed_one(query).await?),
+            None => None,
+        };
+        let lock = self.inner.lock().await;
+        //每一路多取一些候选，融合后再截断
+        let candidates = k * 4;
+        let mut fused: HashMap<String, f32> = HashMap::new();
+        for (rank, (id, _)) in lock.data.bm25.search(query, candidates).into_iter().enumerate() {
+            *fused.entry(id).or_default() += 1.0 / (RRF_K + rank as f32 + 1.0);
+        }
+        if let Some(v) = vector {
+            let hits = lock.vector.search(&v, candidates);
+            let hits = hits.into_iter().filter(|x| x.score >= self.min_vector_score);
+            for (rank, hit) in hits.enumerate() {
+                *fused.entry(hit.record.id).or_default() += 1.0 / (RRF_K + rank as f32 + 1.0);
+            }
+        }
+        let mut list = fused
+            .into_iter()
+            .filter_map(|(id, score)| {
+                let chunk = lock.data.chunks.get(&id)?.clone();
+                Some(KnowledgeHit { chunk, score })
+            })
+            .collect::<Vec<_>>();
+        list.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.chunk.id.cmp(&b.chunk.id)));
+        list.truncate(k);
+        Ok(list)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::knowledge::{knowledge_message, Document, DocumentKind, KnowledgeBase, Retriever};
+    use crate::model::embedding::HashEmbedding;
+
+    #[tokio::test]
+    async fn test_knowledge_base() {
+        let dir = std::env::temp_dir().join(wd_tools::uuid::v4());
+        let docs = dir.join("docs");
+        std::fs::create_dir_all(&docs).unwrap();
+        std::fs::write(
+            docs.join("deploy.md"),
+            "# Deploy\n\n## Docker\n\nbuild the image with `docker build -t agent .`\n\n## Config\n\nset AGENT_SAVE_DIR to change the save dir",
+        )
+        .unwrap();
+        std::fs::write(docs.join("notes.txt"), "the webui uses eframe to draw windows").unwrap();
+        std::fs::write(docs.join("broken.rs"), [0xff, 0xfe]).unwrap();
+
+        let kb = KnowledgeBase::new()
+            .set_embedding(HashEmbedding::default())
+            .set_dir(dir.join("kb"));
+        let report = kb.add_dir(&docs).await.unwrap();
+        assert_eq!(report.files, 2);
+        assert_eq!(report.chunks, 3);
+        assert_eq!(report.failed.len(), 1);
+        //导入结束后统一持久化
+        assert_eq!(KnowledgeBase::open(dir.join("kb")).unwrap().len().await, 3);
+
+        let hits = kb.retrieve("how to build docker image", 2).await.unwrap();
+        assert_eq!(hits[0].chunk.section, "Deploy > Docker");
+        let msg = knowledge_message(&hits).unwrap();
+        assert!(msg.content.contains("[1] 来源：") && msg.content.contains("deploy.md > Deploy > Docker"));
+
+        //重新导入同一来源时替换旧的片段
+        let source = docs.join("notes.txt").display().to_string();
+        kb.add_document(Document::new(source.as_str(), DocumentKind::Text, "eframe\n\nwindows"))
+            .await
+            .unwrap();
+        assert_eq!(kb.len().await, 3);
+
+        let load = KnowledgeBase::open(dir.join("kb")).unwrap();
+        assert_eq!(load.sources().await.len(), 2);
+        let hits = load.retrieve("AGENT_SAVE_DIR", 1).await.unwrap();
+        assert_eq!(hits[0].chunk.section, "Deploy > Config");
+        assert_eq!(load.remove_source(source.as_str()).await.unwrap(), 1);
+        assert!(load.retrieve("eframe", 3).await.unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -1,7 +1,13 @@
 pub mod agent;
+pub mod knowledge;
 pub mod model;
+pub mod prompt;
+pub mod store;
 pub mod utils;
 
 
 pub use agent::*;
-pub use model::*;
\ No newline at end of file
+pub use knowledge::*;
+pub use model::*;
+pub use prompt::*;
+pub use store::*;
\ No newline at end of file
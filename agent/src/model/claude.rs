@@ -0,0 +1,619 @@
+use crate::model::content::{load_local_media, ContentPart, MediaSource};
+use crate::model::error::ModelError;
+use crate::model::{
+    FinishReason, Message, MessageType, ModelConfig, Response, ResponseEvent, ToolCall, ToolDefine, Usage,
+};
+use crate::utils;
+use crate::utils::SseEvent;
+use reqwest::{Method, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use wd_tools::PFErr;
+
+const ANTHROPIC_API_KEY: &str = "ANTHROPIC_API_KEY";
+const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com";
+const ANTHROPIC_MESSAGES_PATH: &str = "/v1/messages";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Anthropic Messages API
+#[derive(Debug, Clone)]
+pub struct ClaudeModel {
+    pub base_url: String,
+    pub api_key: String,
+}
+impl Default for ClaudeModel {
+    fn default() -> Self {
+        let api_key = std::env::var(ANTHROPIC_API_KEY).unwrap_or("".to_string());
+        Self::new(api_key)
+    }
+}
+
+impl ClaudeModel {
+    pub fn new<S: Into<String>>(key: S) -> Self {
+        let api_key = key.into();
+        Self {
+            base_url: ANTHROPIC_BASE_URL.to_string(),
+            api_key,
+        }
+    }
+    pub fn set_base_url<S: Into<String>>(mut self, url: S) -> Self {
+        self.base_url = url.into();
+        self
+    }
+    /// stop_reason在message_delta中给出，到message_stop时才作为Finish发出
+    pub fn sse_stream_response_process(
+        state: &mut ClaudeStreamState,
+        event: anyhow::Result<SseEvent>,
+    ) -> anyhow::Result<(bool, Vec<ResponseEvent>)> {
+        let event = event?;
+        match event.event.as_str() {
+            "message_start" => {
+                let start = serde_json::from_str::<ClaudeMessageStart>(&event.data)?;
+                state.input_tokens = start.message.usage.input_tokens;
+                Ok((true, vec![]))
+            }
+            "content_block_start" => {
+                let start = serde_json::from_str::<ClaudeContentBlockStart>(&event.data)?;
+                if start.content_block.ty == "tool_use" {
+                    let call = ToolCall {
+                        id: start.content_block.id,
+                        name: start.content_block.name,
+                        arguments: String::new(),
+                    };
+                    state.tool_calls.push((start.index, call));
+                }
+                Ok((true, vec![]))
+            }
+            "content_block_delta" => {
+                //开启extended thinking时，思考过程以thinking_delta返回
+                let ClaudeContentBlockDelta { index, delta } = serde_json::from_str(&event.data)?;
+                //工具的参数以input_json_delta分片返回
+                if !delta.partial_json.is_empty() {
+                    if let Some((_, call)) = state.tool_calls.iter_mut().find(|x| x.0 == index) {
+                        call.arguments.push_str(delta.partial_json.as_str());
+                    }
+                }
+                let mut events = vec![];
+                if !delta.thinking.is_empty() {
+                    events.push(ResponseEvent::Reasoning(delta.thinking));
+                }
+                if !delta.text.is_empty() {
+                    events.push(ResponseEvent::Text(delta.text));
+                }
+                Ok((true, events))
+            }
+            "message_delta" => {
+                let delta = serde_json::from_str::<ClaudeMessageDelta>(&event.data)?;
+                if !delta.delta.stop_reason.is_empty() {
+                    state.stop_reason = FinishReason::from(delta.delta.stop_reason.as_str());
+                }
+                //output_tokens是累计值，输入的用量在message_start中
+                let usage = Usage::new(state.input_tokens, delta.usage.output_tokens);
+                Ok((true, vec![ResponseEvent::Usage(usage)]))
+            }
+            "message_stop" => {
+                let mut events = std::mem::take(&mut state.tool_calls)
+                    .into_iter()
+                    .map(|(_, mut call)| {
+                        //没有参数的工具不会返回input_json_delta
+                        if call.arguments.is_empty() {
+                            call.arguments = "{}".to_string();
+                        }
+                        ResponseEvent::ToolCall(call)
+                    })
+                    .collect::<Vec<_>>();
+                events.push(ResponseEvent::Finish(std::mem::take(&mut state.stop_reason)));
+                Ok((false, events))
+            }
+            "error" => {
+                let err = serde_json::from_str::<ClaudeErrorEvent>(&event.data)
+                    .map_err(|_| ModelError::from_body(event.data.as_str()))?;
+                Err(ModelError::from_code(err.error.ty.as_str(), err.error.message.as_str()).into())
+            }
+            //content_block_stop ping
+            _ => Ok((true, vec![])),
+        }
+    }
+    fn messages_url(&self) -> String {
+        format!(
+            "{}{}",
+            self.base_url.trim_end_matches('/'),
+            ANTHROPIC_MESSAGES_PATH
+        )
+    }
+    fn set_request_header(&self, builder: RequestBuilder) -> RequestBuilder {
+        builder
+            .header("Content-Type", "application/json")
+            .header("x-api-key", self.api_key.as_str())
+            .header("anthropic-version", ANTHROPIC_VERSION)
+    }
+    /// 非流式请求，等待完整的回复
+    pub async fn chat_once(
+        &self,
+        cfg: &ModelConfig,
+        msg: &[Message],
+    ) -> anyhow::Result<(Message, FinishReason)> {
+        let msg = load_local_media(msg).await;
+        let body = ClaudeRequest::from((cfg, msg.as_ref()));
+        let resp: ClaudeResponse = utils::json(
+            Method::POST,
+            self.messages_url().as_str(),
+            |rb| self.set_request_header(rb).body(body.to_string()),
+        )
+        .await?;
+        if let Some(err) = resp.error {
+            return Err(ModelError::from_code(err.ty.as_str(), err.message.as_str()).into());
+        }
+        let mut msg = Message::new_assistant("");
+        for block in resp.content {
+            if block.ty == "tool_use" {
+                msg.tool_calls.push(ToolCall {
+                    id: block.id,
+                    name: block.name,
+                    arguments: block.input.to_string(),
+                });
+                continue;
+            }
+            msg.content.push_str(block.text.as_str());
+            msg.reasoning.push_str(block.thinking.as_str());
+        }
+        let reason = FinishReason::from(resp.stop_reason.as_str());
+        msg.usage = Some(resp.usage.into());
+        Ok((msg, reason))
+    }
+}
+
+#[async_trait::async_trait]
+impl super::Model for ClaudeModel {
+    async fn chat(&self, cfg: &ModelConfig, msg: &[Message]) -> anyhow::Result<Response> {
+        if self.api_key.is_empty() {
+            return anyhow::anyhow!("claude api key is null, please set env[ANTHROPIC_API_KEY]")
+                .err();
+        }
+        if !cfg.stream {
+            let (this, cfg, msg) = (self.clone(), cfg.clone(), msg.to_vec());
+            return Ok(Response::once(async move {
+                this.chat_once(&cfg, msg.as_slice()).await
+            }));
+        }
+        let resp = Response::default();
+        let sender = resp.sender.clone();
+        let msg = load_local_media(msg).await;
+        let body = ClaudeRequest::from((cfg, msg.as_ref()));
+
+        let abort = utils::sse(
+            Method::POST,
+            self.messages_url().as_str(),
+            |rb| self.set_request_header(rb).body(body.to_string()),
+            ClaudeStreamState::default(),
+            move |state, event| {
+                let sender = sender.clone();
+                let result = Self::sse_stream_response_process(state, event);
+                async move { Response::send_all(&sender, result).await }
+            },
+        )
+        .await?;
+
+        Ok(resp.set_abort_handle(abort))
+    }
+}
+
+/// 没有多模态内容时content是字符串，否则是内容块数组
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum ClaudeContent {
+    Text(String),
+    Blocks(Vec<ClaudeBlock>),
+}
+impl Default for ClaudeContent {
+    fn default() -> Self {
+        ClaudeContent::Text(String::new())
+    }
+}
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClaudeBlock {
+    Text { text: String },
+    Image { source: ClaudeSource },
+    Document { source: ClaudeSource },
+    ToolUse { id: String, name: String, input: serde_json::Value },
+    ToolResult { tool_use_id: String, content: String },
+}
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClaudeSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+    File { file_id: String },
+}
+impl ClaudeSource {
+    fn from_media(source: &MediaSource) -> Option<Self> {
+        match source {
+            MediaSource::Base64 { mime_type, data } => Some(ClaudeSource::Base64 {
+                media_type: mime_type.clone(),
+                data: data.clone(),
+            }),
+            MediaSource::Url { url } => Some(ClaudeSource::Url { url: url.clone() }),
+            MediaSource::FileId { id } => Some(ClaudeSource::File { file_id: id.clone() }),
+            MediaSource::Path { .. } => None,
+        }
+    }
+}
+impl From<&ContentPart> for ClaudeBlock {
+    fn from(value: &ContentPart) -> Self {
+        let block = match value {
+            ContentPart::Text { text } => return ClaudeBlock::Text { text: text.clone() },
+            ContentPart::Image { source } => ClaudeSource::from_media(source).map(|source| ClaudeBlock::Image { source }),
+            ContentPart::File { source, .. } => ClaudeSource::from_media(source).map(|source| ClaudeBlock::Document { source }),
+        };
+        block.unwrap_or_else(|| ClaudeBlock::Text {
+            text: value.placeholder(),
+        })
+    }
+}
+impl From<&Message> for ClaudeContent {
+    fn from(value: &Message) -> Self {
+        if let MessageType::TOOL = value.role {
+            return ClaudeContent::Blocks(vec![ClaudeBlock::ToolResult {
+                tool_use_id: value.call_id.clone().unwrap_or_default(),
+                content: value.content.clone(),
+            }]);
+        }
+        if value.parts.is_empty() && value.tool_calls.is_empty() {
+            return ClaudeContent::Text(value.content.clone());
+        }
+        let text = Some(value.content.as_str())
+            .filter(|x| !x.is_empty())
+            .map(|x| ClaudeBlock::Text { text: x.to_string() });
+        let tool_use = value.tool_calls.iter().map(|x| ClaudeBlock::ToolUse {
+            id: x.id.clone(),
+            name: x.name.clone(),
+            input: serde_json::from_str(x.arguments.as_str()).unwrap_or_else(|_| serde_json::json!({})),
+        });
+        ClaudeContent::Blocks(
+            text.into_iter()
+                .chain(value.parts.iter().map(ClaudeBlock::from))
+                .chain(tool_use)
+                .collect(),
+        )
+    }
+}
+#[derive(Debug, Default, Serialize)]
+struct ClaudeMessage {
+    role: String,
+    content: ClaudeContent,
+}
+impl From<&Message> for ClaudeMessage {
+    fn from(value: &Message) -> Self {
+        //messages中只允许user和assistant
+        let role = match value.role {
+            MessageType::Assistant => "assistant",
+            _ => "user",
+        };
+        Self {
+            role: role.to_string(),
+            content: ClaudeContent::from(value),
+        }
+    }
+}
+#[derive(Debug, Default, Serialize)]
+struct ClaudeRequest {
+    model: String,
+    max_tokens: usize,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    system: String,
+    messages: Vec<ClaudeMessage>,
+    stream: bool,
+    /// Claude不允许同时设置temperature和top_p，配置了top_p时不发送temperature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ClaudeTool>,
+}
+#[derive(Debug, Serialize)]
+struct ClaudeTool {
+    name: String,
+    description: String,
+    input_schema: serde_json::Value,
+}
+impl From<&ToolDefine> for ClaudeTool {
+    fn from(value: &ToolDefine) -> Self {
+        Self {
+            name: value.name.clone(),
+            description: value.description.clone(),
+            input_schema: value.parameters.clone(),
+        }
+    }
+}
+impl Display for ClaudeRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = serde_json::to_string(self).unwrap();
+        write!(f, "{s}")
+    }
+}
+impl From<(&ModelConfig, &[Message])> for ClaudeRequest {
+    fn from((cfg, ms): (&ModelConfig, &[Message])) -> Self {
+        let system = ms
+            .iter()
+            .filter(|x| matches!(x.role, MessageType::SYSTEM))
+            .map(|x| x.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        //同一轮的多个工具结果需要放在同一条user消息中
+        let mut messages: Vec<ClaudeMessage> = vec![];
+        let mut last_is_tool = false;
+        for x in ms.iter().filter(|x| !matches!(x.role, MessageType::SYSTEM)) {
+            let is_tool = matches!(x.role, MessageType::TOOL);
+            let msg = ClaudeMessage::from(x);
+            match (messages.last_mut(), msg.content) {
+                (Some(ClaudeMessage { content: ClaudeContent::Blocks(last), .. }), ClaudeContent::Blocks(blocks))
+                    if is_tool && last_is_tool =>
+                {
+                    last.extend(blocks)
+                }
+                (_, content) => messages.push(ClaudeMessage { role: msg.role, content }),
+            }
+            last_is_tool = is_tool;
+        }
+        Self {
+            model: cfg.name.clone(),
+            max_tokens: cfg.max_output_token,
+            system,
+            messages,
+            stream: cfg.stream,
+            temperature: cfg.top_p.is_none().then_some(cfg.temperature),
+            top_p: cfg.top_p,
+            tools: cfg.tools.iter().map(ClaudeTool::from).collect(),
+        }
+    }
+}
+
+/// 流式解析过程中需要跨事件保存的状态
+#[derive(Debug, Default)]
+pub struct ClaudeStreamState {
+    stop_reason: FinishReason,
+    input_tokens: usize,
+    /// (content block的index, 参数拼接中的工具调用)
+    tool_calls: Vec<(usize, ToolCall)>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClaudeResponse {
+    content: Vec<ClaudeContentBlock>,
+    stop_reason: String,
+    usage: ClaudeUsage,
+    error: Option<ClaudeError>,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClaudeUsage {
+    input_tokens: usize,
+    output_tokens: usize,
+}
+impl From<ClaudeUsage> for Usage {
+    fn from(value: ClaudeUsage) -> Self {
+        Usage::new(value.input_tokens, value.output_tokens)
+    }
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClaudeMessageStart {
+    message: ClaudeStartMessage,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClaudeStartMessage {
+    usage: ClaudeUsage,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClaudeContentBlockStart {
+    index: usize,
+    content_block: ClaudeContentBlock,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClaudeContentBlock {
+    #[serde(rename = "type")]
+    ty: String,
+    text: String,
+    thinking: String,
+    //tool_use
+    id: String,
+    name: String,
+    input: serde_json::Value,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClaudeContentBlockDelta {
+    index: usize,
+    delta: ClaudeTextDelta,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClaudeTextDelta {
+    text: String,
+    thinking: String,
+    partial_json: String,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClaudeMessageDelta {
+    delta: ClaudeStopReason,
+    usage: ClaudeUsage,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClaudeStopReason {
+    stop_reason: String,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClaudeErrorEvent {
+    error: ClaudeError,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClaudeError {
+    #[serde(rename = "type")]
+    ty: String,
+    message: String,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::claude::{ClaudeModel, ClaudeRequest};
+    use crate::model::content::ContentPart;
+    use crate::model::{ChatHistory, FinishReason, Message, Model, ModelConfig, ToolCall, ToolDefine, Usage};
+    use crate::utils::mock_http_server;
+
+    const TRANSCRIPT: &str = "event: message_start
+data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_01\",\"type\":\"message\",\"role\":\"assistant\",\"content\":[],\"model\":\"claude-mock\",\"stop_reason\":null,\"usage\":{\"input_tokens\":12,\"output_tokens\":1}}}
+
+event: content_block_start
+data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}
+
+event: ping
+data: {\"type\":\"ping\"}
+
+event: content_block_delta
+data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"hello\"}}
+
+event: content_block_delta
+data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\" world\"}}
+
+event: content_block_stop
+data: {\"type\":\"content_block_stop\",\"index\":0}
+
+event: message_delta
+data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":2}}
+
+event: message_stop
+data: {\"type\":\"message_stop\"}
+
+";
+
+    #[test]
+    fn test_claude_request_system() {
+        let history: Vec<Message> = ChatHistory::system("you are a mock").user("hi").into();
+        let req = ClaudeRequest::from((&ModelConfig::default(), history.as_slice()));
+        let value: serde_json::Value = serde_json::from_str(req.to_string().as_str()).unwrap();
+        assert_eq!(value["system"], "you are a mock");
+        assert_eq!(value["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(value["messages"][0]["role"], "user");
+    }
+
+    const TOOL_TRANSCRIPT: &str = "event: message_start
+data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_02\",\"type\":\"message\",\"role\":\"assistant\",\"content\":[],\"model\":\"claude-mock\",\"stop_reason\":null,\"usage\":{\"input_tokens\":20,\"output_tokens\":1}}}
+
+event: content_block_start
+data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_01\",\"name\":\"weather\",\"input\":{}}}
+
+event: content_block_delta
+data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"city\\\": \"}}
+
+event: content_block_delta
+data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"\\\"paris\\\"}\"}}
+
+event: content_block_stop
+data: {\"type\":\"content_block_stop\",\"index\":0}
+
+event: message_delta
+data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":9}}
+
+event: message_stop
+data: {\"type\":\"message_stop\"}
+
+";
+
+    #[test]
+    fn test_claude_request_tools() {
+        let cfg = ModelConfig::default().append_tool(ToolDefine::new(
+            "weather",
+            "query weather",
+            serde_json::json!({"type": "object"}),
+        ));
+        let call = |id: &str| ToolCall {
+            id: id.into(),
+            name: "weather".into(),
+            arguments: "{\"city\":\"paris\"}".into(),
+        };
+        let msg = [
+            Message::new_user("天气"),
+            Message::new_tool_calls(vec![call("toolu_01"), call("toolu_02")]),
+            Message::new_tool("toolu_01", "sunny"),
+            Message::new_tool("toolu_02", "rain"),
+        ];
+        let req = ClaudeRequest::from((&cfg, msg.as_slice()));
+        let value: serde_json::Value = serde_json::from_str(req.to_string().as_str()).unwrap();
+        assert!(value.get("top_p").is_none());
+        assert!(value.get("temperature").is_some());
+        assert_eq!(value["tools"][0]["input_schema"]["type"], "object");
+        let messages = value["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["content"][0]["type"], "tool_use");
+        assert_eq!(messages[1]["content"][1]["input"]["city"], "paris");
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(messages[2]["content"][1], serde_json::json!({"type": "tool_result", "tool_use_id": "toolu_02", "content": "rain"}));
+
+        //配置了top_p时不能再带temperature
+        let req = ClaudeRequest::from((&cfg.clone().set_top_p(0.9), msg.as_slice()));
+        let value: serde_json::Value = serde_json::from_str(req.to_string().as_str()).unwrap();
+        assert!(value.get("temperature").is_none());
+        assert!((value["top_p"].as_f64().unwrap() - 0.9).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn test_claude_model_tool_use() {
+        let addr = mock_http_server("text/event-stream", TOOL_TRANSCRIPT).await;
+        let cfg = ModelConfig::default().set_name("claude-mock");
+        let (msg, reason) = ClaudeModel::new("mock-key")
+            .set_base_url(format!("http://{addr}"))
+            .chat(&cfg, &[Message::new_user("天气")])
+            .await
+            .expect("chat failed")
+            .collect()
+            .await
+            .expect("stream failed");
+        assert_eq!(reason, FinishReason::ToolCalls);
+        assert_eq!(msg.tool_calls.len(), 1);
+        assert_eq!(msg.tool_calls[0].id, "toolu_01");
+        assert_eq!(msg.tool_calls[0].arguments, "{\"city\": \"paris\"}");
+    }
+
+    #[test]
+    fn test_claude_request_parts() {
+        let msg = [Message::new_user("总结一下")
+            .append_part(ContentPart::image_url("https://example.com/a.png"))
+            .append_part(ContentPart::file_id("a.pdf", "file_01"))];
+        let req = ClaudeRequest::from((&ModelConfig::default(), msg.as_slice()));
+        let value: serde_json::Value = serde_json::from_str(req.to_string().as_str()).unwrap();
+        let blocks = &value["messages"][0]["content"];
+        assert_eq!(blocks[0]["text"], "总结一下");
+        assert_eq!(blocks[1], serde_json::json!({"type": "image", "source": {"type": "url", "url": "https://example.com/a.png"}}));
+        assert_eq!(blocks[2]["type"], "document");
+        assert_eq!(blocks[2]["source"]["file_id"], "file_01");
+    }
+
+    #[tokio::test]
+    async fn test_claude_model() {
+        let addr = mock_http_server("text/event-stream", TRANSCRIPT).await;
+        let cfg = ModelConfig::default().set_name("claude-mock");
+        let history: Vec<_> = ChatHistory::system("you are a mock").user("hi").into();
+
+        let (msg, reason) = ClaudeModel::new("mock-key")
+            .set_base_url(format!("http://{addr}"))
+            .chat(&cfg, history.as_slice())
+            .await
+            .expect("chat failed")
+            .collect()
+            .await
+            .expect("stream failed");
+        assert_eq!(msg.content, "hello world");
+        assert_eq!(reason, FinishReason::Stop);
+        assert_eq!(msg.usage, Some(Usage::new(12, 2)));
+    }
+}
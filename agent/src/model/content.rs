@@ -0,0 +1,219 @@
+use crate::model::Message;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use wd_tools::Base64StdEncode;
+
+/// 图片或文件的来源
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MediaSource {
+    /// 本地文件，发送请求前读取并转为base64，历史记录中只保存路径
+    Path { path: PathBuf },
+    Base64 { mime_type: String, data: String },
+    Url { url: String },
+    /// 已经上传到服务商的文件id
+    FileId { id: String },
+}
+
+impl MediaSource {
+    /// Base64以外的来源按扩展名推断，FileId无法推断
+    pub fn mime_type(&self) -> Option<String> {
+        match self {
+            MediaSource::Path { path } => Some(mime_type(path.as_path()).to_string()),
+            MediaSource::Base64 { mime_type, .. } => Some(mime_type.clone()),
+            MediaSource::Url { url } => {
+                let path = url.split(['?', '#']).next().unwrap_or_default();
+                Some(mime_type(Path::new(path)).to_string())
+            }
+            MediaSource::FileId { .. } => None,
+        }
+    }
+    /// Base64转为data url，Url原样返回，其余返回None
+    pub fn url(&self) -> Option<String> {
+        match self {
+            MediaSource::Base64 { mime_type, data } => Some(format!("data:{mime_type};base64,{data}")),
+            MediaSource::Url { url } => Some(url.clone()),
+            _ => None,
+        }
+    }
+    /// 把本地文件读取为Base64，其他来源不变
+    pub async fn load(self) -> anyhow::Result<Self> {
+        let MediaSource::Path { path } = self else {
+            return Ok(self);
+        };
+        let data = tokio::fs::read(path.as_path())
+            .await
+            .map_err(|e| anyhow::anyhow!("read {} failed: {e}", path.display()))?;
+        Ok(MediaSource::Base64 {
+            mime_type: mime_type(path.as_path()).to_string(),
+            data: data.base64_encode_std(),
+        })
+    }
+}
+
+/// 消息中除content以外的内容片段，按顺序排在content之后
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    Image { source: MediaSource },
+    /// name只用于展示和部分服务商的文件名
+    File { name: String, source: MediaSource },
+}
+
+impl ContentPart {
+    pub fn text<S: Into<String>>(text: S) -> Self {
+        ContentPart::Text { text: text.into() }
+    }
+    pub fn image_path<P: Into<PathBuf>>(path: P) -> Self {
+        ContentPart::Image {
+            source: MediaSource::Path { path: path.into() },
+        }
+    }
+    pub fn image_url<S: Into<String>>(url: S) -> Self {
+        ContentPart::Image {
+            source: MediaSource::Url { url: url.into() },
+        }
+    }
+    pub fn image_base64<M: Into<String>, D: Into<String>>(mime_type: M, data: D) -> Self {
+        ContentPart::Image {
+            source: MediaSource::Base64 {
+                mime_type: mime_type.into(),
+                data: data.into(),
+            },
+        }
+    }
+    pub fn file_path<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let name = path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        ContentPart::File {
+            name,
+            source: MediaSource::Path { path },
+        }
+    }
+    pub fn file_url<N: Into<String>, S: Into<String>>(name: N, url: S) -> Self {
+        ContentPart::File {
+            name: name.into(),
+            source: MediaSource::Url { url: url.into() },
+        }
+    }
+    pub fn file_id<N: Into<String>, S: Into<String>>(name: N, id: S) -> Self {
+        ContentPart::File {
+            name: name.into(),
+            source: MediaSource::FileId { id: id.into() },
+        }
+    }
+    /// 服务商不支持这种片段时用文本代替，避免内容被静默丢弃
+    pub fn placeholder(&self) -> String {
+        match self {
+            ContentPart::Text { text } => text.clone(),
+            ContentPart::Image { source } => match source {
+                MediaSource::Url { url } => format!("[图片: {url}]"),
+                _ => "[图片]".to_string(),
+            },
+            ContentPart::File { name, source } => match source {
+                MediaSource::Url { url } => format!("[文件: {name} {url}]"),
+                _ => format!("[文件: {name}]"),
+            },
+        }
+    }
+    /// 本地文件读取失败(比如附件已被删除)时退化为占位文本，不影响整个请求
+    async fn load(self) -> Self {
+        let result = match self {
+            ContentPart::Image { ref source } => {
+                source.clone().load().await.map(|source| ContentPart::Image { source })
+            }
+            ContentPart::File { ref name, ref source } => source.clone().load().await.map(|source| ContentPart::File {
+                name: name.clone(),
+                source,
+            }),
+            ContentPart::Text { .. } => return self,
+        };
+        result.unwrap_or_else(|e| {
+            wd_log::log_field("error", e.to_string()).warn("ContentPart.load local media failed");
+            ContentPart::text(self.placeholder())
+        })
+    }
+}
+
+/// 按扩展名推断，只覆盖常见的图片和文档
+pub fn mime_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|x| x.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "pdf" => "application/pdf",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        _ => "application/octet-stream",
+    }
+}
+
+/// 发送请求前把本地文件读成base64，没有本地文件时不复制消息
+pub(crate) async fn load_local_media(msg: &[Message]) -> Cow<'_, [Message]> {
+    let has_path = msg.iter().flat_map(|x| x.parts.iter()).any(|x| {
+        matches!(
+            x,
+            ContentPart::Image { source: MediaSource::Path { .. } } | ContentPart::File { source: MediaSource::Path { .. }, .. }
+        )
+    });
+    if !has_path {
+        return Cow::Borrowed(msg);
+    }
+    let mut list = msg.to_vec();
+    for m in list.iter_mut() {
+        let mut parts = Vec::with_capacity(m.parts.len());
+        for part in std::mem::take(&mut m.parts) {
+            parts.push(part.load().await);
+        }
+        m.parts = parts;
+    }
+    Cow::Owned(list)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::content::{load_local_media, ContentPart, MediaSource};
+    use crate::model::Message;
+
+    #[tokio::test]
+    async fn test_load_local_media() {
+        let path = std::env::temp_dir().join(format!("{}.png", wd_tools::uuid::v4()));
+        std::fs::write(&path, b"png").unwrap();
+        let msg = vec![Message::new_user("看图")
+            .append_part(ContentPart::image_path(&path))
+            .append_part(ContentPart::image_url("https://example.com/a.jpg?x=1"))];
+        let list = load_local_media(msg.as_slice()).await;
+        let ContentPart::Image { ref source } = list[0].parts[0] else {
+            panic!("unexpected part: {:?}", list[0].parts[0]);
+        };
+        assert_eq!(source.url().unwrap(), "data:image/png;base64,cG5n");
+        let ContentPart::Image { ref source } = list[0].parts[1] else {
+            panic!("unexpected part: {:?}", list[0].parts[1]);
+        };
+        assert_eq!(source.mime_type().unwrap(), "image/jpeg");
+        let _ = std::fs::remove_file(&path);
+
+        //历史记录中保存的是路径
+        let json = serde_json::to_string(&msg[0]).unwrap();
+        let back = serde_json::from_str::<Message>(json.as_str()).unwrap();
+        assert!(matches!(back.parts[0], ContentPart::Image { source: MediaSource::Path { .. } }));
+        //文件已被删除时用占位文本代替
+        let list = load_local_media(msg.as_slice()).await;
+        assert_eq!(list[0].parts[0], ContentPart::text("[图片]"));
+        assert_eq!(list[0].parts[1], msg[0].parts[1]);
+    }
+}
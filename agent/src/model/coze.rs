@@ -1,16 +1,30 @@
-use crate::model::{Message, MessageType, ModelConfig, Response};
+use crate::model::content::{ContentPart, MediaSource};
+use crate::model::error::ModelError;
+use crate::model::{
+    FinishReason, Message, MessageType, ModelConfig, Response, ResponseEvent, ToolCall, Usage,
+};
 use crate::utils;
-use reqwest::Method;
+use crate::utils::SseEvent;
+use reqwest::{Method, RequestBuilder};
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
-use wd_tools::{AsBytes, PFErr, PFSome};
+use wd_tools::PFErr;
 
 const COZE_ACCESS_TOKEN: &'static str = "COZE_ACCESS_TOKEN";
-const COZE_V3_CHAT_PATH: &'static str = " https://api.coze.cn/v3/chat";
+const COZE_BASE_URL: &str = "https://api.coze.cn";
+const COZE_V3_CHAT_PATH: &str = "/v3/chat";
+const COZE_V3_CHAT_RETRIEVE_PATH: &str = "/v3/chat/retrieve";
+const COZE_V3_CHAT_MESSAGE_LIST_PATH: &str = "/v3/chat/message/list";
+const COZE_V3_CHAT_SUBMIT_TOOL_OUTPUTS_PATH: &str = "/v3/chat/submit_tool_outputs";
+const COZE_CHAT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(800);
+const COZE_CHAT_POLL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
 
 #[derive(Debug,Clone)]
 pub struct CozeModel {
+    pub base_url: String,
     pub api_key: String,
+    /// 非流式请求轮询对话状态的最长等待时间
+    pub poll_timeout: std::time::Duration,
 }
 impl Default for CozeModel {
     fn default() -> Self {
@@ -22,26 +36,140 @@ impl Default for CozeModel {
 impl CozeModel {
     pub fn new<S: Into<String>>(key: S) -> Self {
         let api_key = key.into();
-        Self { api_key }
+        Self {
+            base_url: COZE_BASE_URL.to_string(),
+            api_key,
+            poll_timeout: COZE_CHAT_POLL_TIMEOUT,
+        }
+    }
+    pub fn set_base_url<S: Into<String>>(mut self, url: S) -> Self {
+        self.base_url = url.into();
+        self
+    }
+    pub fn set_poll_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.poll_timeout = timeout;
+        self
     }
-    pub fn sse_stream_response_process(is_delta_msg:&mut bool,line:anyhow::Result<String>)->anyhow::Result<(bool,Option<Message>)>{
-        let content = line?;
-        if content.as_str() == "data:\"[DONE]\"" {
-            return Ok((false,Message::default().some()))
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url.trim_end_matches('/'), path)
+    }
+    /// 消息末尾是工具结果时提交到发起调用的那一次对话，否则创建新的对话，返回 (url, body)
+    fn chat_request(&self, cfg: &ModelConfig, msg: &[Message]) -> anyhow::Result<(String, String)> {
+        match CozeToolOutputs::try_from(msg) {
+            Some((conversation_id, chat_id, mut outputs)) => {
+                outputs.stream = cfg.stream;
+                let url = self.url(COZE_V3_CHAT_SUBMIT_TOOL_OUTPUTS_PATH);
+                Ok((
+                    format!("{url}?conversation_id={conversation_id}&chat_id={chat_id}"),
+                    serde_json::to_string(&outputs)?,
+                ))
+            }
+            None => Ok((self.url(COZE_V3_CHAT_PATH), CozeRequest::from((cfg, msg)).to_string())),
         }
-        if !*is_delta_msg {
-            if content.as_str() == "event:conversation.message.delta" {
-                *is_delta_msg = true;
+    }
+    pub fn sse_stream_response_process(event:anyhow::Result<SseEvent>)->anyhow::Result<(bool,Vec<ResponseEvent>)>{
+        let event = event?;
+        match event.event.as_str() {
+            "conversation.message.delta" => {
+                let delta = serde_json::from_str::<CozeResponseDelta>(&event.data)?;
+                if delta.code != 0 {
+                    return Err(ModelError::from_body(event.data.as_str()).into());
+                }
+                if delta.content.is_empty() {
+                    return Ok((true, vec![]));
+                }
+                Ok((true, vec![ResponseEvent::Text(delta.content)]))
+            }
+            //requires_action之后对话暂停，不会再有done事件
+            "conversation.chat.requires_action" => {
+                let chat = serde_json::from_str::<CozeChat>(&event.data)?;
+                let mut events = chat
+                    .tool_calls()
+                    .into_iter()
+                    .map(ResponseEvent::ToolCall)
+                    .collect::<Vec<_>>();
+                events.push(ResponseEvent::Finish(FinishReason::ToolCalls));
+                Ok((false, events))
             }
-            return Ok((true,None))
+            //用量在completed事件中，之后才是done
+            "conversation.chat.completed" => {
+                let chat = serde_json::from_str::<CozeChat>(&event.data)?;
+                Ok((true, vec![ResponseEvent::Usage(chat.usage.into())]))
+            }
+            "done" => Ok((false, vec![ResponseEvent::Finish(FinishReason::Stop)])),
+            "error" | "conversation.chat.failed" => Err(ModelError::from_body(event.data.as_str()).into()),
+            _ => Ok((true, vec![])),
         }
-        *is_delta_msg = false;
-        let delta = serde_json::from_slice::<CozeResponseDelta>(&content.as_bytes()[5..])?;
-        if delta.code != 0 {
-                return anyhow::anyhow!("{content}").err();
+    }
+    fn set_request_header(&self, rb: RequestBuilder) -> RequestBuilder {
+        rb.header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", self.api_key))
+    }
+    /// 非流式请求：创建对话(或提交工具结果)后轮询状态，完成后拉取answer类型的消息
+    pub async fn chat_once(
+        &self,
+        cfg: &ModelConfig,
+        msg: &[Message],
+    ) -> anyhow::Result<(Message, FinishReason)> {
+        let (url, body) = self.chat_request(cfg, msg)?;
+        let mut chat = utils::json::<CozeResult<CozeChat>>(Method::POST, url.as_str(), |rb| {
+            self.set_request_header(rb).body(body)
+        })
+        .await?
+        .into_data()?;
+        let query = [
+            ("conversation_id", chat.conversation_id.clone()),
+            ("chat_id", chat.id.clone()),
+        ];
+        let deadline = tokio::time::Instant::now() + self.poll_timeout;
+        loop {
+            match chat.status.as_str() {
+                "completed" => break,
+                "requires_action" => {
+                    let msg = Message::new_tool_calls(chat.tool_calls());
+                    return Ok((msg, FinishReason::ToolCalls));
+                }
+                "failed" | "canceled" => {
+                    return anyhow::anyhow!("coze chat[{}] status: {}", chat.id, chat.status).err()
+                }
+                //一直停在created/in_progress时按服务端超时处理，可以重试
+                status if tokio::time::Instant::now() >= deadline => {
+                    return Err(ModelError::Server {
+                        status: 504,
+                        message: format!(
+                            "coze chat[{}] still {status} after {}ms",
+                            chat.id,
+                            self.poll_timeout.as_millis()
+                        ),
+                    }
+                    .into())
+                }
+                _ => {
+                    let wait = deadline.saturating_duration_since(tokio::time::Instant::now());
+                    tokio::time::sleep(COZE_CHAT_POLL_INTERVAL.min(wait)).await
+                }
+            }
+            chat = utils::json::<CozeResult<CozeChat>>(Method::GET, self.url(COZE_V3_CHAT_RETRIEVE_PATH).as_str(), |rb| {
+                self.set_request_header(rb).query(&query)
+            })
+            .await?
+            .into_data()?;
         }
-        Ok((true,Some(delta.into())))
-
+        let list = utils::json::<CozeResult<Vec<CozeResponseDelta>>>(
+            Method::GET,
+            self.url(COZE_V3_CHAT_MESSAGE_LIST_PATH).as_str(),
+            |rb| self.set_request_header(rb).query(&query),
+        )
+        .await?
+        .into_data()?;
+        let content = list
+            .into_iter()
+            .filter(|x| x.ty == "answer")
+            .map(|x| x.content)
+            .collect::<String>();
+        let mut msg = Message::new_assistant(content);
+        msg.usage = Some(chat.usage.into());
+        Ok((msg, FinishReason::Stop))
     }
 }
 
@@ -52,50 +180,30 @@ impl super::Model for CozeModel {
             return anyhow::anyhow!("coze api is null, please set env[COZE_ACCESS_TOKEN]").err();
         }
 
+        if !cfg.stream {
+            let (this, cfg, msg) = (self.clone(), cfg.clone(), msg.to_vec());
+            return Ok(Response::once(async move {
+                this.chat_once(&cfg, msg.as_slice()).await
+            }));
+        }
         let resp = Response::default();
-        let sse = resp.clone();
-        let mut is_delta_msg = false;
-        let body = CozeRequest::from((cfg, msg));
-        let auth_key = format!("Bearer {}", self.api_key);
+        let sender = resp.sender.clone();
+        //工具的结果需要提交到发起调用的那一次对话中
+        let (url, body) = self.chat_request(cfg, msg)?;
 
-        utils::sse(
+        let abort = utils::sse(
             Method::POST,
-            COZE_V3_CHAT_PATH,
-            |rb| {
-                rb.header("Content-Type", "application/json")
-                    .header("Authorization", auth_key)
-                    .body(body.to_string())
-            },is_delta_msg,
-            move |mut is_delta_msg, line| {
-                let sse = sse.sender.clone();
-                let result = Self::sse_stream_response_process(is_delta_msg,line);
-                async move {
-                    let (cont,msg) = match result {
-                        Ok(o) => o,
-                        Err(e) => {
-                            if let Err(err) = sse.send(Err(anyhow::Error::from(e))).await {
-                                wd_log::log_field("error", err).error(
-                                    "CozeModel.stream_handle.send parse delta message error",
-                                );
-                            }
-                            return false
-                        }
-                    };
-                    if let Some(s) = msg {
-                        if let Err(err) = sse.send(Ok(s)).await {
-                            wd_log::log_field("error", err).error(
-                                "CozeModel.stream_handle.send a delta message error",
-                            );
-                            return false
-                        }
-                    }
-                    return cont
-                }
+            url.as_str(),
+            |rb| self.set_request_header(rb).body(body),(),
+            move |_, event| {
+                let sender = sender.clone();
+                let result = Self::sse_stream_response_process(event);
+                async move { Response::send_all(&sender, result).await }
             },
         )
         .await?;
 
-        Ok(resp)
+        Ok(resp.set_abort_handle(abort))
     }
 }
 
@@ -110,10 +218,68 @@ pub struct CozeMessage {
 }
 impl From<&Message> for CozeMessage {
     fn from(value: &Message) -> Self {
+        if value.parts.is_empty() {
+            return CozeMessage {
+                role: value.role.to_string(),
+                content: value.content.to_string(),
+                content_type: "text".to_string(),
+            };
+        }
+        let text = Some(value.content.as_str())
+            .filter(|x| !x.is_empty())
+            .map(|x| CozeObject::Text { text: x.to_string() });
+        let objects = text
+            .into_iter()
+            .chain(value.parts.iter().map(CozeObject::from))
+            .collect::<Vec<_>>();
         CozeMessage {
             role: value.role.to_string(),
-            content: value.content.to_string(),
-            content_type: "text".to_string(),
+            content: serde_json::to_string(&objects).unwrap_or_default(),
+            content_type: "object_string".to_string(),
+        }
+    }
+}
+/// object_string中的一项，图片和文件只能通过file_id或者file_url引用
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CozeObject {
+    Text {
+        text: String,
+    },
+    Image {
+        #[serde(flatten)]
+        file: CozeFileRef,
+    },
+    File {
+        #[serde(flatten)]
+        file: CozeFileRef,
+    },
+}
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CozeFileRef {
+    FileId(String),
+    FileUrl(String),
+}
+impl From<&ContentPart> for CozeObject {
+    fn from(value: &ContentPart) -> Self {
+        let source = match value {
+            ContentPart::Text { text } => return CozeObject::Text { text: text.clone() },
+            ContentPart::Image { source } | ContentPart::File { source, .. } => source,
+        };
+        //本地文件和base64需要先上传到coze，这里不支持
+        let file = match source {
+            MediaSource::FileId { id } => CozeFileRef::FileId(id.clone()),
+            MediaSource::Url { url } => CozeFileRef::FileUrl(url.clone()),
+            _ => {
+                return CozeObject::Text {
+                    text: value.placeholder(),
+                }
+            }
+        };
+        match value {
+            ContentPart::Image { .. } => CozeObject::Image { file },
+            _ => CozeObject::File { file },
         }
     }
 }
@@ -133,7 +299,12 @@ impl Display for CozeRequest {
 }
 impl From<(&ModelConfig, &[Message])> for CozeRequest {
     fn from((cfg, ms): (&ModelConfig, &[Message])) -> Self {
-        let additional_messages = ms.iter().map(|x| CozeMessage::from(x)).collect::<Vec<_>>();
+        //工具调用只在发起它的那次对话中有效，不作为历史消息发送
+        let additional_messages = ms
+            .iter()
+            .filter(|x| !matches!(x.role, MessageType::TOOL) && x.tool_calls.is_empty())
+            .map(CozeMessage::from)
+            .collect::<Vec<_>>();
         let user_id = cfg
             .extend
             .get("user_id")
@@ -142,7 +313,7 @@ impl From<(&ModelConfig, &[Message])> for CozeRequest {
         CozeRequest {
             bot_id: cfg.name.clone(),
             user_id,
-            stream: true,
+            stream: cfg.stream,
             auto_save_history: false,
             additional_messages,
         }
@@ -150,6 +321,126 @@ impl From<(&ModelConfig, &[Message])> for CozeRequest {
 }
 #[derive(Debug, Default, Deserialize)]
 #[serde(default)]
+pub struct CozeResult<T> {
+    code: i32,
+    msg: String,
+    data: Option<T>,
+}
+impl<T> CozeResult<T> {
+    pub fn into_data(self) -> anyhow::Result<T> {
+        if self.code != 0 {
+            return Err(ModelError::from_code(self.code.to_string().as_str(), self.msg.as_str()).into());
+        }
+        self.data
+            .ok_or_else(|| anyhow::anyhow!("coze response data is null"))
+    }
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CozeChat {
+    id: String,
+    conversation_id: String,
+    status: String,
+    required_action: CozeRequiredAction,
+    usage: CozeUsage,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CozeUsage {
+    token_count: usize,
+    output_count: usize,
+    input_count: usize,
+}
+impl From<CozeUsage> for Usage {
+    fn from(value: CozeUsage) -> Self {
+        Self {
+            prompt_tokens: value.input_count,
+            completion_tokens: value.output_count,
+            total_tokens: value.token_count,
+        }
+    }
+}
+impl CozeChat {
+    /// 工具调用的id中带上对话信息，格式为 `conversation_id:chat_id:tool_call_id`
+    pub fn tool_calls(&self) -> Vec<ToolCall> {
+        self.required_action
+            .submit_tool_outputs
+            .tool_calls
+            .iter()
+            .map(|x| ToolCall {
+                id: format!("{}:{}:{}", self.conversation_id, self.id, x.id),
+                name: x.function.name.clone(),
+                arguments: x.function.arguments.clone(),
+            })
+            .collect()
+    }
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CozeRequiredAction {
+    submit_tool_outputs: CozeSubmitToolOutputs,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CozeSubmitToolOutputs {
+    tool_calls: Vec<CozeToolCall>,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CozeToolCall {
+    id: String,
+    function: CozeFunction,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CozeFunction {
+    name: String,
+    arguments: String,
+}
+#[derive(Debug, Default, Serialize)]
+pub struct CozeToolOutput {
+    tool_call_id: String,
+    output: String,
+}
+#[derive(Debug, Default, Serialize)]
+pub struct CozeToolOutputs {
+    tool_outputs: Vec<CozeToolOutput>,
+    stream: bool,
+}
+impl CozeToolOutputs {
+    /// 消息末尾是工具结果时，返回 (conversation_id, chat_id, 待提交的结果)
+    pub fn try_from(ms: &[Message]) -> Option<(String, String, Self)> {
+        let mut ids = None;
+        let mut tool_outputs = vec![];
+        for i in ms.iter().rev() {
+            if !matches!(i.role, MessageType::TOOL) {
+                break;
+            }
+            let mut split = i.call_id.as_deref()?.splitn(3, ':');
+            let (conversation_id, chat_id, tool_call_id) =
+                (split.next()?, split.next()?, split.next()?);
+            ids = Some((conversation_id.to_string(), chat_id.to_string()));
+            tool_outputs.insert(
+                0,
+                CozeToolOutput {
+                    tool_call_id: tool_call_id.to_string(),
+                    output: i.content.clone(),
+                },
+            );
+        }
+        let (conversation_id, chat_id) = ids?;
+        Some((
+            conversation_id,
+            chat_id,
+            Self {
+                tool_outputs,
+                stream: true,
+            },
+        ))
+    }
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
 pub struct CozeResponseDelta {
     #[serde(default="Default::default")]
     code: i32,
@@ -166,16 +457,120 @@ impl Into<Message> for CozeResponseDelta {
         Message {
             role: MessageType::from(self.role.as_str()),
             content: self.content,
+            parts: vec![],
             call_id: None,
+            tool_calls: vec![],
+            usage: None,
+            reasoning: String::new(),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::model::coze::CozeModel;
-    use crate::model::{ChatHistory, Model, ModelConfig};
-    use std::collections::VecDeque;
+    use crate::model::content::ContentPart;
+    use crate::agent::{Agent, FnTool, SingleAgent};
+    use crate::model::coze::{CozeMessage, CozeModel};
+    use crate::model::error::ModelError;
+    use crate::model::{ChatHistory, FinishReason, Message, Model, ModelConfig, ResponseEvent, ToolDefine, Usage};
+    use crate::utils::mock_http_routes;
+    use crate::utils::SseEvent;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    #[test]
+    fn test_coze_object_string() {
+        let msg = CozeMessage::from(&Message::new_user("hi"));
+        assert_eq!(msg.content_type, "text");
+        let msg = Message::new_user("这是什么")
+            .append_part(ContentPart::image_url("https://example.com/a.png"))
+            .append_part(ContentPart::file_id("a.pdf", "7400"))
+            .append_part(ContentPart::image_path("a.png"));
+        let msg = CozeMessage::from(&msg);
+        assert_eq!(msg.content_type, "object_string");
+        let value: serde_json::Value = serde_json::from_str(msg.content.as_str()).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!([
+                {"type": "text", "text": "这是什么"},
+                {"type": "image", "file_url": "https://example.com/a.png"},
+                {"type": "file", "file_id": "7400"},
+                {"type": "text", "text": "[图片]"},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_coze_usage() {
+        let event = SseEvent {
+            event: "conversation.chat.completed".into(),
+            data: r#"{"id":"1","conversation_id":"2","status":"completed","usage":{"token_count":30,"output_count":10,"input_count":20}}"#.into(),
+            id: None,
+            retry: None,
+        };
+        let (cont, events) = CozeModel::sse_stream_response_process(Ok(event)).unwrap();
+        assert!(cont);
+        assert!(matches!(events[0], ResponseEvent::Usage(u) if u == Usage::new(20, 10)));
+    }
+
+    #[tokio::test]
+    async fn test_coze_non_stream_tool_round_trip() {
+        let (addr, requests) = mock_http_routes(vec![
+            ("/v3/chat", "application/json", r#"{"code":0,"msg":"","data":{"id":"chat1","conversation_id":"conv1","status":"requires_action","required_action":{"type":"submit_tool_outputs","submit_tool_outputs":{"tool_calls":[{"id":"call1","type":"function","function":{"name":"now","arguments":"{}"}}]}}}}"#),
+            ("/v3/chat/submit_tool_outputs", "application/json", r#"{"code":0,"msg":"","data":{"id":"chat1","conversation_id":"conv1","status":"completed","usage":{"token_count":30,"output_count":10,"input_count":20}}}"#),
+            ("/v3/chat/message/list", "application/json", r#"{"code":0,"msg":"","data":[{"role":"assistant","type":"answer","content":"12:00","content_type":"text"}]}"#),
+        ])
+        .await;
+        let now = FnTool::new(
+            ToolDefine::new("now", "current time", serde_json::json!({"type":"object"})),
+            |_: String| async move { Ok("12:00".to_string()) },
+        );
+        let agent = SingleAgent::new(CozeModel::new("mock-key").set_base_url(format!("http://{addr}")))
+            .set_model_config(|cfg| {
+                cfg.stream = false;
+                cfg.extend.insert("user_id".into(), "teshin".into());
+            })
+            .register_tool(now);
+        let events = agent.chat("what time is it?".into()).await.expect("chat error").collect::<Vec<_>>().await;
+        assert!(matches!(events.last(), Some(ResponseEvent::Finish(FinishReason::Stop))), "{events:?}");
+        let text = events
+            .iter()
+            .filter_map(|x| match x {
+                ResponseEvent::Text(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect::<String>();
+        assert_eq!(text, "12:00");
+
+        let requests = requests.lock().unwrap().clone();
+        let submit = requests
+            .iter()
+            .find(|x| x.starts_with("POST /v3/chat/submit_tool_outputs "))
+            .expect("tool outputs not submitted");
+        let body: serde_json::Value = serde_json::from_str(submit.splitn(3, ' ').nth(2).unwrap()).unwrap();
+        assert_eq!(body["stream"], false);
+        assert_eq!(body["tool_outputs"][0], serde_json::json!({"tool_call_id": "call1", "output": "12:00"}));
+        assert_eq!(requests.iter().filter(|x| x.starts_with("POST /v3/chat ")).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_coze_non_stream_poll_timeout() {
+        let (addr, requests) = mock_http_routes(vec![
+            ("/v3/chat", "application/json", r#"{"code":0,"msg":"","data":{"id":"chat1","conversation_id":"conv1","status":"created"}}"#),
+            ("/v3/chat/retrieve", "application/json", r#"{"code":0,"msg":"","data":{"id":"chat1","conversation_id":"conv1","status":"in_progress"}}"#),
+        ])
+        .await;
+        let cfg = ModelConfig::default().set_stream_mode(false).append_extend("user_id", "teshin");
+        let err = CozeModel::new("mock-key")
+            .set_base_url(format!("http://{addr}"))
+            .set_poll_timeout(Duration::from_millis(50))
+            .chat_once(&cfg, [Message::new_user("hi")].as_slice())
+            .await
+            .expect_err("should time out");
+        let kind = ModelError::classify(&err).map(|x| x.kind());
+        assert_eq!(kind, Some("server"), "{err}");
+        assert!(requests.lock().unwrap().iter().any(|x| x.starts_with("GET /v3/chat/retrieve ")));
+    }
 
     #[tokio::test]
     async fn test_coze_model() {
@@ -191,13 +586,8 @@ mod test {
             .await
             .expect("chat failed");
 
-        while let res = resp.next().await {
-            println!("-=-=>{:?}", res);
-            if let Ok(o)=res{
-                if o.is_over() {
-                    break
-                }
-            }
+        while let Some(event) = resp.next().await {
+            println!("-=-=>{:?}", event);
         }
         println!("---> success <---")
     }
@@ -1,37 +1,271 @@
+use crate::model::claude::ClaudeModel;
 use crate::model::coze::CozeModel;
-use crate::model::{Message, Model, ModelConfig, Response};
-use std::collections::HashMap;
-use wd_tools::PFBox;
+use crate::model::gemini::GeminiModel;
+use crate::model::ollama::OllamaModel;
+use crate::model::openai::OpenAICompatModel;
 use crate::model::qwen::QwenModel;
+use crate::model::Model;
+use crate::utils::{invalid, load_config};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::Arc;
+use wd_tools::PFErr;
 
 pub const GLOBAL_MODEL_COZE: &'static str = "GLOBAL_MODEL_COZE";
 pub const GLOBAL_MODEL_QWEN: &'static str = "GLOBAL_MODEL_QWEN";
+pub const GLOBAL_MODEL_OLLAMA: &str = "GLOBAL_MODEL_OLLAMA";
+pub const GLOBAL_MODEL_OPENAI: &str = "GLOBAL_MODEL_OPENAI";
+pub const GLOBAL_MODEL_CLAUDE: &str = "GLOBAL_MODEL_CLAUDE";
+pub const GLOBAL_MODEL_GEMINI: &str = "GLOBAL_MODEL_GEMINI";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelProvider {
+    Openai,
+    Qwen,
+    Claude,
+    Gemini,
+    Ollama,
+    Coze,
+}
+impl Display for ModelProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ModelProvider::Openai => "openai",
+            ModelProvider::Qwen => "qwen",
+            ModelProvider::Claude => "claude",
+            ModelProvider::Gemini => "gemini",
+            ModelProvider::Ollama => "ollama",
+            ModelProvider::Coze => "coze",
+        };
+        write!(f, "{s}")
+    }
+}
 
+/// 读取保存密钥的环境变量，没有配置时返回None，由各provider读取自己默认的环境变量
+pub(crate) fn read_key_env(key: &str, env: &Option<String>) -> anyhow::Result<Option<String>> {
+    let Some(env) = env else {
+        return Ok(None);
+    };
+    match std::env::var(env) {
+        Ok(s) if !s.is_empty() => Ok(Some(s)),
+        _ => Err(invalid(key, format!("env[{env}] is not set"))),
+    }
+}
+
+/// 一个模型实例的连接信息，密钥只能通过环境变量引用，不写在配置里
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderDefine {
+    pub provider: ModelProvider,
+    pub base_url: Option<String>,
+    /// 保存密钥的环境变量名，为空时使用provider默认的环境变量
+    pub api_key_env: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+impl ProviderDefine {
+    pub fn new(provider: ModelProvider) -> Self {
+        Self {
+            provider,
+            base_url: None,
+            api_key_env: None,
+            headers: HashMap::new(),
+        }
+    }
+    pub fn set_base_url<S: Into<String>>(mut self, url: S) -> Self {
+        self.base_url = Some(url.into());
+        self
+    }
+    pub fn set_api_key_env<S: Into<String>>(mut self, env: S) -> Self {
+        self.api_key_env = Some(env.into());
+        self
+    }
+    pub fn set_header<K: Into<String>, V: Into<String>>(mut self, k: K, v: V) -> Self {
+        self.headers.insert(k.into(), v.into());
+        self
+    }
+    /// key为该定义在配置中的位置，用于错误提示
+    pub fn validate(&self, key: &str) -> anyhow::Result<()> {
+        let unsupported = match self.provider {
+            ModelProvider::Openai => None,
+            ModelProvider::Claude | ModelProvider::Gemini => (!self.headers.is_empty()).then_some("headers"),
+            ModelProvider::Qwen | ModelProvider::Coze => {
+                if self.base_url.is_some() {
+                    Some("base_url")
+                } else {
+                    (!self.headers.is_empty()).then_some("headers")
+                }
+            }
+            ModelProvider::Ollama => {
+                if self.api_key_env.is_some() {
+                    Some("api_key_env")
+                } else {
+                    (!self.headers.is_empty()).then_some("headers")
+                }
+            }
+        };
+        if let Some(field) = unsupported {
+            return invalid(format!("{key}.{field}"), format!("not supported by provider[{}]", self.provider)).err();
+        }
+        Ok(())
+    }
+    pub fn build(&self, key: &str) -> anyhow::Result<Arc<dyn Model + Sync>> {
+        self.validate(key)?;
+        let api_key = read_key_env(format!("{key}.api_key_env").as_str(), &self.api_key_env)?;
+        let model: Arc<dyn Model + Sync> = match self.provider {
+            ModelProvider::Openai => {
+                let mut model = OpenAICompatModel::default();
+                if let Some(ref url) = self.base_url {
+                    model.base_url = url.clone();
+                }
+                if let Some(key) = api_key {
+                    model.api_key = key;
+                }
+                model.headers.extend(self.headers.clone());
+                Arc::new(model)
+            }
+            ModelProvider::Qwen => Arc::new(api_key.map(QwenModel::new).unwrap_or_default()),
+            ModelProvider::Claude => {
+                let model = api_key.map(ClaudeModel::new).unwrap_or_default();
+                match self.base_url {
+                    Some(ref url) => Arc::new(model.set_base_url(url)),
+                    None => Arc::new(model),
+                }
+            }
+            ModelProvider::Gemini => {
+                let model = api_key.map(GeminiModel::new).unwrap_or_default();
+                match self.base_url {
+                    Some(ref url) => Arc::new(model.set_base_url(url)),
+                    None => Arc::new(model),
+                }
+            }
+            ModelProvider::Ollama => Arc::new(self.base_url.clone().map(OllamaModel::new).unwrap_or_default()),
+            ModelProvider::Coze => Arc::new(api_key.map(CozeModel::new).unwrap_or_default()),
+        };
+        Ok(model)
+    }
+}
+
+/// 全局的模型注册表，按实例名共享模型，同一个provider可以注册多个使用不同密钥、地址的实例
+///
+/// ```toml
+/// [work-qwen]
+/// provider = "qwen"
+/// api_key_env = "WORK_DASHSCOPE_API_KEY"
+///
+/// [local-llama]
+/// provider = "ollama"
+/// base_url = "http://127.0.0.1:11434"
+/// ```
 #[derive(Default)]
 #[wd_macro::global]
 pub struct GlobalModel {
-    models: HashMap<String, Box<dyn Model+Sync>>,
+    models: HashMap<String, Arc<dyn Model + Sync>>,
 }
 
-// impl Default for GlobalModel{
-//     fn default() -> Self {
-//         let mut this = Self{
-//             models : HashMap::new()
-//         };
-//         this.models.insert(GLOBAL_MODEL_COZE.into(), );
-//         this.models.insert(GLOBAL_MODEL_QWEN.into(), );
-//         this
-//     }
-// }
-
 impl GlobalModel {
-    pub fn default_model(mode_type:&str)->Box<dyn Model+Sync>{
-        match mode_type {
-            GLOBAL_MODEL_COZE=> CozeModel::default().to_box(),
-            GLOBAL_MODEL_QWEN=> QwenModel::default().to_box(),
-            _=>{
-                panic!("unknown mode type")
-            }
+    /// 同名时替换，返回被替换的实例
+    pub fn register<S: Into<String>, M: Model + Sync + 'static>(name: S, model: M) -> Option<Arc<dyn Model + Sync>> {
+        Self::register_shared(name, Arc::new(model))
+    }
+    pub fn register_shared<S: Into<String>>(name: S, model: Arc<dyn Model + Sync>) -> Option<Arc<dyn Model + Sync>> {
+        let name = name.into();
+        Self::lock_ref_mut(|x| x.models.insert(name, model))
+    }
+    pub fn register_define<S: Into<String>>(name: S, define: &ProviderDefine) -> anyhow::Result<()> {
+        let name = name.into();
+        let model = define.build(name.as_str())?;
+        Self::register_shared(name, model);
+        Ok(())
+    }
+    /// 从toml/yaml文件注册实例，表名为实例名；有一个实例出错时都不注册，返回注册的实例名
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<String>> {
+        let path = path.as_ref();
+        let defines = load_config::<HashMap<String, ProviderDefine>, _>(path)?;
+        let mut models = vec![];
+        for (name, define) in defines {
+            let model = define
+                .build(name.as_str())
+                .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+            models.push((name, model));
         }
+        models.sort_by(|a, b| a.0.cmp(&b.0));
+        let names = models.iter().map(|x| x.0.clone()).collect();
+        Self::lock_ref_mut(|x| x.models.extend(models));
+        Ok(names)
+    }
+    /// 先查注册表，再查内置的GLOBAL_MODEL_*
+    pub fn get(name: &str) -> anyhow::Result<Arc<dyn Model + Sync>> {
+        if let Some(model) = Self::lock_ref_mut(|x| x.models.get(name).cloned()) {
+            return Ok(model);
+        }
+        Ok(Self::default_model(name)?.into())
+    }
+    pub fn contains(name: &str) -> bool {
+        Self::lock_ref_mut(|x| x.models.contains_key(name))
     }
-}
\ No newline at end of file
+    pub fn remove(name: &str) -> Option<Arc<dyn Model + Sync>> {
+        Self::lock_ref_mut(|x| x.models.remove(name))
+    }
+    /// 已注册的实例名，按字典序
+    pub fn names() -> Vec<String> {
+        let mut names = Self::lock_ref_mut(|x| x.models.keys().cloned().collect::<Vec<_>>());
+        names.sort();
+        names
+    }
+    /// 内置的实例，密钥读取各provider默认的环境变量
+    pub fn default_model(mode_type: &str) -> anyhow::Result<Box<dyn Model + Sync>> {
+        let model: Box<dyn Model + Sync> = match mode_type {
+            GLOBAL_MODEL_COZE => Box::new(CozeModel::default()),
+            GLOBAL_MODEL_QWEN => Box::new(QwenModel::default()),
+            GLOBAL_MODEL_OLLAMA => Box::new(OllamaModel::default()),
+            GLOBAL_MODEL_OPENAI => Box::new(OpenAICompatModel::default()),
+            GLOBAL_MODEL_CLAUDE => Box::new(ClaudeModel::default()),
+            GLOBAL_MODEL_GEMINI => Box::new(GeminiModel::default()),
+            _ => return anyhow::anyhow!("model[{mode_type}] is not registered").err(),
+        };
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::define::{GlobalModel, ModelProvider, ProviderDefine, GLOBAL_MODEL_QWEN};
+    use crate::model::mock::MockModel;
+    use crate::model::{Model, ModelConfig};
+
+    #[tokio::test]
+    async fn test_global_model() {
+        assert!(GlobalModel::get("test-none").is_err());
+        assert!(GlobalModel::get(GLOBAL_MODEL_QWEN).is_ok());
+
+        GlobalModel::register("test-mock", MockModel::new().reply_text("hello"));
+        let model = GlobalModel::get("test-mock").unwrap();
+        let (msg, _) = model.chat(&ModelConfig::default(), &[]).await.unwrap().collect().await.unwrap();
+        assert_eq!(msg.content, "hello");
+        assert!(GlobalModel::names().contains(&"test-mock".to_string()));
+        assert!(GlobalModel::remove("test-mock").is_some());
+        assert!(!GlobalModel::contains("test-mock"));
+
+        let define = ProviderDefine::new(ModelProvider::Ollama).set_api_key_env("KEY");
+        let err = GlobalModel::register_define("test-ollama", &define).unwrap_err();
+        assert!(err.to_string().starts_with("test-ollama.api_key_env:"));
+
+        let path = std::env::temp_dir().join(format!("{}.toml", wd_tools::uuid::v4()));
+        let source = "[test-local]\nprovider = \"ollama\"\nbase_url = \"127.0.0.1:11434\"\n\n[test-remote]\nprovider = \"openai\"\nbase_url = \"http://127.0.0.1:8000/v1\"\n";
+        std::fs::write(&path, source).unwrap();
+        assert_eq!(GlobalModel::load(&path).unwrap(), vec!["test-local", "test-remote"]);
+        assert!(GlobalModel::contains("test-local"));
+        std::fs::write(&path, "[test-bad]\nprovider = \"gpt\"\n").unwrap();
+        let err = GlobalModel::load(&path).unwrap_err().to_string();
+        assert!(err.contains("test-bad.provider:"), "{err}");
+        //测试用的模型只能通过register注册，不能在配置中选择
+        std::fs::write(&path, "[test-bad]\nprovider = \"mock\"\n").unwrap();
+        assert!(GlobalModel::load(&path).is_err());
+        let _ = std::fs::remove_file(path);
+    }
+}
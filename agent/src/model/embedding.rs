@@ -0,0 +1,292 @@
+use crate::utils;
+use reqwest::RequestBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use wd_tools::PFErr;
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+const OPENAI_API_KEY: &str = "OPENAI_API_KEY";
+const EMBEDDINGS_PATH: &str = "/embeddings";
+const DASHSCOPE_BASE_URL: &str = "https://dashscope.aliyuncs.com/compatible-mode/v1";
+const DASHSCOPE_API_KEY: &str = "DASHSCOPE_API_KEY";
+
+/// 把文本转换为向量
+#[async_trait::async_trait]
+pub trait EmbeddingModel: Send + Sync {
+    /// 返回的向量与texts一一对应
+    async fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>>;
+    async fn embed_one(&self, text: &str) -> anyhow::Result<Vec<f32>> {
+        let mut list = self.embed(&[text.to_string()]).await?;
+        match list.pop() {
+            Some(o) => Ok(o),
+            None => anyhow::anyhow!("embedding model return nothing").err(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: EmbeddingModel + ?Sized> EmbeddingModel for Arc<T> {
+    async fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        self.as_ref().embed(texts).await
+    }
+}
+
+/// 兼容 OpenAI `embeddings` 协议的向量模型
+#[derive(Debug, Clone)]
+pub struct OpenAICompatEmbedding {
+    pub base_url: String,
+    pub api_key: String,
+    pub model: String,
+    /// 部分模型支持指定输出维度，为None时使用模型默认值
+    pub dimensions: Option<usize>,
+    /// 单次请求最多携带的文本数，超出时分批请求
+    pub batch_size: usize,
+    pub headers: HashMap<String, String>,
+}
+impl Default for OpenAICompatEmbedding {
+    fn default() -> Self {
+        let api_key = std::env::var(OPENAI_API_KEY).unwrap_or("".to_string());
+        Self::new(OPENAI_BASE_URL, api_key, "text-embedding-3-small")
+    }
+}
+
+impl OpenAICompatEmbedding {
+    pub fn new<U: Into<String>, S: Into<String>, M: Into<String>>(base_url: U, key: S, model: M) -> Self {
+        Self {
+            base_url: base_url.into(),
+            api_key: key.into(),
+            model: model.into(),
+            dimensions: None,
+            batch_size: 64,
+            headers: HashMap::new(),
+        }
+    }
+    pub fn set_dimensions(mut self, dimensions: usize) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+    pub fn set_batch_size(mut self, size: usize) -> Self {
+        self.batch_size = size.max(1);
+        self
+    }
+    pub fn set_header<K: Into<String>, V: Into<String>>(mut self, k: K, v: V) -> Self {
+        self.headers.insert(k.into(), v.into());
+        self
+    }
+    pub fn embeddings_url(&self) -> String {
+        format!("{}{}", self.base_url.trim_end_matches('/'), EMBEDDINGS_PATH)
+    }
+    fn set_request_header(&self, mut builder: RequestBuilder) -> RequestBuilder {
+        builder = builder.header("Content-Type", "application/json");
+        if !self.api_key.is_empty() {
+            builder = builder.header("Authorization", format!("Bearer {}", self.api_key));
+        }
+        for (k, v) in self.headers.iter() {
+            builder = builder.header(k, v);
+        }
+        builder
+    }
+    async fn embed_batch(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        let req = OpenAIEmbeddingRequest {
+            model: self.model.as_str(),
+            input: texts,
+            dimensions: self.dimensions,
+            encoding_format: "float",
+        };
+        let body = serde_json::to_string(&req)?;
+        let mut resp: OpenAIEmbeddingResponse = utils::json(
+            reqwest::Method::POST,
+            self.embeddings_url().as_str(),
+            |builder| self.set_request_header(builder).body(body),
+        )
+        .await?;
+        if resp.data.len() != texts.len() {
+            return anyhow::anyhow!(
+                "embedding count[{}] not match input count[{}]",
+                resp.data.len(),
+                texts.len()
+            )
+            .err();
+        }
+        resp.data.sort_by_key(|x| x.index);
+        Ok(resp.data.into_iter().map(|x| x.embedding).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl EmbeddingModel for OpenAICompatEmbedding {
+    async fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        let mut list = Vec::with_capacity(texts.len());
+        for chunk in texts.chunks(self.batch_size.max(1)) {
+            list.extend(self.embed_batch(chunk).await?);
+        }
+        Ok(list)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAIEmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dimensions: Option<usize>,
+    encoding_format: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIEmbeddingResponse {
+    data: Vec<OpenAIEmbeddingData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIEmbeddingData {
+    #[serde(default)]
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// 通义向量模型，基于dashscope的openai兼容模式，单次最多10条文本
+#[derive(Debug, Clone)]
+pub struct DashScopeEmbedding {
+    inner: OpenAICompatEmbedding,
+}
+impl Default for DashScopeEmbedding {
+    fn default() -> Self {
+        let api_key = std::env::var(DASHSCOPE_API_KEY).unwrap_or("".to_string());
+        Self::new(api_key)
+    }
+}
+
+impl DashScopeEmbedding {
+    pub fn new<S: Into<String>>(key: S) -> Self {
+        let inner = OpenAICompatEmbedding::new(DASHSCOPE_BASE_URL, key, "text-embedding-v3")
+            .set_batch_size(10);
+        Self { inner }
+    }
+    pub fn set_model<M: Into<String>>(mut self, model: M) -> Self {
+        self.inner.model = model.into();
+        self
+    }
+    pub fn set_dimensions(mut self, dimensions: usize) -> Self {
+        self.inner = self.inner.set_dimensions(dimensions);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl EmbeddingModel for DashScopeEmbedding {
+    async fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        self.inner.embed(texts).await
+    }
+}
+
+/// 本地的确定性向量：把词哈希到固定维度再归一化，不需要网络，用于测试和离线环境
+///
+/// ASCII单词按小写整体计入，其他字符(中文等)逐字计入，词重叠越多相似度越高
+#[derive(Debug, Clone, Copy)]
+pub struct HashEmbedding {
+    pub dimension: usize,
+}
+impl Default for HashEmbedding {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
+
+impl HashEmbedding {
+    pub fn new(dimension: usize) -> Self {
+        Self {
+            dimension: dimension.max(1),
+        }
+    }
+    //FNV-1a，保证不同进程、不同版本之间结果一致
+    fn hash(token: &str) -> u64 {
+        token.bytes().fold(0xcbf29ce484222325u64, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+    fn add(&self, vector: &mut [f32], token: &str) {
+        let h = Self::hash(token);
+        let sign = if h >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[(h % self.dimension as u64) as usize] += sign;
+    }
+    pub fn embed_text(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0f32; self.dimension];
+        let mut word = String::new();
+        for c in text.chars() {
+            if c.is_ascii_alphanumeric() {
+                word.push(c.to_ascii_lowercase());
+                continue;
+            }
+            if !word.is_empty() {
+                self.add(&mut vector, word.as_str());
+                word.clear();
+            }
+            if !c.is_whitespace() && !c.is_ascii_punctuation() {
+                self.add(&mut vector, c.encode_utf8(&mut [0; 4]));
+            }
+        }
+        if !word.is_empty() {
+            self.add(&mut vector, word.as_str());
+        }
+        normalize(&mut vector);
+        vector
+    }
+}
+
+#[async_trait::async_trait]
+impl EmbeddingModel for HashEmbedding {
+    async fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        Ok(texts.iter().map(|x| self.embed_text(x.as_str())).collect())
+    }
+}
+
+/// 原地归一化为单位向量，零向量保持不变
+pub fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// 余弦相似度，任一向量为零向量时返回0
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum::<f32>();
+    let na = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let nb = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if na == 0.0 || nb == 0.0 {
+        return 0.0;
+    }
+    dot / (na * nb)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::embedding::{cosine_similarity, EmbeddingModel, HashEmbedding, OpenAICompatEmbedding};
+    use crate::utils::mock_http_server;
+
+    #[tokio::test]
+    async fn test_hash_embedding() {
+        let model = HashEmbedding::new(64);
+        let a = model.embed_one("I like rust programming").await.unwrap();
+        let b = model.embed_one("rust programming is fun").await.unwrap();
+        let c = model.embed_one("今天天气很好").await.unwrap();
+        assert_eq!(a.len(), 64);
+        assert_eq!(a, model.embed_one("i LIKE rust, programming!").await.unwrap());
+        assert!(cosine_similarity(&a, &b) > cosine_similarity(&a, &c));
+        assert!((cosine_similarity(&a, &a) - 1.0).abs() < 1e-5);
+    }
+
+    #[tokio::test]
+    async fn test_openai_compat_embedding() {
+        let body = r#"{"object":"list","data":[{"object":"embedding","index":1,"embedding":[0.0,1.0]},{"object":"embedding","index":0,"embedding":[1.0,0.0]}],"model":"mock"}"#;
+        let addr = mock_http_server("application/json", body).await;
+        let model = OpenAICompatEmbedding::new(format!("http://{addr}/v1"), "", "mock");
+        let list = model.embed(&["a".to_string(), "b".to_string()]).await.unwrap();
+        assert_eq!(list, vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+        //分批后每批只有一条，与回复的条数不一致
+        let model = model.set_batch_size(1);
+        assert!(model.embed(&["a".to_string(), "b".to_string()]).await.is_err());
+    }
+}
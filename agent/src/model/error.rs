@@ -0,0 +1,288 @@
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// 模型调用失败的分类，可以从anyhow::Error中通过 `ModelError::classify` 取出
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModelError {
+    /// 密钥错误、过期或没有权限
+    Auth(String),
+    /// 触发限流，retry_after为服务端建议的等待时间
+    RateLimit {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// 账户欠费或额度用完，需要充值，重试无法恢复
+    QuotaExceeded(String),
+    /// 请求超出模型的上下文长度
+    ContextLength(String),
+    /// 输入或输出被安全策略拦截
+    ContentFilter(String),
+    /// 连接失败、超时或连接中途断开
+    Network(String),
+    /// 服务端5xx
+    Server { status: u16, message: String },
+    /// 其他服务商返回的错误，code为服务商的错误码
+    Provider { code: String, message: String },
+}
+
+impl Display for ModelError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModelError::Auth(m) => write!(f, "authentication failed: {m}"),
+            ModelError::RateLimit {
+                message,
+                retry_after: Some(d),
+            } => write!(f, "rate limited, retry after {}ms: {message}", d.as_millis()),
+            ModelError::RateLimit { message, .. } => write!(f, "rate limited: {message}"),
+            ModelError::QuotaExceeded(m) => write!(f, "quota exceeded: {m}"),
+            ModelError::ContextLength(m) => write!(f, "context length exceeded: {m}"),
+            ModelError::ContentFilter(m) => write!(f, "content filtered: {m}"),
+            ModelError::Network(m) => write!(f, "network error: {m}"),
+            ModelError::Server { status, message } => write!(f, "server error[{status}]: {message}"),
+            ModelError::Provider { code, message } => write!(f, "provider error[{code}]: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+impl From<reqwest::Error> for ModelError {
+    fn from(value: reqwest::Error) -> Self {
+        match value.status() {
+            Some(status) => Self::from_status(status.as_u16(), None, value.to_string().as_str()),
+            None => ModelError::Network(value.to_string()),
+        }
+    }
+}
+
+impl ModelError {
+    /// 限流、网络和服务端错误通常是暂时的，可以重试
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ModelError::RateLimit { .. } | ModelError::Network(_) | ModelError::Server { .. }
+        )
+    }
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ModelError::RateLimit { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+    /// 分类的名称，供日志和界面使用
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ModelError::Auth(_) => "auth",
+            ModelError::RateLimit { .. } => "rate_limit",
+            ModelError::QuotaExceeded(_) => "quota_exceeded",
+            ModelError::ContextLength(_) => "context_length",
+            ModelError::ContentFilter(_) => "content_filter",
+            ModelError::Network(_) => "network",
+            ModelError::Server { .. } => "server",
+            ModelError::Provider { .. } => "provider",
+        }
+    }
+    /// 取出错误链中的分类，没有分类的错误返回None
+    pub fn classify(err: &anyhow::Error) -> Option<&ModelError> {
+        err.chain().find_map(|x| x.downcast_ref::<ModelError>())
+    }
+    /// 非2xx的http响应，状态码不足以区分时再看body中的错误码和信息
+    pub fn from_status(status: u16, retry_after: Option<Duration>, body: &str) -> Self {
+        let (code, message) = error_body(body);
+        match status {
+            401 | 403 => ModelError::Auth(message),
+            //openai额度用完时也返回429
+            429 if is_quota_exceeded(format!("{code} {message}").as_str()) => ModelError::QuotaExceeded(message),
+            429 => ModelError::RateLimit { message, retry_after },
+            413 => ModelError::ContextLength(message),
+            500..=599 => ModelError::Server { status, message },
+            _ => match Self::from_code(code.as_str(), message.as_str()) {
+                ModelError::Provider { message, .. } if code.is_empty() => ModelError::Provider {
+                    code: status.to_string(),
+                    message,
+                },
+                e => e,
+            },
+        }
+    }
+    /// 流中收到的无法解析的行，多数情况下是服务商返回的错误对象
+    pub fn from_body(body: &str) -> Self {
+        let (code, message) = error_body(body);
+        Self::from_code(code.as_str(), message.as_str())
+    }
+    /// 按各家的错误码和错误信息中的关键字分类
+    pub fn from_code(code: &str, message: &str) -> Self {
+        //gemini等在body中给出http状态码
+        if let Ok(status @ (401 | 403 | 413 | 429 | 500..=599)) = code.parse::<u16>() {
+            return Self::from_status(status, None, message);
+        }
+        let text = format!("{code} {message}").to_lowercase();
+        let has = |list: &[&str]| list.iter().any(|x| text.contains(x));
+        let message = message.to_string();
+        if is_quota_exceeded(text.as_str()) {
+            ModelError::QuotaExceeded(message)
+        } else if has(&[
+            "invalidapikey",
+            "invalid_api_key",
+            "authentication",
+            "unauthorized",
+            "permission",
+            "api key",
+            "accessdenied",
+        ]) || matches!(code, "4100" | "4101")
+        {
+            ModelError::Auth(message)
+        } else if has(&[
+            "throttling",
+            "rate_limit",
+            "rate limit",
+            "ratelimit",
+            "too many requests",
+            "resource_exhausted",
+            "overloaded",
+        ]) || code == "4013"
+        {
+            ModelError::RateLimit {
+                message,
+                retry_after: None,
+            }
+        } else if has(&[
+            "context_length",
+            "context length",
+            "maximum context",
+            "range of input length",
+            "too long",
+            "prompt is too long",
+        ]) {
+            ModelError::ContextLength(message)
+        } else if has(&[
+            "data_inspection_failed",
+            "content_filter",
+            "inappropriate",
+            "safety",
+            "sensitive",
+        ]) {
+            ModelError::ContentFilter(message)
+        } else if has(&["internalerror", "internal_error", "server_error", "service unavailable", "timeout"]) {
+            ModelError::Server { status: 500, message }
+        } else {
+            ModelError::Provider {
+                code: code.to_string(),
+                message,
+            }
+        }
+    }
+}
+
+/// 欠费或额度用完，gemini按分钟的配额超限仍然算限流
+fn is_quota_exceeded(text: &str) -> bool {
+    let text = text.to_lowercase();
+    [
+        "insufficient_quota",
+        "exceeded your current quota",
+        "arrearage",
+        "credit balance",
+        "insufficient balance",
+        "billing",
+        "余额不足",
+        "欠费",
+    ]
+    .iter()
+    .any(|x| text.contains(x))
+}
+
+/// http响应头中的重试等待时间，支持 `retry-after-ms` 和秒数形式的 `retry-after`
+pub fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let get = |k: &str| headers.get(k).and_then(|x| x.to_str().ok()).map(|x| x.trim().to_string());
+    if let Some(ms) = get("retry-after-ms").and_then(|x| x.parse::<f64>().ok()) {
+        return Some(Duration::from_millis(ms.max(0.0) as u64));
+    }
+    let secs = get("retry-after")?.parse::<f64>().ok()?;
+    Some(Duration::from_millis((secs.max(0.0) * 1000.0) as u64))
+}
+
+/// 从各家的错误body中取出错误码和错误信息，不是json时整个body作为信息
+///
+/// - openai/dashscope兼容模式: `{"error":{"code","type","message"}}`
+/// - dashscope原生: `{"code","message"}`
+/// - claude: `{"type":"error","error":{"type","message"}}`
+/// - gemini: `{"error":{"code","status","message"}}`
+/// - coze: `{"code","msg"}` 或 `{"last_error":{"code","msg"}}`
+fn error_body(body: &str) -> (String, String) {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(body.trim()) else {
+        return (String::new(), body.trim().to_string());
+    };
+    //coze的conversation.chat.failed事件中错误在last_error里
+    let obj = match value.get("error").or_else(|| value.get("last_error")) {
+        Some(e) if e.is_object() => e,
+        _ => &value,
+    };
+    let text = |v: Option<&serde_json::Value>| match v {
+        Some(serde_json::Value::String(s)) if !s.is_empty() => Some(s.clone()),
+        Some(serde_json::Value::Number(n)) if n.as_i64() != Some(0) => Some(n.to_string()),
+        _ => None,
+    };
+    let code = ["code", "status", "type"]
+        .iter()
+        .find_map(|k| text(obj.get(k)))
+        .unwrap_or_default();
+    let message = ["message", "msg"]
+        .iter()
+        .find_map(|k| text(obj.get(k)))
+        .unwrap_or_else(|| body.trim().to_string());
+    (code, message)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::error::ModelError;
+    use std::time::Duration;
+
+    #[test]
+    fn test_model_error() {
+        let e = ModelError::from_status(429, Some(Duration::from_secs(2)), r#"{"error":{"message":"slow down"}}"#);
+        assert_eq!(
+            e,
+            ModelError::RateLimit {
+                message: "slow down".into(),
+                retry_after: Some(Duration::from_secs(2))
+            }
+        );
+        assert!(e.is_retryable());
+        assert!(matches!(ModelError::from_status(401, None, "no"), ModelError::Auth(_)));
+        assert!(matches!(ModelError::from_status(503, None, ""), ModelError::Server { status: 503, .. }));
+
+        let qwen = r#"{"error":{"code":"data_inspection_failed","message":"Input data may contain inappropriate content."}}"#;
+        assert_eq!(ModelError::from_status(400, None, qwen).kind(), "content_filter");
+        let qwen = r#"{"error":{"code":"invalid_parameter_error","message":"Range of input length should be [1, 30720]"}}"#;
+        assert_eq!(ModelError::from_body(qwen).kind(), "context_length");
+        let coze = r#"{"code":4100,"msg":"authentication is invalid"}"#;
+        assert_eq!(ModelError::from_body(coze).kind(), "auth");
+        let claude = r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        assert!(ModelError::from_body(claude).is_retryable());
+        let gemini = r#"{"error":{"code":429,"message":"Resource has been exhausted","status":"RESOURCE_EXHAUSTED"}}"#;
+        assert_eq!(ModelError::from_body(gemini).kind(), "rate_limit");
+        let openai = r#"{"error":{"code":"insufficient_quota","message":"You exceeded your current quota, please check your plan and billing details."}}"#;
+        let e = ModelError::from_status(429, Some(Duration::from_secs(1)), openai);
+        assert_eq!(e.kind(), "quota_exceeded");
+        assert!(!e.is_retryable());
+        assert_eq!(e.retry_after(), None);
+        let qwen = r#"{"code":"Arrearage","message":"Access denied, please make sure your account is in good standing."}"#;
+        assert_eq!(ModelError::from_status(400, None, qwen).kind(), "quota_exceeded");
+        let claude = r#"{"type":"error","error":{"type":"invalid_request_error","message":"Your credit balance is too low to access the Anthropic API."}}"#;
+        assert!(!ModelError::from_body(claude).is_retryable());
+        let other = ModelError::from_status(400, None, "bad request");
+        assert_eq!(
+            other,
+            ModelError::Provider {
+                code: "400".into(),
+                message: "bad request".into()
+            }
+        );
+        assert!(!other.is_retryable());
+
+        let err = anyhow::Error::from(ModelError::Network("reset".into())).context("chat failed");
+        assert_eq!(ModelError::classify(&err).map(|x| x.kind()), Some("network"));
+        assert!(ModelError::classify(&anyhow::anyhow!("plain")).is_none());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::model::error::ModelError;
+use crate::model::{Message, Model, ModelConfig, Response, ResponseEvent};
+use std::sync::Arc;
+use wd_tools::PFErr;
+
+/// 组合模型中的一个后端，不同后端的模型名通常不同，name不为空时替换请求中的模型名
+#[derive(Clone)]
+pub struct ModelTarget {
+    pub model: Arc<dyn Model + Sync>,
+    pub name: Option<String>,
+}
+
+impl ModelTarget {
+    pub fn new<M: Model + Sync + 'static>(model: M) -> Self {
+        Self::shared(Arc::new(model))
+    }
+    pub fn shared(model: Arc<dyn Model + Sync>) -> Self {
+        Self { model, name: None }
+    }
+    pub fn set_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+    pub(crate) fn config(&self, cfg: &ModelConfig) -> ModelConfig {
+        let mut cfg = cfg.clone();
+        if let Some(ref name) = self.name {
+            cfg.name = name.clone();
+        }
+        cfg
+    }
+}
+
+/// 按顺序尝试多个模型，前一个在输出任何内容之前返回了分类的错误(ModelError)时换下一个
+///
+/// 未分类的错误和已经开始输出之后的错误不降级，原样返回
+#[derive(Clone, Default)]
+pub struct FallbackModel {
+    pub targets: Vec<ModelTarget>,
+}
+
+impl FallbackModel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn push<M: Model + Sync + 'static>(self, model: M) -> Self {
+        self.push_target(ModelTarget::new(model))
+    }
+    pub fn push_target(mut self, target: ModelTarget) -> Self {
+        self.targets.push(target);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Model for FallbackModel {
+    async fn chat(&self, cfg: &ModelConfig, msg: &[Message]) -> anyhow::Result<Response> {
+        if self.targets.is_empty() {
+            return anyhow::anyhow!("FallbackModel has no model").err();
+        }
+        let resp = Response::default();
+        let sender = resp.sender.clone();
+        let targets = self.targets.clone();
+        let (cfg, msg) = (cfg.clone(), msg.to_vec());
+        let task = tokio::spawn(async move {
+            for (i, target) in targets.iter().enumerate() {
+                let cfg = target.config(&cfg);
+                let result = target.model.chat(&cfg, msg.as_slice()).await;
+                let err = match Response::first_event(result).await {
+                    Ok((stream, first)) => {
+                        let _ = sender.send(ResponseEvent::Model(cfg.name)).await;
+                        return stream.forward(first, &sender).await;
+                    }
+                    Err(e) => e,
+                };
+                if i + 1 == targets.len() || ModelError::classify(&err).is_none() {
+                    let _ = sender.send(ResponseEvent::Error(err)).await;
+                    return;
+                }
+                wd_log::log_field("index", i)
+                    .field("error", err.to_string())
+                    .warn("FallbackModel.chat fall over to next model");
+            }
+        });
+        Ok(resp.set_abort_handle(task.abort_handle()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::error::ModelError;
+    use crate::model::fallback::{FallbackModel, ModelTarget};
+    use crate::model::mock::{MockModel, MockReply};
+    use crate::model::{Model, ModelConfig, ResponseEvent};
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_fallback_model() {
+        let primary = MockModel::new()
+            .reply(MockReply::Fail(ModelError::RateLimit {
+                message: "slow down".into(),
+                retry_after: None,
+            }))
+            .reply(MockReply::Error {
+                chunks: vec![],
+                error: "unknown".into(),
+            });
+        let backup = MockModel::new().reply_text("from backup");
+        let model = FallbackModel::new()
+            .push(primary.clone())
+            .push_target(ModelTarget::new(backup.clone()).set_name("llama3"));
+        let cfg = ModelConfig::default().set_name("qwen-turbo");
+        let (msg, _) = model.chat(&cfg, &[]).await.unwrap().collect().await.unwrap();
+        assert_eq!(msg.content, "from backup");
+        assert_eq!(primary.last_record().unwrap().config.name, "qwen-turbo");
+        assert_eq!(backup.last_record().unwrap().config.name, "llama3");
+
+        //未分类的错误不降级
+        assert!(model.chat(&cfg, &[]).await.unwrap().collect().await.is_err());
+        assert_eq!(backup.records().len(), 1);
+        assert!(FallbackModel::new().chat(&cfg, &[]).await.is_err());
+
+        //降级后先告知实际使用的模型
+        let backup = MockModel::new().reply_text("from backup");
+        let model = FallbackModel::new()
+            .push(MockModel::new().reply(MockReply::Fail(ModelError::Network("reset".into()))))
+            .push_target(ModelTarget::new(backup).set_name("llama3"));
+        let events = StreamExt::collect::<Vec<_>>(model.chat(&cfg, &[]).await.unwrap()).await;
+        assert!(matches!(events.first(), Some(ResponseEvent::Model(name)) if name == "llama3"));
+    }
+}
@@ -0,0 +1,433 @@
+use crate::model::content::{load_local_media, mime_type, ContentPart, MediaSource};
+use crate::model::error::ModelError;
+use crate::model::{FinishReason, Message, MessageType, ModelConfig, Response, ResponseEvent, Usage};
+use crate::utils;
+use crate::utils::SseEvent;
+use reqwest::{Method, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use wd_tools::{PFErr, PFSome};
+
+const GEMINI_API_KEY: &str = "GEMINI_API_KEY";
+const GEMINI_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+/// Google Gemini `streamGenerateContent` 接口
+///
+/// ModelConfig.extend 支持:
+/// - candidateCount: 候选数量，流中只返回第0个候选
+/// - safetySettings: json数组，如 `[{"category":"HARM_CATEGORY_HARASSMENT","threshold":"BLOCK_NONE"}]`
+#[derive(Debug, Clone)]
+pub struct GeminiModel {
+    pub base_url: String,
+    pub api_key: String,
+}
+impl Default for GeminiModel {
+    fn default() -> Self {
+        let api_key = std::env::var(GEMINI_API_KEY).unwrap_or("".to_string());
+        Self::new(api_key)
+    }
+}
+
+impl GeminiModel {
+    pub fn new<S: Into<String>>(key: S) -> Self {
+        let api_key = key.into();
+        Self {
+            base_url: GEMINI_BASE_URL.to_string(),
+            api_key,
+        }
+    }
+    pub fn set_base_url<S: Into<String>>(mut self, url: S) -> Self {
+        self.base_url = url.into();
+        self
+    }
+    pub fn content_url(&self, model: &str, stream: bool) -> String {
+        let method = if stream {
+            "streamGenerateContent?alt=sse"
+        } else {
+            "generateContent"
+        };
+        format!(
+            "{}/models/{}:{}",
+            self.base_url.trim_end_matches('/'),
+            model,
+            method
+        )
+    }
+    fn set_request_header(&self, builder: RequestBuilder) -> RequestBuilder {
+        builder
+            .header("Content-Type", "application/json")
+            .header("x-goog-api-key", self.api_key.as_str())
+    }
+    /// 非流式请求，等待完整的回复
+    pub async fn chat_once(
+        &self,
+        cfg: &ModelConfig,
+        msg: &[Message],
+    ) -> anyhow::Result<(Message, FinishReason)> {
+        let msg = load_local_media(msg).await;
+        let body = GeminiRequest::from((cfg, msg.as_ref()));
+        let resp: GeminiStreamResponse = utils::json(
+            Method::POST,
+            self.content_url(cfg.name.as_str(), false).as_str(),
+            |rb| self.set_request_header(rb).body(body.to_string()),
+        )
+        .await?;
+        if let Some(err) = resp.error {
+            return Err(ModelError::from_code(err.code.to_string().as_str(), err.message.as_str()).into());
+        }
+        let Some(candidate) = resp.candidates.into_iter().find(|x| x.index == 0) else {
+            return Ok((Message::new_assistant(""), FinishReason::Stop));
+        };
+        let reason = FinishReason::from(candidate.finish_reason.as_str());
+        let (content, reasoning) = candidate.content.into_text();
+        let mut msg = Message::new_assistant(content);
+        msg.reasoning = reasoning;
+        msg.usage = resp.usage_metadata.map(Usage::from);
+        Ok((msg, reason))
+    }
+    pub fn sse_stream_response_process(
+        event: anyhow::Result<SseEvent>,
+    ) -> anyhow::Result<(bool, Vec<ResponseEvent>)> {
+        let event = event?;
+        let resp = serde_json::from_str::<GeminiStreamResponse>(&event.data)
+            .map_err(|_| ModelError::from_body(event.data.as_str()))?;
+        if let Some(err) = resp.error {
+            return Err(ModelError::from_code(err.code.to_string().as_str(), err.message.as_str()).into());
+        }
+        let Some(candidate) = resp.candidates.into_iter().find(|x| x.index == 0) else {
+            return Ok((true, vec![]));
+        };
+        let (text, reasoning) = candidate.content.into_text();
+        let mut events = vec![];
+        if !reasoning.is_empty() {
+            events.push(ResponseEvent::Reasoning(reasoning));
+        }
+        if !text.is_empty() {
+            events.push(ResponseEvent::Text(text));
+        }
+        //最后一帧可能同时带有文本，文本在前
+        if candidate.finish_reason.is_empty() {
+            return Ok((true, events));
+        }
+        //每一帧的usageMetadata都是累计值，只取最后一帧
+        events.extend(resp.usage_metadata.map(|x| ResponseEvent::Usage(x.into())));
+        events.push(ResponseEvent::Finish(FinishReason::from(
+            candidate.finish_reason.as_str(),
+        )));
+        Ok((false, events))
+    }
+}
+
+#[async_trait::async_trait]
+impl super::Model for GeminiModel {
+    async fn chat(&self, cfg: &ModelConfig, msg: &[Message]) -> anyhow::Result<Response> {
+        if self.api_key.is_empty() {
+            return anyhow::anyhow!("gemini api key is null, please set env[GEMINI_API_KEY]")
+                .err();
+        }
+        if !cfg.stream {
+            let (this, cfg, msg) = (self.clone(), cfg.clone(), msg.to_vec());
+            return Ok(Response::once(async move {
+                this.chat_once(&cfg, msg.as_slice()).await
+            }));
+        }
+        let resp = Response::default();
+        let sender = resp.sender.clone();
+        let msg = load_local_media(msg).await;
+        let body = GeminiRequest::from((cfg, msg.as_ref()));
+
+        let abort = utils::sse(
+            Method::POST,
+            self.content_url(cfg.name.as_str(), true).as_str(),
+            |rb| self.set_request_header(rb).body(body.to_string()),
+            (),
+            move |_, event| {
+                let sender = sender.clone();
+                let result = Self::sse_stream_response_process(event);
+                async move { Response::send_all(&sender, result).await }
+            },
+        )
+        .await?;
+
+        Ok(resp.set_abort_handle(abort))
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct GeminiPart {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    text: String,
+    /// 开启includeThoughts时，思考过程的part标记为thought
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    thought: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inline_data: Option<GeminiBlob>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_data: Option<GeminiFileData>,
+}
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiBlob {
+    mime_type: String,
+    data: String,
+}
+/// url或者通过files接口上传后得到的uri
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiFileData {
+    mime_type: String,
+    file_uri: String,
+}
+impl GeminiPart {
+    fn text<S: Into<String>>(text: S) -> Self {
+        Self {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+}
+impl From<&ContentPart> for GeminiPart {
+    fn from(value: &ContentPart) -> Self {
+        let (source, name) = match value {
+            ContentPart::Text { text } => return GeminiPart::text(text.as_str()),
+            ContentPart::Image { source } => (source, ""),
+            ContentPart::File { name, source } => (source, name.as_str()),
+        };
+        let mime_type = source
+            .mime_type()
+            .unwrap_or_else(|| mime_type(Path::new(name)).to_string());
+        match source {
+            MediaSource::Base64 { data, .. } => GeminiPart {
+                inline_data: Some(GeminiBlob {
+                    mime_type,
+                    data: data.clone(),
+                }),
+                ..Default::default()
+            },
+            MediaSource::Url { url: uri } | MediaSource::FileId { id: uri } => GeminiPart {
+                file_data: Some(GeminiFileData {
+                    mime_type,
+                    file_uri: uri.clone(),
+                }),
+                ..Default::default()
+            },
+            MediaSource::Path { .. } => GeminiPart::text(value.placeholder()),
+        }
+    }
+}
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct GeminiContent {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    role: String,
+    parts: Vec<GeminiPart>,
+}
+impl From<&Message> for GeminiContent {
+    fn from(value: &Message) -> Self {
+        let role = match value.role {
+            MessageType::Assistant => "model",
+            _ => "user",
+        };
+        Self {
+            role: role.to_string(),
+            parts: Some(value.content.as_str())
+                .filter(|x| !x.is_empty())
+                .map(GeminiPart::text)
+                .into_iter()
+                .chain(value.parts.iter().map(GeminiPart::from))
+                .collect(),
+        }
+    }
+}
+impl GeminiContent {
+    /// 拆分为 (正文, 思考过程)
+    fn into_text(self) -> (String, String) {
+        let (mut text, mut thought) = (String::new(), String::new());
+        for part in self.parts {
+            match part.thought {
+                true => thought.push_str(part.text.as_str()),
+                false => text.push_str(part.text.as_str()),
+            }
+        }
+        (text, thought)
+    }
+}
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct GeminiSafetySetting {
+    category: String,
+    threshold: String,
+}
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiGenerationConfig {
+    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    max_output_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    candidate_count: Option<usize>,
+}
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiRequest {
+    contents: Vec<GeminiContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<GeminiContent>,
+    generation_config: GeminiGenerationConfig,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    safety_settings: Vec<GeminiSafetySetting>,
+}
+impl Display for GeminiRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = serde_json::to_string(self).unwrap();
+        write!(f, "{s}")
+    }
+}
+impl From<(&ModelConfig, &[Message])> for GeminiRequest {
+    fn from((cfg, ms): (&ModelConfig, &[Message])) -> Self {
+        let system = ms
+            .iter()
+            .filter(|x| matches!(x.role, MessageType::SYSTEM))
+            .map(|x| GeminiPart::text(x.content.as_str()))
+            .collect::<Vec<_>>();
+        let system_instruction = if system.is_empty() {
+            None
+        } else {
+            GeminiContent {
+                role: String::new(),
+                parts: system,
+            }
+            .some()
+        };
+        let contents = ms
+            .iter()
+            .filter(|x| !matches!(x.role, MessageType::SYSTEM))
+            .map(GeminiContent::from)
+            .collect::<Vec<_>>();
+        let candidate_count = cfg
+            .extend
+            .get("candidateCount")
+            .and_then(|x| x.parse::<usize>().ok());
+        let safety_settings = match cfg.extend.get("safetySettings") {
+            None => vec![],
+            Some(s) => serde_json::from_str::<Vec<GeminiSafetySetting>>(s).unwrap_or_else(|e| {
+                wd_log::log_field("error", e)
+                    .warn("GeminiRequest.from parse extend[safetySettings] failed");
+                vec![]
+            }),
+        };
+        Self {
+            contents,
+            system_instruction,
+            generation_config: GeminiGenerationConfig {
+                temperature: cfg.temperature,
+                top_p: cfg.top_p,
+                max_output_tokens: cfg.max_output_token,
+                candidate_count,
+            },
+            safety_settings,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+struct GeminiStreamResponse {
+    candidates: Vec<GeminiCandidate>,
+    usage_metadata: Option<GeminiUsage>,
+    error: Option<GeminiError>,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct GeminiUsage {
+    prompt_token_count: usize,
+    candidates_token_count: usize,
+    total_token_count: usize,
+}
+impl From<GeminiUsage> for Usage {
+    fn from(value: GeminiUsage) -> Self {
+        Self {
+            prompt_tokens: value.prompt_token_count,
+            completion_tokens: value.candidates_token_count,
+            total_tokens: value.total_token_count,
+        }
+    }
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct GeminiCandidate {
+    content: GeminiContent,
+    finish_reason: String,
+    index: usize,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct GeminiError {
+    code: i32,
+    message: String,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::content::ContentPart;
+    use crate::model::gemini::{GeminiModel, GeminiRequest};
+    use crate::model::{ChatHistory, FinishReason, Message, Model, ModelConfig, Usage};
+    use crate::utils::mock_http_server;
+
+    const TRANSCRIPT: &str = "data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"hello\"}],\"role\":\"model\"},\"index\":0}]}\r\n\r\n\
+data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\" world\"}],\"role\":\"model\"},\"finishReason\":\"STOP\",\"index\":0}],\"usageMetadata\":{\"promptTokenCount\":4,\"candidatesTokenCount\":2,\"totalTokenCount\":6}}\r\n\r\n";
+
+    #[test]
+    fn test_gemini_request() {
+        let cfg = ModelConfig::default()
+            .append_extend("candidateCount", "1")
+            .append_extend(
+                "safetySettings",
+                r#"[{"category":"HARM_CATEGORY_HARASSMENT","threshold":"BLOCK_NONE"}]"#,
+            );
+        let history: Vec<Message> = ChatHistory::system("you are a mock")
+            .user("hi")
+            .assistant("hello")
+            .user("bye")
+            .into();
+        let req = GeminiRequest::from((&cfg, history.as_slice()));
+        let value: serde_json::Value = serde_json::from_str(req.to_string().as_str()).unwrap();
+        assert_eq!(value["systemInstruction"]["parts"][0]["text"], "you are a mock");
+        assert_eq!(value["contents"].as_array().unwrap().len(), 3);
+        assert_eq!(value["contents"][1]["role"], "model");
+        assert_eq!(value["generationConfig"]["candidateCount"], 1);
+        assert_eq!(value["safetySettings"][0]["threshold"], "BLOCK_NONE");
+
+        let msg = [Message::new_user("")
+            .append_part(ContentPart::image_base64("image/png", "cG5n"))
+            .append_part(ContentPart::file_url("a.pdf", "gs://bucket/a.pdf"))];
+        let req = GeminiRequest::from((&cfg, msg.as_slice()));
+        let value: serde_json::Value = serde_json::from_str(req.to_string().as_str()).unwrap();
+        let parts = &value["contents"][0]["parts"];
+        assert_eq!(parts[0], serde_json::json!({"inlineData": {"mimeType": "image/png", "data": "cG5n"}}));
+        assert_eq!(parts[1]["fileData"]["mimeType"], "application/pdf");
+        assert_eq!(parts[1]["fileData"]["fileUri"], "gs://bucket/a.pdf");
+    }
+
+    #[tokio::test]
+    async fn test_gemini_model() {
+        let addr = mock_http_server("text/event-stream", TRANSCRIPT).await;
+        let cfg = ModelConfig::default().set_name("gemini-mock");
+        let history: Vec<_> = ChatHistory::system("you are a mock").user("hi").into();
+
+        let (msg, reason) = GeminiModel::new("mock-key")
+            .set_base_url(format!("http://{addr}/v1beta"))
+            .chat(&cfg, history.as_slice())
+            .await
+            .expect("chat failed")
+            .collect()
+            .await
+            .expect("stream failed");
+        assert_eq!(msg.content, "hello world");
+        assert_eq!(reason, FinishReason::Stop);
+        assert_eq!(msg.usage, Some(Usage::new(4, 2)));
+    }
+}
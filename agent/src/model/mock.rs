@@ -0,0 +1,246 @@
+use crate::model::error::ModelError;
+use crate::model::{
+    FinishReason, Message, MessageType, ModelConfig, Response, ResponseEvent, ToolCall, Usage,
+};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use wd_tools::sync::Am;
+
+/// MockModel 每次chat消费的一条剧本
+#[derive(Debug, Clone)]
+pub enum MockReply {
+    /// 一次性返回完整内容
+    Text(String),
+    /// 按片段返回，每个片段之间等待delay
+    Chunks { chunks: Vec<String>, delay: Duration },
+    /// 先返回若干片段，再返回一个错误
+    Error { chunks: Vec<String>, error: String },
+    /// 直接返回一个分类的错误，用于测试重试和降级
+    Fail(ModelError),
+    /// 先返回思考过程，再返回完整内容
+    Reasoning { reasoning: String, text: String },
+    /// 要求调用工具
+    ToolCalls(Vec<ToolCall>),
+    /// 返回最后一条user消息
+    Echo,
+}
+
+impl MockReply {
+    pub fn text<S: Into<String>>(s: S) -> Self {
+        MockReply::Text(s.into())
+    }
+    pub fn chunks<S: Into<String>>(chunks: impl IntoIterator<Item = S>, delay: Duration) -> Self {
+        let chunks = chunks.into_iter().map(|x| x.into()).collect();
+        MockReply::Chunks { chunks, delay }
+    }
+    pub fn error<S: Into<String>, E: Into<String>>(
+        chunks: impl IntoIterator<Item = S>,
+        error: E,
+    ) -> Self {
+        let chunks = chunks.into_iter().map(|x| x.into()).collect();
+        MockReply::Error {
+            chunks,
+            error: error.into(),
+        }
+    }
+}
+
+/// 一次chat调用收到的参数
+#[derive(Debug, Clone)]
+pub struct MockRecord {
+    pub config: ModelConfig,
+    pub messages: Vec<Message>,
+}
+
+/// 离线测试用的模型，按顺序回放剧本，剧本用完后回显用户消息
+///
+/// clone出来的实例共享剧本和调用记录，可以在交给agent之后继续断言
+#[derive(Debug, Clone)]
+pub struct MockModel {
+    script: Arc<Am<VecDeque<MockReply>>>,
+    records: Arc<Am<Vec<MockRecord>>>,
+    //每次正常结束的回复在Finish之前附带的用量
+    usage: Option<Usage>,
+}
+
+impl Default for MockModel {
+    fn default() -> Self {
+        Self {
+            script: Arc::new(Am::new(VecDeque::new())),
+            records: Arc::new(Am::new(Vec::new())),
+            usage: None,
+        }
+    }
+}
+
+impl MockModel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set_usage(mut self, usage: Usage) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+    pub fn reply(self, reply: MockReply) -> Self {
+        self.script.synchronize().push_back(reply);
+        self
+    }
+    pub fn reply_text<S: Into<String>>(self, s: S) -> Self {
+        self.reply(MockReply::text(s))
+    }
+    pub fn records(&self) -> Vec<MockRecord> {
+        self.records.synchronize().clone()
+    }
+    pub fn last_record(&self) -> Option<MockRecord> {
+        self.records.synchronize().last().cloned()
+    }
+}
+
+#[async_trait::async_trait]
+impl super::Model for MockModel {
+    async fn chat(&self, cfg: &ModelConfig, msg: &[Message]) -> anyhow::Result<Response> {
+        self.records.lock().await.push(MockRecord {
+            config: cfg.clone(),
+            messages: msg.to_vec(),
+        });
+        let reply = self
+            .script
+            .lock()
+            .await
+            .pop_front()
+            .unwrap_or(MockReply::Echo);
+
+        let resp = Response::default();
+        let sender = resp.sender.clone();
+        let usage = self.usage;
+        let (chunks, delay, error) = match reply {
+            MockReply::Text(s) => (vec![s], Duration::ZERO, None),
+            MockReply::Chunks { chunks, delay } => (chunks, delay, None),
+            MockReply::Error { chunks, error } => (chunks, Duration::ZERO, Some(error)),
+            MockReply::Reasoning { reasoning, text } => {
+                sender.send(ResponseEvent::Reasoning(reasoning)).await?;
+                (vec![text], Duration::ZERO, None)
+            }
+            MockReply::Fail(e) => {
+                sender.send(ResponseEvent::Error(e.into())).await?;
+                return Ok(resp);
+            }
+            MockReply::ToolCalls(calls) => {
+                for i in calls {
+                    sender.send(ResponseEvent::ToolCall(i)).await?;
+                }
+                if let Some(usage) = usage {
+                    sender.send(ResponseEvent::Usage(usage)).await?;
+                }
+                sender
+                    .send(ResponseEvent::Finish(FinishReason::ToolCalls))
+                    .await?;
+                return Ok(resp);
+            }
+            MockReply::Echo => {
+                let query = msg
+                    .iter()
+                    .rev()
+                    .find(|x| matches!(x.role, MessageType::User))
+                    .map(|x| x.content.clone())
+                    .unwrap_or_default();
+                (vec![query], Duration::ZERO, None)
+            }
+        };
+        let task = tokio::spawn(async move {
+            for i in chunks {
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                if sender.send(ResponseEvent::Text(i)).await.is_err() {
+                    return;
+                }
+            }
+            let last = match (error, usage) {
+                (Some(e), _) => ResponseEvent::Error(anyhow::anyhow!("{e}")),
+                (None, Some(usage)) => {
+                    if sender.send(ResponseEvent::Usage(usage)).await.is_err() {
+                        return;
+                    }
+                    ResponseEvent::Finish(FinishReason::Stop)
+                }
+                (None, None) => ResponseEvent::Finish(FinishReason::Stop),
+            };
+            let _ = sender.send(last).await;
+        });
+        Ok(resp.set_abort_handle(task.abort_handle()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::mock::{MockModel, MockReply};
+    use crate::model::{ChatHistory, FinishReason, Model, ModelConfig, ResponseEvent, ToolCall};
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_mock_model() {
+        let model = MockModel::new()
+            .reply(MockReply::chunks(["hello", " world"], Duration::from_millis(5)))
+            .reply(MockReply::error(["partial"], "boom"))
+            .reply(MockReply::ToolCalls(vec![ToolCall {
+                id: "call_0".into(),
+                name: "now".into(),
+                arguments: "{}".into(),
+            }]));
+        let cfg = ModelConfig::default().set_name("mock");
+        let history: Vec<_> = ChatHistory::system("sys").user("hi").into();
+
+        let resp = model.chat(&cfg, history.as_slice()).await.unwrap();
+        let (msg, reason) = resp.collect().await.unwrap();
+        assert_eq!(msg.content, "hello world");
+        assert_eq!(reason, FinishReason::Stop);
+
+        let mut resp = model.chat(&cfg, history.as_slice()).await.unwrap();
+        assert!(matches!(resp.next().await, Some(ResponseEvent::Text(s)) if s == "partial"));
+        assert!(
+            matches!(resp.next().await, Some(ResponseEvent::Error(e)) if e.to_string() == "boom")
+        );
+        //错误之后流结束
+        assert!(resp.next().await.is_none());
+
+        let resp = model.chat(&cfg, history.as_slice()).await.unwrap();
+        let (msg, reason) = resp.collect().await.unwrap();
+        assert_eq!(msg.tool_calls[0].name, "now");
+        assert_eq!(reason, FinishReason::ToolCalls);
+
+        let resp = model.chat(&cfg, history.as_slice()).await.unwrap();
+        assert_eq!(resp.collect().await.unwrap().0.content, "hi");
+
+        let records = model.records();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].config.name, "mock");
+        assert_eq!(records[0].messages.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_mock_model_stream() {
+        let model = MockModel::new().reply(MockReply::chunks(
+            ["a", "b", "c"],
+            Duration::from_millis(5),
+        ));
+        let cfg = ModelConfig::default();
+        let history: Vec<_> = ChatHistory::default().user("hi").into();
+
+        let resp = model.chat(&cfg, history.as_slice()).await.unwrap();
+        let text = resp
+            .filter_map(|x| async move {
+                match x {
+                    ResponseEvent::Text(s) => Some(s),
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        let text = tokio::time::timeout(Duration::from_secs(1), text)
+            .await
+            .expect("stream not finished");
+        assert_eq!(text, vec!["a", "b", "c"]);
+    }
+}
@@ -1,19 +1,46 @@
+pub mod claude;
+pub mod content;
 pub mod coze;
 pub mod define;
+pub mod embedding;
+pub mod error;
+pub mod fallback;
+pub mod gemini;
+pub mod mock;
+pub mod ollama;
+pub mod openai;
+pub mod price;
 pub mod qwen;
+pub mod retry;
+pub mod router;
+pub mod tokenizer;
 
+use crate::model::content::ContentPart;
 use async_channel::{Receiver, Sender};
+use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::task::AbortHandle;
+use wd_tools::PFErr;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelConfig {
     pub name: String,
     pub temperature: f32,
-    pub top_p: f32,
+    /// 为None时不发送，使用服务商的默认值
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
     pub max_output_token: usize,
+    /// 上下文长度，为0时按模型名推断
+    #[serde(default)]
+    pub context_size: usize,
     pub stream: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolDefine>,
 
     pub extend: HashMap<String, String>,
 }
@@ -23,9 +50,11 @@ impl Default for ModelConfig {
         Self {
             name: "".to_string(),
             temperature: 1.0,
-            top_p: 0.9,
+            top_p: None,
             max_output_token: 512,
+            context_size: 0,
             stream: true,
+            tools: vec![],
             extend: Default::default(),
         }
     }
@@ -35,21 +64,63 @@ impl ModelConfig {
         self.temperature = temperature;
         self
     }
+    pub fn set_top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
     pub fn set_name<S: Into<String>>(mut self, name: S) -> Self {
         self.name = name.into();
         self
     }
+    pub fn set_context_size(mut self, size: usize) -> Self {
+        self.context_size = size;
+        self
+    }
     pub fn set_stream_mode(mut self, stream: bool) -> Self {
         self.stream = stream;
         self
     }
+    pub fn append_tool(mut self, tool: ToolDefine) -> Self {
+        self.tools.push(tool);
+        self
+    }
     pub fn append_extend<K: Into<String>, V: Into<String>>(mut self, k: K, v: V) -> Self {
         self.extend.insert(k.into(), v.into());
         self
     }
 }
 
+/// 提供给模型的工具(函数)定义
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ToolDefine {
+    pub name: String,
+    pub description: String,
+    /// 参数的json schema
+    pub parameters: serde_json::Value,
+}
+impl ToolDefine {
+    pub fn new<N: Into<String>, D: Into<String>>(
+        name: N,
+        description: D,
+        parameters: serde_json::Value,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            parameters,
+        }
+    }
+}
+
+/// 模型发起的一次工具调用，arguments为json字符串
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum MessageType {
     SYSTEM,
@@ -83,12 +154,23 @@ impl Display for MessageType {
     }
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Message {
     pub role: MessageType,
     pub content: String,
+    /// 图片、文件等多模态内容，排在content之后
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<ContentPart>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// 生成这条assistant消息的请求消耗的token，不会发送给模型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// 推理模型的思考过程，只用于展示，不会作为历史发送给模型
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reasoning: String,
 }
 
 impl Message {
@@ -96,7 +178,11 @@ impl Message {
         Message {
             role: role.into(),
             content: content.into(),
+            parts: vec![],
             call_id: None,
+            tool_calls: vec![],
+            usage: None,
+            reasoning: String::new(),
         }
     }
     pub fn new_system<C: Into<String>>(content: C) -> Message {
@@ -108,8 +194,30 @@ impl Message {
     pub fn new_assistant<C: Into<String>>(content: C) -> Message {
         Message::new(MessageType::Assistant, content)
     }
-    pub fn is_over(&self) -> bool {
-        self.content.is_empty()
+    pub fn new_tool<I: Into<String>, C: Into<String>>(call_id: I, content: C) -> Message {
+        let mut msg = Message::new(MessageType::TOOL, content);
+        msg.call_id = Some(call_id.into());
+        msg
+    }
+    pub fn new_tool_calls(tool_calls: Vec<ToolCall>) -> Message {
+        let mut msg = Message::new(MessageType::Assistant, "");
+        msg.tool_calls = tool_calls;
+        msg
+    }
+    pub fn append_part(mut self, part: ContentPart) -> Self {
+        self.parts.push(part);
+        self
+    }
+    /// content和parts按顺序拼接成文本，图片和文件用占位文本代替
+    pub fn text_with_placeholder(&self) -> String {
+        let mut text = self.content.clone();
+        for part in self.parts.iter() {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(part.placeholder().as_str());
+        }
+        text
     }
 }
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -137,19 +245,240 @@ impl From<ChatHistory> for Vec<Message> {
     }
 }
 
-#[derive(Debug, Clone)]
+/// token用量
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+impl Usage {
+    pub fn new(prompt_tokens: usize, completion_tokens: usize) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.total_tokens == 0 && self.prompt_tokens == 0 && self.completion_tokens == 0
+    }
+}
+impl std::ops::Add for Usage {
+    type Output = Usage;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            prompt_tokens: self.prompt_tokens + rhs.prompt_tokens,
+            completion_tokens: self.completion_tokens + rhs.completion_tokens,
+            total_tokens: self.total_tokens + rhs.total_tokens,
+        }
+    }
+}
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+impl std::iter::Sum for Usage {
+    fn sum<I: Iterator<Item = Usage>>(iter: I) -> Self {
+        iter.fold(Usage::default(), |a, b| a + b)
+    }
+}
+
+/// 回复结束的原因
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    #[default]
+    Stop,
+    /// 达到最大输出长度
+    Length,
+    /// 模型要求调用工具
+    ToolCalls,
+    /// 内容被安全策略拦截
+    ContentFilter,
+    /// 被调用方主动取消
+    Canceled,
+    Unknown(String),
+}
+/// 兼容各家模型的结束原因
+impl From<&str> for FinishReason {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "stop" | "end_turn" | "stop_sequence" | "completed" => FinishReason::Stop,
+            "length" | "max_tokens" => FinishReason::Length,
+            "tool_calls" | "tool_use" | "function_call" | "requires_action" => {
+                FinishReason::ToolCalls
+            }
+            "content_filter" | "safety" | "recitation" | "blocklist" | "prohibited_content"
+            | "spii" | "refusal" => FinishReason::ContentFilter,
+            "canceled" | "cancelled" => FinishReason::Canceled,
+            x => FinishReason::Unknown(x.to_string()),
+        }
+    }
+}
+
+/// 模型回复流中的事件，一次回复以 Finish 或 Error 结束
+#[derive(Debug)]
+pub enum ResponseEvent {
+    /// 文本增量
+    Text(String),
+    /// 推理(思考)过程的增量
+    Reasoning(String),
+    /// 一次工具调用，参数已经拼接完整
+    ToolCall(ToolCall),
+    Usage(Usage),
+    /// 实际处理请求的模型名，FallbackModel和RouterModel选定后端后发送，用于按模型记录用量
+    Model(String),
+    Finish(FinishReason),
+    Error(anyhow::Error),
+}
+impl ResponseEvent {
+    pub fn is_end(&self) -> bool {
+        matches!(self, ResponseEvent::Finish(_) | ResponseEvent::Error(_))
+    }
+}
+impl From<anyhow::Error> for ResponseEvent {
+    fn from(value: anyhow::Error) -> Self {
+        ResponseEvent::Error(value)
+    }
+}
+
+/// 模型回复的事件流，收到 Finish 或 Error 之后流结束(返回None)
+#[derive(Debug)]
 pub struct Response {
-    sender: Sender<anyhow::Result<Message>>,
-    receiver: Receiver<anyhow::Result<Message>>,
+    sender: Sender<ResponseEvent>,
+    //Receiver没有实现Unpin，固定在堆上以便直接poll
+    receiver: Pin<Box<Receiver<ResponseEvent>>>,
+    finished: bool,
+    //产生事件的后台任务，取消时一并终止
+    abort: Option<AbortHandle>,
+}
+impl Clone for Response {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            receiver: Box::pin((*self.receiver).clone()),
+            finished: self.finished,
+            abort: self.abort.clone(),
+        }
+    }
 }
 impl Response {
-    pub async fn next(&mut self) -> anyhow::Result<Message> {
-        self.receiver.recv().await?
+    /// 非流式请求也通过Response返回：完整的消息拆成事件，最后跟一个Finish
+    pub fn once<F>(fut: F) -> Self
+    where
+        F: Future<Output = anyhow::Result<(Message, FinishReason)>> + Send + 'static,
+    {
+        let resp = Self::default();
+        let sender = resp.sender.clone();
+        let task = tokio::spawn(async move {
+            let events = match fut.await {
+                Ok((msg, reason)) => {
+                    let mut events = vec![];
+                    if !msg.reasoning.is_empty() {
+                        events.push(ResponseEvent::Reasoning(msg.reasoning));
+                    }
+                    if !msg.content.is_empty() {
+                        events.push(ResponseEvent::Text(msg.content));
+                    }
+                    events.extend(msg.tool_calls.into_iter().map(ResponseEvent::ToolCall));
+                    events.extend(msg.usage.map(ResponseEvent::Usage));
+                    events.push(ResponseEvent::Finish(reason));
+                    events
+                }
+                Err(e) => vec![ResponseEvent::Error(e)],
+            };
+            Self::send_all(&sender, Ok((false, events))).await;
+        });
+        resp.set_abort_handle(task.abort_handle())
+    }
+    pub fn set_abort_handle(mut self, abort: AbortHandle) -> Self {
+        self.abort = Some(abort);
+        self
     }
-    pub async fn push(&mut self, msg: anyhow::Result<Message>) -> anyhow::Result<()> {
-        self.sender.send(msg).await?;
+    /// 终止后台的请求任务并关闭事件流，已经收到的事件仍然可以读取
+    pub fn abort(&self) {
+        if let Some(ref abort) = self.abort {
+            abort.abort();
+        }
+        self.receiver.close();
+    }
+    /// 供stream_handle使用：发送解析出的事件，解析失败时发送错误；返回是否继续读取
+    pub(crate) async fn send_all(
+        sender: &Sender<ResponseEvent>,
+        result: anyhow::Result<(bool, Vec<ResponseEvent>)>,
+    ) -> bool {
+        let (cont, events) = match result {
+            Ok(o) => o,
+            Err(e) => (false, vec![ResponseEvent::Error(e)]),
+        };
+        for i in events {
+            if let Err(err) = sender.send(i).await {
+                wd_log::log_field("error", err).error("Response.send_all send event failed");
+                return false;
+            }
+        }
+        cont
+    }
+    /// 等待第一个事件，请求失败或第一个事件就是错误时返回Err，说明还没有任何输出
+    pub(crate) async fn first_event(
+        result: anyhow::Result<Response>,
+    ) -> anyhow::Result<(Response, Option<ResponseEvent>)> {
+        let mut resp = result?;
+        match resp.next().await {
+            Some(ResponseEvent::Error(e)) => Err(e),
+            first => Ok((resp, first)),
+        }
+    }
+    /// 把first和剩余的事件转发到sender，接收方关闭时终止上游的请求
+    pub(crate) async fn forward(mut self, first: Option<ResponseEvent>, sender: &Sender<ResponseEvent>) {
+        let mut event = first;
+        while let Some(i) = event {
+            if sender.send(i).await.is_err() {
+                self.abort();
+                return;
+            }
+            event = self.next().await;
+        }
+    }
+    pub async fn push(&mut self, event: ResponseEvent) -> anyhow::Result<()> {
+        self.sender.send(event).await?;
         Ok(())
     }
+    /// 等待回复结束，合并为一条assistant消息
+    pub async fn collect(mut self) -> anyhow::Result<(Message, FinishReason)> {
+        let mut msg = Message::new_assistant("");
+        while let Some(event) = self.next().await {
+            match event {
+                ResponseEvent::Text(s) => msg.content.push_str(s.as_str()),
+                ResponseEvent::ToolCall(c) => msg.tool_calls.push(c),
+                ResponseEvent::Usage(u) => msg.usage = Some(msg.usage.unwrap_or_default() + u),
+                ResponseEvent::Finish(r) => return Ok((msg, r)),
+                ResponseEvent::Error(e) => return Err(e),
+                ResponseEvent::Reasoning(s) => msg.reasoning.push_str(s.as_str()),
+                ResponseEvent::Model(_) => {}
+            }
+        }
+        anyhow::anyhow!("response stream closed before finish").err()
+    }
+}
+impl Stream for Response {
+    type Item = ResponseEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+        let poll = self.receiver.as_mut().poll_next(cx);
+        if let Poll::Ready(ref event) = poll {
+            self.finished = event.as_ref().map(|x| x.is_end()).unwrap_or(true);
+        }
+        poll
+    }
 }
 impl Drop for Response {
     fn drop(&mut self) {
@@ -160,7 +489,12 @@ impl Drop for Response {
 impl Default for Response {
     fn default() -> Self {
         let (sender, receiver) = async_channel::unbounded();
-        Self { sender, receiver }
+        Self {
+            sender,
+            receiver: Box::pin(receiver),
+            finished: false,
+            abort: None,
+        }
     }
 }
 
@@ -168,3 +502,11 @@ impl Default for Response {
 pub trait Model: Send {
     async fn chat(&self, cfg: &ModelConfig, msg: &[Message]) -> anyhow::Result<Response>;
 }
+
+/// 同一个模型实例可以被多个agent共享
+#[async_trait::async_trait]
+impl<T: Model + Sync + ?Sized> Model for std::sync::Arc<T> {
+    async fn chat(&self, cfg: &ModelConfig, msg: &[Message]) -> anyhow::Result<Response> {
+        self.as_ref().chat(cfg, msg).await
+    }
+}
@@ -0,0 +1,314 @@
+use crate::model::content::{load_local_media, ContentPart, MediaSource};
+use crate::model::error::ModelError;
+use crate::model::{FinishReason, Message, ModelConfig, Response, ResponseEvent, Usage};
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt::{Display, Formatter};
+
+const OLLAMA_HOST: &str = "OLLAMA_HOST";
+const OLLAMA_DEFAULT_HOST: &str = "http://localhost:11434";
+const OLLAMA_CHAT_PATH: &str = "/api/chat";
+/// ModelConfig.extend 中会被透传到 ollama options 的字段
+const OLLAMA_OPTION_KEYS: &[&str] = &[
+    "num_ctx",
+    "num_keep",
+    "num_gpu",
+    "num_thread",
+    "seed",
+    "top_k",
+    "min_p",
+    "typical_p",
+    "tfs_z",
+    "repeat_last_n",
+    "repeat_penalty",
+    "presence_penalty",
+    "frequency_penalty",
+    "mirostat",
+    "mirostat_tau",
+    "mirostat_eta",
+    "stop",
+];
+
+/// 本地ollama服务，走原生 `/api/chat` 接口，流式返回为 ndjson
+#[derive(Debug, Clone)]
+pub struct OllamaModel {
+    pub host: String,
+}
+impl Default for OllamaModel {
+    fn default() -> Self {
+        let host = std::env::var(OLLAMA_HOST).unwrap_or(OLLAMA_DEFAULT_HOST.to_string());
+        Self::new(host)
+    }
+}
+
+impl OllamaModel {
+    pub fn new<S: Into<String>>(host: S) -> Self {
+        let mut host = host.into();
+        if !host.starts_with("http://") && !host.starts_with("https://") {
+            host = format!("http://{host}");
+        }
+        Self { host }
+    }
+    pub fn chat_url(&self) -> String {
+        format!("{}{}", self.host.trim_end_matches('/'), OLLAMA_CHAT_PATH)
+    }
+    pub fn ndjson_stream_response_process(
+        line: &str,
+    ) -> anyhow::Result<(bool, Vec<ResponseEvent>)> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok((true, vec![]));
+        }
+        let delta = serde_json::from_str::<OllamaChatResponse>(line)
+            .map_err(|_| ModelError::from_body(line))?;
+        if !delta.error.is_empty() {
+            return Err(ModelError::from_code("", delta.error.as_str()).into());
+        }
+        let mut list = vec![];
+        let (done, reason, usage) = (delta.done, delta.finish_reason(), delta.usage());
+        if !delta.message.thinking.is_empty() {
+            list.push(ResponseEvent::Reasoning(delta.message.thinking));
+        }
+        if !delta.message.content.is_empty() {
+            list.push(ResponseEvent::Text(delta.message.content));
+        }
+        if done {
+            list.push(ResponseEvent::Usage(usage));
+            list.push(ResponseEvent::Finish(reason));
+        }
+        Ok((!done, list))
+    }
+    /// 非流式请求，等待完整的回复
+    pub async fn chat_once(
+        &self,
+        cfg: &ModelConfig,
+        msg: &[Message],
+    ) -> anyhow::Result<(Message, FinishReason)> {
+        let msg = load_local_media(msg).await;
+        let req_body = OllamaChatRequest::from((cfg, msg.as_ref())).to_string();
+        let resp: OllamaChatResponse = utils::json(
+            reqwest::Method::POST,
+            self.chat_url().as_str(),
+            |builder| {
+                builder
+                    .header("Content-Type", "application/json")
+                    .body(req_body)
+            },
+        )
+        .await?;
+        if !resp.error.is_empty() {
+            return Err(ModelError::from_code("", resp.error.as_str()).into());
+        }
+        let (reason, usage) = (resp.finish_reason(), resp.usage());
+        let mut msg = Message::new_assistant(resp.message.content);
+        msg.reasoning = resp.message.thinking;
+        msg.usage = Some(usage);
+        Ok((msg, reason))
+    }
+}
+
+#[async_trait::async_trait]
+impl super::Model for OllamaModel {
+    async fn chat(&self, cfg: &ModelConfig, msg: &[Message]) -> anyhow::Result<Response> {
+        if !cfg.stream {
+            let (this, cfg, msg) = (self.clone(), cfg.clone(), msg.to_vec());
+            return Ok(Response::once(async move {
+                this.chat_once(&cfg, msg.as_slice()).await
+            }));
+        }
+        let resp = Response::default();
+        let sender = resp.sender.clone();
+        let msg = load_local_media(msg).await;
+        let req_body = OllamaChatRequest::from((cfg, msg.as_ref())).to_string();
+        let abort = utils::ndjson(
+            reqwest::Method::POST,
+            self.chat_url().as_str(),
+            |builder| {
+                builder
+                    .header("Content-Type", "application/json")
+                    .body(req_body)
+            },
+            (),
+            move |_, line| {
+                let sender = sender.clone();
+                let result = line.and_then(|x| Self::ndjson_stream_response_process(x.as_str()));
+                async move { Response::send_all(&sender, result).await }
+            },
+        )
+        .await?;
+
+        Ok(resp.set_abort_handle(abort))
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct OllamaMsg {
+    #[serde(default)]
+    role: String,
+    #[serde(default)]
+    content: String,
+    //开启think的模型单独给出思考过程，历史中的思考过程不回传
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    thinking: String,
+    /// 只支持base64的图片，其他片段以文本代替
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+}
+impl From<&Message> for OllamaMsg {
+    fn from(value: &Message) -> Self {
+        let mut content = value.content.clone();
+        let mut images = vec![];
+        for part in value.parts.iter() {
+            if let ContentPart::Image {
+                source: MediaSource::Base64 { data, .. },
+            } = part
+            {
+                images.push(data.clone());
+                continue;
+            }
+            if !content.is_empty() {
+                content.push('\n');
+            }
+            content.push_str(part.placeholder().as_str());
+        }
+        Self {
+            role: value.role.to_string(),
+            content,
+            thinking: String::new(),
+            images,
+        }
+    }
+}
+#[derive(Debug, Default, Serialize)]
+struct OllamaChatRequest {
+    model: String,
+    messages: Vec<OllamaMsg>,
+    stream: bool,
+    options: Map<String, Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<String>,
+}
+impl Display for OllamaChatRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = serde_json::to_string(self).unwrap();
+        write!(f, "{s}")
+    }
+}
+impl From<(&ModelConfig, &[Message])> for OllamaChatRequest {
+    fn from((cfg, msg): (&ModelConfig, &[Message])) -> Self {
+        let messages = msg.iter().map(OllamaMsg::from).collect::<Vec<_>>();
+        let mut options = Map::new();
+        options.insert("temperature".into(), Value::from(cfg.temperature));
+        if let Some(top_p) = cfg.top_p {
+            options.insert("top_p".into(), Value::from(top_p));
+        }
+        options.insert("num_predict".into(), Value::from(cfg.max_output_token));
+        for (k, v) in cfg.extend.iter() {
+            if !OLLAMA_OPTION_KEYS.contains(&k.as_str()) {
+                continue;
+            }
+            //数字或数组按json解析，失败时按字符串透传
+            let value = serde_json::from_str::<Value>(v).unwrap_or(Value::String(v.clone()));
+            options.insert(k.clone(), value);
+        }
+        Self {
+            model: cfg.name.clone(),
+            messages,
+            stream: cfg.stream,
+            options,
+            keep_alive: cfg.extend.get("keep_alive").cloned(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct OllamaChatResponse {
+    message: OllamaMsg,
+    done: bool,
+    done_reason: String,
+    error: String,
+    //只在done为true的最后一行中给出
+    prompt_eval_count: usize,
+    eval_count: usize,
+}
+impl OllamaChatResponse {
+    fn usage(&self) -> Usage {
+        Usage::new(self.prompt_eval_count, self.eval_count)
+    }
+    fn finish_reason(&self) -> FinishReason {
+        if self.done_reason.is_empty() {
+            FinishReason::Stop
+        } else {
+            FinishReason::from(self.done_reason.as_str())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::content::ContentPart;
+    use crate::model::error::ModelError;
+    use crate::model::ollama::{OllamaChatRequest, OllamaModel};
+    use crate::model::{ChatHistory, FinishReason, Message, Model, ModelConfig, Usage};
+    use crate::utils::mock_http_server;
+
+    const TRANSCRIPT: &str = "{\"model\":\"llama3\",\"message\":{\"role\":\"assistant\",\"content\":\"\",\"thinking\":\"greet back\"},\"done\":false}\n\
+{\"model\":\"llama3\",\"message\":{\"role\":\"assistant\",\"content\":\"hello\"},\"done\":false}\n\
+{\"model\":\"llama3\",\"message\":{\"role\":\"assistant\",\"content\":\" world\"},\"done\":false}\n\
+{\"model\":\"llama3\",\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":26,\"eval_count\":3}\n";
+
+    #[test]
+    fn test_ollama_request_options() {
+        let cfg = ModelConfig::default()
+            .set_name("llama3")
+            .append_extend("num_ctx", "8192")
+            .append_extend("user_id", "teshin");
+        let req = OllamaChatRequest::from((&cfg, [Message::new_user("hi")].as_slice()));
+        let value: serde_json::Value = serde_json::from_str(req.to_string().as_str()).unwrap();
+        assert_eq!(value["options"]["num_ctx"], 8192);
+        assert!(value["messages"][0].get("images").is_none());
+        assert_eq!(value["options"]["num_predict"], 512);
+        assert!(value["options"].get("user_id").is_none());
+
+        let msg = Message::new_user("这是什么")
+            .append_part(ContentPart::image_base64("image/png", "cG5n"))
+            .append_part(ContentPart::image_url("https://example.com/a.png"));
+        let req = OllamaChatRequest::from((&cfg, [msg].as_slice()));
+        let value: serde_json::Value = serde_json::from_str(req.to_string().as_str()).unwrap();
+        assert_eq!(value["messages"][0]["images"], serde_json::json!(["cG5n"]));
+        assert_eq!(value["messages"][0]["content"], "这是什么\n[图片: https://example.com/a.png]");
+    }
+
+    #[tokio::test]
+    async fn test_ollama_model() {
+        let addr = mock_http_server("application/x-ndjson", TRANSCRIPT).await;
+        let cfg = ModelConfig::default().set_name("llama3");
+        let history: Vec<_> = ChatHistory::default().user("hi").into();
+
+        let (msg, reason) = OllamaModel::new(addr.to_string())
+            .chat(&cfg, history.as_slice())
+            .await
+            .expect("chat failed")
+            .collect()
+            .await
+            .expect("stream failed");
+        assert_eq!(msg.content, "hello world");
+        assert_eq!(msg.reasoning, "greet back");
+        assert_eq!(reason, FinishReason::Stop);
+        assert_eq!(msg.usage, Some(Usage::new(26, 3)));
+    }
+
+    #[tokio::test]
+    async fn test_ollama_chat_once_error() {
+        let addr = mock_http_server("application/json", r#"{"error":"model 'llama3' not found, try pulling it first"}"#).await;
+        let cfg = ModelConfig::default().set_name("llama3").set_stream_mode(false);
+        let err = OllamaModel::new(addr.to_string())
+            .chat_once(&cfg, [Message::new_user("hi")].as_slice())
+            .await
+            .expect_err("should fail");
+        let kind = ModelError::classify(&err).map(|x| x.kind());
+        assert_eq!(kind, Some("provider"));
+    }
+}
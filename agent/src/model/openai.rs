@@ -0,0 +1,584 @@
+use crate::model::content::{load_local_media, ContentPart, MediaSource};
+use crate::model::error::ModelError;
+use crate::model::{
+    FinishReason, Message, ModelConfig, Response, ResponseEvent, ToolCall, ToolDefine, Usage,
+};
+use crate::utils;
+use crate::utils::SseEvent;
+use reqwest::RequestBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+const OPENAI_API_KEY: &str = "OPENAI_API_KEY";
+const CHAT_COMPLETIONS_PATH: &str = "/chat/completions";
+/// 已知支持 `stream_options` 的服务
+const STREAM_USAGE_HOSTS: [&str; 3] = [
+    "api.openai.com",
+    "dashscope.aliyuncs.com",
+    "dashscope-intl.aliyuncs.com",
+];
+
+/// 兼容 OpenAI `chat/completions` 协议的通用模型
+///
+/// 只需要替换 base_url 即可对接 OpenAI、DeepSeek、Moonshot、vLLM、llama.cpp server 等服务
+#[derive(Debug, Clone)]
+pub struct OpenAICompatModel {
+    pub base_url: String,
+    pub api_key: String,
+    pub headers: HashMap<String, String>,
+    /// 流式请求是否带上 `stream_options.include_usage` 以获取用量，
+    /// 为None时只对已知支持的服务开启，部分兼容服务收到不认识的字段会返回400
+    pub include_usage: Option<bool>,
+}
+impl Default for OpenAICompatModel {
+    fn default() -> Self {
+        let api_key = std::env::var(OPENAI_API_KEY).unwrap_or("".to_string());
+        Self::new(OPENAI_BASE_URL, api_key)
+    }
+}
+
+impl OpenAICompatModel {
+    pub fn new<U: Into<String>, S: Into<String>>(base_url: U, key: S) -> Self {
+        let base_url = base_url.into();
+        let api_key = key.into();
+        Self {
+            base_url,
+            api_key,
+            headers: HashMap::new(),
+            include_usage: None,
+        }
+    }
+    pub fn set_header<K: Into<String>, V: Into<String>>(mut self, k: K, v: V) -> Self {
+        self.headers.insert(k.into(), v.into());
+        self
+    }
+    pub fn set_include_usage(mut self, include: bool) -> Self {
+        self.include_usage = Some(include);
+        self
+    }
+    fn include_usage(&self) -> bool {
+        self.include_usage
+            .unwrap_or_else(|| STREAM_USAGE_HOSTS.iter().any(|x| self.base_url.contains(x)))
+    }
+    fn chat_request(&self, cfg: &ModelConfig, msg: &[Message]) -> OpenAIChatRequest {
+        let mut req = OpenAIChatRequest::from((cfg, msg));
+        //流式请求默认不返回用量
+        if cfg.stream && self.include_usage() {
+            req.stream_options = Some(OpenAIStreamOptions { include_usage: true });
+        }
+        req
+    }
+    pub fn chat_url(&self) -> String {
+        format!(
+            "{}{}",
+            self.base_url.trim_end_matches('/'),
+            CHAT_COMPLETIONS_PATH
+        )
+    }
+    fn set_request_header(&self, mut builder: RequestBuilder) -> RequestBuilder {
+        builder = builder.header("Content-Type", "application/json");
+        if !self.api_key.is_empty() {
+            builder = builder.header("Authorization", format!("Bearer {}", self.api_key));
+        }
+        for (k, v) in self.headers.iter() {
+            builder = builder.header(k, v);
+        }
+        builder
+    }
+    /// 非流式请求，等待完整的回复
+    pub async fn chat_once(
+        &self,
+        cfg: &ModelConfig,
+        msg: &[Message],
+    ) -> anyhow::Result<(Message, FinishReason)> {
+        let msg = load_local_media(msg).await;
+        let req_body = self.chat_request(cfg, msg.as_ref()).to_string();
+        let resp: OpenAIChatResponse = utils::json(
+            reqwest::Method::POST,
+            self.chat_url().as_str(),
+            |builder| self.set_request_header(builder).body(req_body),
+        )
+        .await?;
+        let Some(choice) = resp.choices.into_iter().next() else {
+            return Ok((Message::new_assistant(""), FinishReason::Stop));
+        };
+        let mut msg = Message::new_assistant(choice.message.content);
+        msg.reasoning = choice.message.reasoning_content;
+        msg.usage = resp.usage;
+        msg.tool_calls = choice
+            .message
+            .tool_calls
+            .into_iter()
+            .map(ToolCall::from)
+            .collect();
+        Ok((msg, FinishReason::from(choice.finish_reason.as_str())))
+    }
+}
+
+#[async_trait::async_trait]
+impl super::Model for OpenAICompatModel {
+    async fn chat(&self, cfg: &ModelConfig, msg: &[Message]) -> anyhow::Result<Response> {
+        if !cfg.stream {
+            let (this, cfg, msg) = (self.clone(), cfg.clone(), msg.to_vec());
+            return Ok(Response::once(async move {
+                this.chat_once(&cfg, msg.as_slice()).await
+            }));
+        }
+        let resp = Response::default();
+        let sender = resp.sender.clone();
+        let msg = load_local_media(msg).await;
+        let req_body = self.chat_request(cfg, msg.as_ref()).to_string();
+        let abort = utils::sse(
+            reqwest::Method::POST,
+            self.chat_url().as_str(),
+            |builder| self.set_request_header(builder).body(req_body),
+            OpenAIStreamState::default(),
+            move |state, x| {
+                let sender = sender.clone();
+                let result = Self::sse_stream_response_process(state, x);
+                async move { Response::send_all(&sender, result).await }
+            },
+        )
+        .await?;
+
+        Ok(resp.set_abort_handle(abort))
+    }
+}
+
+/// 流式解析过程中需要跨事件保存的状态
+#[derive(Debug, Default)]
+pub struct OpenAIStreamState {
+    tool_calls: Vec<ToolCall>,
+    finish_reason: Option<FinishReason>,
+}
+
+impl OpenAICompatModel {
+    /// 工具调用的参数是分片返回的，先按index累积，结束时逐个发出
+    pub fn sse_stream_response_process(
+        state: &mut OpenAIStreamState,
+        event: anyhow::Result<SseEvent>,
+    ) -> anyhow::Result<(bool, Vec<ResponseEvent>)> {
+        let event = event?;
+        if event.data.as_str() == "[DONE]" {
+            let mut list = std::mem::take(&mut state.tool_calls)
+                .into_iter()
+                .map(ResponseEvent::ToolCall)
+                .collect::<Vec<_>>();
+            let reason = match state.finish_reason.take() {
+                Some(r) => r,
+                None if !list.is_empty() => FinishReason::ToolCalls,
+                None => FinishReason::Stop,
+            };
+            list.push(ResponseEvent::Finish(reason));
+            return Ok((false, list));
+        }
+        //dashscope等在流中以错误对象返回限流、内容审核等错误
+        let delta = serde_json::from_str::<OpenAIStreamResponse>(&event.data)
+            .map_err(|_| ModelError::from_body(event.data.as_str()))?;
+        let mut list = vec![];
+        //开启include_usage后，用量在[DONE]之前单独的一个choices为空的事件中
+        if let Some(usage) = delta.usage {
+            list.push(ResponseEvent::Usage(usage));
+        }
+        for i in delta.choices {
+            for call in i.delta.tool_calls {
+                if state.tool_calls.len() <= call.index {
+                    state.tool_calls.resize(call.index + 1, ToolCall::default());
+                }
+                let tc = &mut state.tool_calls[call.index];
+                if !call.id.is_empty() {
+                    tc.id = call.id;
+                }
+                if !call.function.name.is_empty() {
+                    tc.name = call.function.name;
+                }
+                tc.arguments.push_str(call.function.arguments.as_str());
+            }
+            //QwQ、DeepSeek-R1等推理模型先输出思考过程，此时content为空
+            if !i.delta.reasoning_content.is_empty() {
+                list.push(ResponseEvent::Reasoning(i.delta.reasoning_content));
+            }
+            if !i.delta.content.is_empty() {
+                list.push(ResponseEvent::Text(i.delta.content));
+            }
+            if !i.finish_reason.is_empty() {
+                state.finish_reason = Some(FinishReason::from(i.finish_reason.as_str()));
+            }
+        }
+        Ok((true, list))
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct OpenAIFunction {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    name: String,
+    arguments: String,
+}
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct OpenAIToolCall {
+    #[serde(skip_serializing)]
+    index: usize,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    id: String,
+    #[serde(rename = "type")]
+    ty: String,
+    function: OpenAIFunction,
+}
+impl From<&ToolCall> for OpenAIToolCall {
+    fn from(value: &ToolCall) -> Self {
+        Self {
+            index: 0,
+            id: value.id.clone(),
+            ty: "function".to_string(),
+            function: OpenAIFunction {
+                name: value.name.clone(),
+                arguments: value.arguments.clone(),
+            },
+        }
+    }
+}
+impl From<OpenAIToolCall> for ToolCall {
+    fn from(value: OpenAIToolCall) -> Self {
+        Self {
+            id: value.id,
+            name: value.function.name,
+            arguments: value.function.arguments,
+        }
+    }
+}
+#[derive(Debug, Serialize)]
+struct OpenAITool {
+    #[serde(rename = "type")]
+    ty: &'static str,
+    function: ToolDefine,
+}
+/// 没有多模态内容时content是字符串，否则是片段数组
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum OpenAIContent {
+    Text(String),
+    Parts(Vec<OpenAIPart>),
+}
+impl Default for OpenAIContent {
+    fn default() -> Self {
+        OpenAIContent::Text(String::new())
+    }
+}
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenAIPart {
+    Text { text: String },
+    ImageUrl { image_url: OpenAIImageUrl },
+    File { file: OpenAIFile },
+}
+#[derive(Debug, Serialize)]
+struct OpenAIImageUrl {
+    url: String,
+}
+#[derive(Debug, Default, Serialize)]
+struct OpenAIFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filename: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_data: Option<String>,
+}
+impl From<&ContentPart> for OpenAIPart {
+    fn from(value: &ContentPart) -> Self {
+        match value {
+            ContentPart::Image { source } => match source.url() {
+                Some(url) => OpenAIPart::ImageUrl {
+                    image_url: OpenAIImageUrl { url },
+                },
+                None => OpenAIPart::Text {
+                    text: value.placeholder(),
+                },
+            },
+            ContentPart::File { name, source } => match source {
+                MediaSource::FileId { id } => OpenAIPart::File {
+                    file: OpenAIFile {
+                        file_id: Some(id.clone()),
+                        ..Default::default()
+                    },
+                },
+                MediaSource::Base64 { .. } => OpenAIPart::File {
+                    file: OpenAIFile {
+                        filename: Some(name.clone()),
+                        file_data: source.url(),
+                        ..Default::default()
+                    },
+                },
+                _ => OpenAIPart::Text {
+                    text: value.placeholder(),
+                },
+            },
+            ContentPart::Text { text } => OpenAIPart::Text { text: text.clone() },
+        }
+    }
+}
+#[derive(Debug, Default, Serialize)]
+struct OpenAIMsg {
+    role: String,
+    content: OpenAIContent,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OpenAIToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+impl From<&Message> for OpenAIMsg {
+    fn from(value: &Message) -> Self {
+        Self {
+            role: value.role.to_string(),
+            content: OpenAIContent::from(value),
+            tool_calls: value.tool_calls.iter().map(OpenAIToolCall::from).collect(),
+            tool_call_id: value.call_id.clone(),
+        }
+    }
+}
+impl From<&Message> for OpenAIContent {
+    fn from(value: &Message) -> Self {
+        if value.parts.is_empty() {
+            return OpenAIContent::Text(value.content.clone());
+        }
+        let text = Some(value.content.as_str())
+            .filter(|x| !x.is_empty())
+            .map(|x| OpenAIPart::Text { text: x.to_string() });
+        OpenAIContent::Parts(text.into_iter().chain(value.parts.iter().map(OpenAIPart::from)).collect())
+    }
+}
+#[derive(Debug, Default, Serialize)]
+struct OpenAIChatRequest {
+    model: String,
+    messages: Vec<OpenAIMsg>,
+    stream: bool,
+    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    max_tokens: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<OpenAITool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<OpenAIStreamOptions>,
+}
+#[derive(Debug, Default, Serialize)]
+struct OpenAIStreamOptions {
+    include_usage: bool,
+}
+impl Display for OpenAIChatRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = serde_json::to_string(self).unwrap();
+        write!(f, "{s}")
+    }
+}
+impl From<(&ModelConfig, &[Message])> for OpenAIChatRequest {
+    fn from((cfg, msg): (&ModelConfig, &[Message])) -> Self {
+        let messages = msg.iter().map(OpenAIMsg::from).collect::<Vec<_>>();
+        let tools = cfg
+            .tools
+            .iter()
+            .map(|x| OpenAITool {
+                ty: "function",
+                function: x.clone(),
+            })
+            .collect::<Vec<_>>();
+        Self {
+            model: cfg.name.clone(),
+            messages,
+            stream: cfg.stream,
+            temperature: cfg.temperature,
+            top_p: cfg.top_p,
+            max_tokens: cfg.max_output_token,
+            tools,
+            stream_options: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OpenAIChatResponse {
+    choices: Vec<OpenAIResponseChoice>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+#[derive(Debug, Default, Deserialize)]
+struct OpenAIResponseChoice {
+    message: OpenAIDeltaMsg,
+    #[serde(default, deserialize_with = "null_as_default")]
+    finish_reason: String,
+}
+#[derive(Debug, Default, Deserialize)]
+struct OpenAIStreamResponse {
+    #[serde(default)]
+    choices: Vec<OpenAIResponseDelta>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+#[derive(Debug, Default, Deserialize)]
+struct OpenAIResponseDelta {
+    delta: OpenAIDeltaMsg,
+    #[serde(default, deserialize_with = "null_as_default")]
+    finish_reason: String,
+}
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct OpenAIDeltaMsg {
+    #[serde(default = "Default::default")]
+    pub role: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub content: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub reasoning_content: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    tool_calls: Vec<OpenAIToolCall>,
+}
+
+fn null_as_default<'de, D, T>(d: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(d)?.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::content::ContentPart;
+    use crate::model::openai::{OpenAIChatRequest, OpenAICompatModel};
+    use crate::model::{ChatHistory, FinishReason, Message, Model, ModelConfig, ToolDefine, Usage};
+    use crate::utils::mock_http_server;
+
+    const TRANSCRIPT: &str = "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":\"\"}}]}\n\n\
+data: {\"choices\":[{\"delta\":{\"content\":\"hello\"}}]}\n\n\
+data: {\"choices\":[{\"delta\":{\"content\":\" world\"},\"finish_reason\":\"stop\"}],\"usage\":null}\n\n\
+data: {\"choices\":[],\"usage\":{\"prompt_tokens\":9,\"completion_tokens\":2,\"total_tokens\":11}}\n\n\
+data: [DONE]\n\n";
+
+    #[test]
+    fn test_openai_request_parts() {
+        let msg = [
+            Message::new_system("you are a mock"),
+            Message::new_user("这是什么")
+                .append_part(ContentPart::image_base64("image/png", "cG5n"))
+                .append_part(ContentPart::file_id("a.pdf", "file-1"))
+                .append_part(ContentPart::file_url("b.pdf", "https://example.com/b.pdf")),
+        ];
+        let req = OpenAIChatRequest::from((&ModelConfig::default(), msg.as_slice()));
+        let value: serde_json::Value = serde_json::from_str(req.to_string().as_str()).unwrap();
+        assert_eq!(value["messages"][0]["content"], "you are a mock");
+        let parts = &value["messages"][1]["content"];
+        assert_eq!(parts[0], serde_json::json!({"type": "text", "text": "这是什么"}));
+        assert_eq!(parts[1]["image_url"]["url"], "data:image/png;base64,cG5n");
+        assert_eq!(parts[2]["file"]["file_id"], "file-1");
+        assert_eq!(parts[3]["text"], "[文件: b.pdf https://example.com/b.pdf]");
+    }
+
+    #[test]
+    fn test_openai_stream_options() {
+        let (cfg, msg) = (ModelConfig::default(), [Message::new_user("hi")]);
+        let options = |model: OpenAICompatModel, cfg: &ModelConfig| {
+            let req = model.chat_request(cfg, msg.as_slice()).to_string();
+            serde_json::from_str::<serde_json::Value>(req.as_str()).unwrap()["stream_options"].clone()
+        };
+        assert_eq!(options(OpenAICompatModel::default(), &cfg), serde_json::json!({"include_usage": true}));
+        let qwen = OpenAICompatModel::new("https://dashscope.aliyuncs.com/compatible-mode/v1", "");
+        assert!(!options(qwen, &cfg).is_null());
+        //未知的兼容服务默认不发送，可以手动开启
+        let local = OpenAICompatModel::new("http://127.0.0.1:8000/v1", "");
+        assert!(options(local.clone(), &cfg).is_null());
+        assert!(!options(local.clone().set_include_usage(true), &cfg).is_null());
+        assert!(options(OpenAICompatModel::default().set_include_usage(false), &cfg).is_null());
+        assert!(options(local.set_include_usage(true), &cfg.clone().set_stream_mode(false)).is_null());
+    }
+
+    #[tokio::test]
+    async fn test_openai_compat_model() {
+        let addr = mock_http_server("text/event-stream", TRANSCRIPT).await;
+        let cfg = ModelConfig::default().set_name("mock");
+        let history: Vec<_> = ChatHistory::system("you are a mock").user("hi").into();
+
+        let (msg, reason) = OpenAICompatModel::new(format!("http://{addr}/v1"), "")
+            .set_header("X-Test", "1")
+            .chat(&cfg, history.as_slice())
+            .await
+            .expect("chat failed")
+            .collect()
+            .await
+            .expect("stream failed");
+        assert_eq!(msg.content, "hello world");
+        assert_eq!(reason, FinishReason::Stop);
+        assert_eq!(msg.usage, Some(Usage::new(9, 2)));
+    }
+
+    #[tokio::test]
+    async fn test_openai_compat_model_reasoning() {
+        let transcript = "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":null,\"reasoning_content\":\"1+1\"}}]}\n\n\
+data: {\"choices\":[{\"delta\":{\"content\":\"\",\"reasoning_content\":\" is 2\"}}]}\n\n\
+data: {\"choices\":[{\"delta\":{\"content\":\"2\",\"reasoning_content\":null},\"finish_reason\":\"stop\"}]}\n\n\
+data: [DONE]\n\n";
+        let addr = mock_http_server("text/event-stream", transcript).await;
+        let cfg = ModelConfig::default().set_name("qwq-plus");
+        let history: Vec<_> = ChatHistory::default().user("1+1=?").into();
+
+        let (msg, _) = OpenAICompatModel::new(format!("http://{addr}/v1"), "")
+            .chat(&cfg, history.as_slice())
+            .await
+            .expect("chat failed")
+            .collect()
+            .await
+            .expect("stream failed");
+        assert_eq!(msg.reasoning, "1+1 is 2");
+        assert_eq!(msg.content, "2");
+    }
+
+    #[tokio::test]
+    async fn test_openai_compat_model_no_stream() {
+        let body = r#"{"id":"1","choices":[{"index":0,"message":{"role":"assistant","content":"hello world"},"finish_reason":"stop"}],"usage":{"prompt_tokens":5,"completion_tokens":2,"total_tokens":7}}"#;
+        let addr = mock_http_server("application/json", body).await;
+        let cfg = ModelConfig::default().set_name("mock").set_stream_mode(false);
+        let history: Vec<_> = ChatHistory::default().user("hi").into();
+
+        let (msg, reason) = OpenAICompatModel::new(format!("http://{addr}/v1"), "key")
+            .chat(&cfg, history.as_slice())
+            .await
+            .expect("chat failed")
+            .collect()
+            .await
+            .expect("stream failed");
+        assert_eq!(msg.content, "hello world");
+        assert_eq!(reason, FinishReason::Stop);
+        assert_eq!(msg.usage, Some(Usage::new(5, 2)));
+    }
+
+    #[tokio::test]
+    async fn test_openai_compat_model_tool_calls() {
+        let transcript = "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":null,\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"type\":\"function\",\"function\":{\"name\":\"get_weather\",\"arguments\":\"\"}}]}}]}\n\n\
+data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"city\\\":\"}}]}}]}\n\n\
+data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"hangzhou\\\"}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n\
+data: [DONE]\n\n";
+        let addr = mock_http_server("text/event-stream", transcript).await;
+        let cfg = ModelConfig::default().set_name("mock").append_tool(ToolDefine::new(
+            "get_weather",
+            "query weather",
+            serde_json::json!({"type":"object","properties":{"city":{"type":"string"}}}),
+        ));
+        let history: Vec<_> = ChatHistory::default().user("weather?").into();
+
+        let (msg, reason) = OpenAICompatModel::new(format!("http://{addr}/v1"), "")
+            .chat(&cfg, history.as_slice())
+            .await
+            .expect("chat failed")
+            .collect()
+            .await
+            .expect("stream failed");
+        assert_eq!(reason, FinishReason::ToolCalls);
+        assert_eq!(msg.tool_calls.len(), 1);
+        assert_eq!(msg.tool_calls[0].id, "call_1");
+        assert_eq!(msg.tool_calls[0].name, "get_weather");
+        assert_eq!(msg.tool_calls[0].arguments, r#"{"city":"hangzhou"}"#);
+    }
+}
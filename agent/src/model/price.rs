@@ -0,0 +1,135 @@
+use crate::model::Usage;
+use crate::utils::{invalid, load_config};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use wd_tools::PFErr;
+
+/// 每百万token的价格
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+}
+
+impl ModelPrice {
+    pub fn new(input: f64, output: f64) -> Self {
+        Self { input, output }
+    }
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.input + usage.completion_tokens as f64 * self.output) / 1_000_000.0
+    }
+}
+
+/// 模型价格表，按模型名查找，没有完全一致的名字时取最长的前缀
+///
+/// ```toml
+/// currency = "¥"
+///
+/// [models]
+/// qwen-turbo = { input = 0.3, output = 0.6 }
+/// qwen-plus = { input = 0.8, output = 2.0 }
+/// ```
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PriceTable {
+    /// 只用于展示
+    #[serde(default)]
+    pub currency: String,
+    #[serde(default)]
+    pub models: HashMap<String, ModelPrice>,
+}
+
+impl PriceTable {
+    pub fn new<S: Into<String>>(currency: S) -> Self {
+        Self {
+            currency: currency.into(),
+            models: HashMap::new(),
+        }
+    }
+    pub fn set_price<S: Into<String>>(mut self, model: S, price: ModelPrice) -> Self {
+        self.models.insert(model.into(), price);
+        self
+    }
+    /// 从toml/yaml文件读取
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let table = load_config::<Self, _>(path)?;
+        table
+            .validate()
+            .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+        Ok(table)
+    }
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, price) in self.models.iter() {
+            for (field, value) in [("input", price.input), ("output", price.output)] {
+                if !value.is_finite() || value < 0.0 {
+                    return invalid(format!("models.{name}.{field}"), "must be a non-negative number").err();
+                }
+            }
+        }
+        Ok(())
+    }
+    /// 用后加载的价格覆盖同名的模型
+    pub fn merge(mut self, other: PriceTable) -> Self {
+        if !other.currency.is_empty() {
+            self.currency = other.currency;
+        }
+        self.models.extend(other.models);
+        self
+    }
+    pub fn price(&self, model: &str) -> Option<ModelPrice> {
+        if let Some(price) = self.models.get(model) {
+            return Some(*price);
+        }
+        self.models
+            .iter()
+            .filter(|(k, _)| model.starts_with(k.as_str()))
+            .max_by_key(|(k, _)| k.len())
+            .map(|(_, v)| *v)
+    }
+    /// 价格表中没有的模型返回None
+    pub fn cost(&self, model: &str, usage: &Usage) -> Option<f64> {
+        self.price(model).map(|x| x.cost(usage))
+    }
+    /// 多个模型的合计，没有价格的模型不计入
+    pub fn total_cost<'a, I: IntoIterator<Item = (&'a String, &'a Usage)>>(&self, usage: I) -> f64 {
+        usage
+            .into_iter()
+            .filter_map(|(model, usage)| self.cost(model, usage))
+            .sum()
+    }
+    pub fn format(&self, amount: f64) -> String {
+        format!("{}{amount:.4}", self.currency)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::price::{ModelPrice, PriceTable};
+    use crate::model::Usage;
+
+    #[test]
+    fn test_price_table() {
+        let table = PriceTable::new("¥")
+            .set_price("qwen-turbo", ModelPrice::new(0.3, 0.6))
+            .set_price("qwen", ModelPrice::new(2.0, 6.0));
+        let usage = Usage::new(1_000_000, 500_000);
+        assert_eq!(table.cost("qwen-turbo", &usage), Some(0.6));
+        assert_eq!(table.cost("qwen-turbo-latest", &usage), Some(0.6));
+        assert_eq!(table.cost("qwen-max", &usage), Some(5.0));
+        assert_eq!(table.cost("llama3", &usage), None);
+        assert_eq!(table.format(0.6), "¥0.6000");
+
+        let path = std::env::temp_dir().join(format!("{}.toml", wd_tools::uuid::v4()));
+        std::fs::write(&path, "currency = \"$\"\n[models]\ngpt-4o = { input = 2.5, output = 10 }\n").unwrap();
+        let table = table.merge(PriceTable::load(&path).unwrap());
+        assert_eq!(table.currency, "$");
+        assert_eq!(table.price("gpt-4o"), Some(ModelPrice::new(2.5, 10.0)));
+        std::fs::write(&path, "[models]\ngpt-4o = { input = -1, output = 10 }\n").unwrap();
+        let err = PriceTable::load(&path).unwrap_err().to_string();
+        assert!(err.contains("models.gpt-4o.input:"), "{err}");
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::model::error::ModelError;
+use crate::model::{Message, Model, ModelConfig, Response, ResponseEvent};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 指数退避的重试策略，等待时间在 `[d/2, d]` 之间随机，避免多个请求同时重试
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// 最多重试几次，不含第一次请求
+    pub max_retries: usize,
+    /// 第一次重试前的等待时间，之后每次翻倍
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: usize) -> Self {
+        Self {
+            max_retries,
+            ..Default::default()
+        }
+    }
+    pub fn set_base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+    pub fn set_max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+    /// 第attempt次(从0开始)重试前的等待时间，错误不可重试或次数用完时返回None
+    ///
+    /// 服务端给出了retry-after时以服务端为准，超过max_delay时不再等待，直接返回错误
+    pub fn next_delay(&self, attempt: usize, err: &anyhow::Error) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        let err = ModelError::classify(err)?;
+        if !err.is_retryable() {
+            return None;
+        }
+        if let Some(d) = err.retry_after() {
+            return (d <= self.max_delay).then_some(d);
+        }
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << attempt.min(16) as u32)
+            .min(self.max_delay);
+        Some(delay / 2 + delay.mul_f64(random() / 2.0))
+    }
+}
+
+/// [0, 1)之间的随机数，只用于退避抖动，不需要密码学强度
+fn random() -> f64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_nanos() as u64)
+        .unwrap_or_default();
+    let mut x = nanos ^ COUNTER.fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed);
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// 按策略重试的模型，只在收到第一个事件之前重试
+///
+/// 已经输出内容之后的错误原样转发，避免重复的文本
+pub struct RetryModel {
+    inner: Arc<dyn Model + Sync>,
+    pub policy: RetryPolicy,
+}
+
+impl RetryModel {
+    pub fn new<M: Model + Sync + 'static>(model: M, policy: RetryPolicy) -> Self {
+        Self {
+            inner: Arc::new(model),
+            policy,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Model for RetryModel {
+    async fn chat(&self, cfg: &ModelConfig, msg: &[Message]) -> anyhow::Result<Response> {
+        let resp = Response::default();
+        let sender = resp.sender.clone();
+        let (inner, policy) = (self.inner.clone(), self.policy.clone());
+        let (cfg, msg) = (cfg.clone(), msg.to_vec());
+        let task = tokio::spawn(async move {
+            let mut attempt = 0;
+            loop {
+                let err = match Response::first_event(inner.chat(&cfg, msg.as_slice()).await).await {
+                    Ok((stream, first)) => return stream.forward(first, &sender).await,
+                    Err(e) => e,
+                };
+                let Some(delay) = policy.next_delay(attempt, &err) else {
+                    let _ = sender.send(ResponseEvent::Error(err)).await;
+                    return;
+                };
+                attempt += 1;
+                wd_log::log_field("attempt", attempt)
+                    .field("delay_ms", delay.as_millis())
+                    .field("error", err.to_string())
+                    .warn("RetryModel.chat retry");
+                tokio::time::sleep(delay).await;
+            }
+        });
+        Ok(resp.set_abort_handle(task.abort_handle()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::error::ModelError;
+    use crate::model::mock::{MockModel, MockReply};
+    use crate::model::retry::{RetryModel, RetryPolicy};
+    use crate::model::{Model, ModelConfig};
+    use std::time::Duration;
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy::new(2).set_base_delay(Duration::from_millis(100));
+        let network = anyhow::Error::from(ModelError::Network("reset".into()));
+        let d = policy.next_delay(1, &network).unwrap();
+        assert!(d >= Duration::from_millis(100) && d <= Duration::from_millis(200));
+        assert!(policy.next_delay(2, &network).is_none());
+        assert!(policy.next_delay(0, &anyhow::anyhow!("plain")).is_none());
+        let auth = anyhow::Error::from(ModelError::Auth("bad key".into()));
+        assert!(policy.next_delay(0, &auth).is_none());
+        let limit = anyhow::Error::from(ModelError::RateLimit {
+            message: "".into(),
+            retry_after: Some(Duration::from_secs(3)),
+        });
+        assert_eq!(policy.next_delay(0, &limit), Some(Duration::from_secs(3)));
+        let limit = anyhow::Error::from(ModelError::RateLimit {
+            message: "".into(),
+            retry_after: Some(Duration::from_secs(3600)),
+        });
+        assert!(policy.next_delay(0, &limit).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retry_model() {
+        let policy = RetryPolicy::new(2).set_base_delay(Duration::from_millis(1));
+        let mock = MockModel::new()
+            .reply(MockReply::Fail(ModelError::Network("reset".into())))
+            .reply_text("hello");
+        let model = RetryModel::new(mock.clone(), policy.clone());
+        let (msg, _) = model.chat(&ModelConfig::default(), &[]).await.unwrap().collect().await.unwrap();
+        assert_eq!(msg.content, "hello");
+        assert_eq!(mock.records().len(), 2);
+
+        //已经输出内容之后的错误不重试
+        let mock = MockModel::new().reply(MockReply::Error {
+            chunks: vec!["partial".into()],
+            error: "boom".into(),
+        });
+        let model = RetryModel::new(mock.clone(), policy);
+        assert!(model.chat(&ModelConfig::default(), &[]).await.unwrap().collect().await.is_err());
+        assert_eq!(mock.records().len(), 1);
+    }
+}
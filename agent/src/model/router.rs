@@ -0,0 +1,157 @@
+use crate::model::fallback::ModelTarget;
+use crate::model::{Message, MessageType, Model, ModelConfig, Response, ResponseEvent};
+use futures::StreamExt;
+use std::sync::Arc;
+
+/// ModelConfig.extend中指定路由的key，转发给后端之前会被移除
+pub const ROUTE_HINT_KEY: &str = "route";
+
+pub type RouteFn = Arc<dyn Fn(&ModelConfig, &[Message]) -> bool + Send + Sync>;
+
+/// 路由规则，RouterModel按添加的顺序匹配
+#[derive(Clone)]
+pub enum RouteRule {
+    /// ModelConfig.extend["route"]等于给定值，优先于其他规则
+    Hint(String),
+    /// 最后一条user消息不少于n个字符
+    MinChars(usize),
+    /// 最后一条user消息中含有代码
+    HasCode,
+    Custom(RouteFn),
+}
+
+impl RouteRule {
+    pub fn hint<S: Into<String>>(hint: S) -> Self {
+        RouteRule::Hint(hint.into())
+    }
+    pub fn custom(f: impl Fn(&ModelConfig, &[Message]) -> bool + Send + Sync + 'static) -> Self {
+        RouteRule::Custom(Arc::new(f))
+    }
+    pub fn matches(&self, cfg: &ModelConfig, msg: &[Message]) -> bool {
+        let query = || {
+            msg.iter()
+                .rev()
+                .find(|x| matches!(x.role, MessageType::User))
+                .map(|x| x.content.as_str())
+                .unwrap_or_default()
+        };
+        match self {
+            RouteRule::Hint(hint) => cfg.extend.get(ROUTE_HINT_KEY) == Some(hint),
+            RouteRule::MinChars(n) => query().chars().count() >= *n,
+            RouteRule::HasCode => has_code(query()),
+            RouteRule::Custom(f) => f(cfg, msg),
+        }
+    }
+}
+
+/// 代码块，或者至少两行看起来像代码的行
+fn has_code(text: &str) -> bool {
+    if text.contains("```") {
+        return true;
+    }
+    const PREFIX: [&str; 10] = [
+        "fn ", "pub ", "let ", "def ", "class ", "import ", "func ", "const ", "#include", "return ",
+    ];
+    text.lines()
+        .map(str::trim)
+        .filter(|x| {
+            x.ends_with(';') || x.ends_with('{') || *x == "}" || PREFIX.iter().any(|p| x.starts_with(p))
+        })
+        .count()
+        >= 2
+}
+
+/// 按规则为每次请求选择一个后端，都不匹配时使用默认的后端
+///
+/// ```ignore
+/// let model = RouterModel::new(QwenModel::default())
+///     .route(RouteRule::hint("local"), ModelTarget::new(OllamaModel::default()).set_name("qwen2.5"))
+///     .route(RouteRule::HasCode, ModelTarget::new(QwenModel::default()).set_name("qwen-coder-plus"));
+/// ```
+#[derive(Clone)]
+pub struct RouterModel {
+    pub routes: Vec<(RouteRule, ModelTarget)>,
+    pub default: ModelTarget,
+}
+
+impl RouterModel {
+    pub fn new<M: Model + Sync + 'static>(default: M) -> Self {
+        Self::from_target(ModelTarget::new(default))
+    }
+    pub fn from_target(default: ModelTarget) -> Self {
+        Self {
+            routes: vec![],
+            default,
+        }
+    }
+    pub fn route(mut self, rule: RouteRule, target: ModelTarget) -> Self {
+        self.routes.push((rule, target));
+        self
+    }
+    /// 先匹配Hint规则，再按顺序匹配其他规则
+    pub fn select(&self, cfg: &ModelConfig, msg: &[Message]) -> &ModelTarget {
+        let (hints, others): (Vec<_>, Vec<_>) =
+            self.routes.iter().partition(|x| matches!(x.0, RouteRule::Hint(_)));
+        hints
+            .into_iter()
+            .chain(others)
+            .find(|x| x.0.matches(cfg, msg))
+            .map(|x| &x.1)
+            .unwrap_or(&self.default)
+    }
+}
+
+#[async_trait::async_trait]
+impl Model for RouterModel {
+    async fn chat(&self, cfg: &ModelConfig, msg: &[Message]) -> anyhow::Result<Response> {
+        let target = self.select(cfg, msg);
+        let mut cfg = target.config(cfg);
+        cfg.extend.remove(ROUTE_HINT_KEY);
+        let mut stream = target.model.chat(&cfg, msg).await?;
+        //先告知选中的模型，再转发后端的回复
+        let resp = Response::default();
+        let sender = resp.sender.clone();
+        let task = tokio::spawn(async move {
+            let _ = sender.send(ResponseEvent::Model(cfg.name)).await;
+            let first = stream.next().await;
+            stream.forward(first, &sender).await;
+        });
+        Ok(resp.set_abort_handle(task.abort_handle()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::fallback::ModelTarget;
+    use crate::model::mock::MockModel;
+    use crate::model::router::{RouteRule, RouterModel};
+    use crate::model::{Message, Model, ModelConfig};
+
+    #[tokio::test]
+    async fn test_router_model() {
+        let (chat, coder, long, local) = (MockModel::new(), MockModel::new(), MockModel::new(), MockModel::new());
+        let model = RouterModel::new(chat.clone())
+            .route(RouteRule::HasCode, ModelTarget::new(coder.clone()).set_name("coder"))
+            .route(RouteRule::MinChars(20), ModelTarget::new(long.clone()))
+            .route(RouteRule::hint("local"), ModelTarget::new(local.clone()));
+        let cfg = ModelConfig::default();
+        let ask = |s: &str| vec![Message::new("user", s)];
+
+        model.chat(&cfg, &ask("你好")).await.unwrap().collect().await.unwrap();
+        assert_eq!(chat.records().len(), 1);
+        let code = "为什么报错\n```rust\nlet a = 1\n```";
+        model.chat(&cfg, &ask(code)).await.unwrap().collect().await.unwrap();
+        assert_eq!(coder.last_record().unwrap().config.name, "coder");
+        let code = "def add(a, b):\n    return a + b";
+        model.chat(&cfg, &ask(code)).await.unwrap().collect().await.unwrap();
+        assert_eq!(coder.records().len(), 2);
+        model.chat(&cfg, &ask("这是一段比较长的问题，需要交给长文本模型处理")).await.unwrap().collect().await.unwrap();
+        assert_eq!(long.records().len(), 1);
+
+        //hint优先，并且不会透传给后端
+        let cfg = ModelConfig::default().append_extend("route", "local");
+        model.chat(&cfg, &ask(code)).await.unwrap().collect().await.unwrap();
+        assert!(local.last_record().unwrap().config.extend.is_empty());
+        assert_eq!(coder.records().len(), 2);
+    }
+}
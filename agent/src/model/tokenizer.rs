@@ -0,0 +1,174 @@
+use crate::model::content::ContentPart;
+use crate::model::{Message, ModelConfig};
+use std::sync::Arc;
+
+/// 每条消息在角色、分隔符上的固定开销，参考openai chat格式
+const MESSAGE_TOKEN_OVERHEAD: usize = 4;
+/// 每张图片的估算开销，各家按分辨率计费，这里取常见尺寸的上限
+const IMAGE_TOKEN_COST: usize = 1024;
+/// 每个文件的估算开销，内容由服务商解析，无法在本地统计
+const FILE_TOKEN_COST: usize = 2048;
+/// 未知模型的上下文长度
+const DEFAULT_CONTEXT_SIZE: usize = 8192;
+
+/// 统计文本的token数
+pub trait Tokenizer: Send + Sync {
+    fn count(&self, text: &str) -> usize;
+    /// 一条消息的token数，包含工具调用、附加的内容片段和格式开销
+    fn count_message(&self, msg: &Message) -> usize {
+        let calls = msg
+            .tool_calls
+            .iter()
+            .map(|x| self.count(x.name.as_str()) + self.count(x.arguments.as_str()))
+            .sum::<usize>();
+        let parts = msg
+            .parts
+            .iter()
+            .map(|x| match x {
+                ContentPart::Text { text } => self.count(text.as_str()),
+                ContentPart::Image { .. } => IMAGE_TOKEN_COST,
+                ContentPart::File { .. } => FILE_TOKEN_COST,
+            })
+            .sum::<usize>();
+        MESSAGE_TOKEN_OVERHEAD + self.count(msg.content.as_str()) + calls + parts
+    }
+}
+
+/// 不依赖词表的快速估算，宁多勿少：
+/// ASCII单词约4个字符一个token，标点各算一个，非ASCII字符(中文等)每个字符算一个
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EstimateTokenizer;
+
+impl Tokenizer for EstimateTokenizer {
+    fn count(&self, text: &str) -> usize {
+        let mut tokens = 0usize;
+        let mut word = 0usize;
+        for c in text.chars() {
+            if c.is_ascii_alphanumeric() {
+                word += 1;
+                continue;
+            }
+            tokens += word.div_ceil(4);
+            word = 0;
+            if !c.is_whitespace() {
+                tokens += 1;
+            }
+        }
+        tokens + word.div_ceil(4)
+    }
+}
+
+/// openai系列模型的精确分词
+#[cfg(feature = "tiktoken")]
+#[derive(Clone)]
+pub struct TiktokenTokenizer {
+    bpe: Arc<tiktoken_rs::CoreBPE>,
+}
+
+#[cfg(feature = "tiktoken")]
+impl TiktokenTokenizer {
+    /// 不认识的模型返回None；词表加载较慢，同一种词表只加载一次
+    pub fn from_model(name: &str) -> Option<Self> {
+        use std::collections::HashMap;
+        use std::sync::{Mutex, OnceLock};
+        use tiktoken_rs::tokenizer::Tokenizer as Kind;
+        static CACHE: OnceLock<Mutex<HashMap<Kind, Arc<tiktoken_rs::CoreBPE>>>> = OnceLock::new();
+
+        let kind = tiktoken_rs::tokenizer::get_tokenizer(name)?;
+        let mut cache = CACHE.get_or_init(Default::default).lock().ok()?;
+        if let Some(bpe) = cache.get(&kind) {
+            return Some(Self { bpe: bpe.clone() });
+        }
+        let bpe = Arc::new(tiktoken_rs::get_bpe_from_tokenizer(kind).ok()?);
+        cache.insert(kind, bpe.clone());
+        Some(Self { bpe })
+    }
+}
+
+#[cfg(feature = "tiktoken")]
+impl Tokenizer for TiktokenTokenizer {
+    fn count(&self, text: &str) -> usize {
+        self.bpe.encode_ordinary(text).len()
+    }
+}
+
+/// 按模型名选择分词器，有精确词表时优先使用，否则退回估算
+pub fn tokenizer_for_model(name: &str) -> Arc<dyn Tokenizer> {
+    #[cfg(feature = "tiktoken")]
+    if let Some(t) = TiktokenTokenizer::from_model(name) {
+        return Arc::new(t);
+    }
+    #[cfg(not(feature = "tiktoken"))]
+    let _ = name;
+    Arc::new(EstimateTokenizer)
+}
+
+/// 模型的上下文长度，ModelConfig.context_size 不为0时优先使用
+pub fn context_size(cfg: &ModelConfig) -> usize {
+    if cfg.context_size > 0 {
+        return cfg.context_size;
+    }
+    let name = cfg.name.to_lowercase();
+    let known: &[(&str, usize)] = &[
+        ("qwen-long", 10_000_000),
+        ("qwen-turbo", 1_000_000),
+        ("qwen-plus", 131_072),
+        ("qwen-max", 32_768),
+        ("claude-", 200_000),
+        ("gemini-", 1_048_576),
+        ("llama3", 8_192),
+        ("gpt-4o", 128_000),
+        ("gpt-4-turbo", 128_000),
+        ("gpt-4", 8_192),
+        ("gpt-3.5-turbo", 16_385),
+        ("o1", 128_000),
+    ];
+    known
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map(|(_, size)| *size)
+        .unwrap_or(DEFAULT_CONTEXT_SIZE)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::tokenizer::{context_size, tokenizer_for_model, EstimateTokenizer, Tokenizer};
+    use crate::model::content::ContentPart;
+    use crate::model::{Message, ModelConfig};
+
+    #[test]
+    fn test_estimate_tokenizer() {
+        let t = EstimateTokenizer;
+        assert_eq!(t.count(""), 0);
+        assert_eq!(t.count("hello world"), 4);
+        assert_eq!(t.count("hi, rust!"), 4);
+        assert_eq!(t.count("你好世界"), 4);
+
+        let msg = Message::new_user("你好");
+        assert_eq!(t.count_message(&msg), 6);
+        let msg = msg
+            .append_part(ContentPart::text("hello world"))
+            .append_part(ContentPart::image_url("https://example.com/a.png"))
+            .append_part(ContentPart::file_path("a.pdf"));
+        assert_eq!(t.count_message(&msg), 6 + 4 + 1024 + 2048);
+    }
+
+    #[cfg(feature = "tiktoken")]
+    #[test]
+    fn test_tiktoken_tokenizer() {
+        let t = tokenizer_for_model("gpt-4o");
+        assert_eq!(t.count("hello world"), 2);
+        //未知模型退回估算
+        let t = tokenizer_for_model("qwen-turbo");
+        assert_eq!(t.count("hello world"), 4);
+    }
+
+    #[test]
+    fn test_context_size() {
+        assert_eq!(context_size(&ModelConfig::default().set_name("claude-3-5-sonnet")), 200_000);
+        assert_eq!(context_size(&ModelConfig::default().set_name("unknown")), 8192);
+        let mut cfg = ModelConfig::default().set_name("qwen-max");
+        cfg.context_size = 1024;
+        assert_eq!(context_size(&cfg), 1024);
+    }
+}
@@ -0,0 +1,121 @@
+mod template;
+
+pub use template::*;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// 内置变量，按需求值，自定义变量同名时优先
+pub const BUILTIN_VARS: &[&str] = &["date", "time", "datetime", "weekday", "os", "arch", "user", "clipboard"];
+/// 目录中可以作为partial加载的文件扩展名
+const PARTIAL_EXTENSIONS: &[&str] = &["md", "txt", "prompt", "tpl"];
+
+/// 模板渲染时的变量和partial
+#[derive(Debug, Default, Clone)]
+pub struct PromptEnv {
+    pub vars: HashMap<String, String>,
+    /// partial的模板原文，渲染时再解析
+    pub partials: HashMap<String, String>,
+    /// 关闭后内置变量全部为空，用于测试或不希望泄露本机信息的场景
+    pub disable_builtin: bool,
+}
+
+impl PromptEnv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set_var<K: Into<String>, V: Into<String>>(mut self, k: K, v: V) -> Self {
+        self.vars.insert(k.into(), v.into());
+        self
+    }
+    pub fn set_partial<K: Into<String>, V: Into<String>>(mut self, name: K, source: V) -> Self {
+        self.partials.insert(name.into(), source.into());
+        self
+    }
+    pub fn set_disable_builtin(mut self, disable: bool) -> Self {
+        self.disable_builtin = disable;
+        self
+    }
+    /// 以文件名(不含扩展名)为partial名，加载目录下的模板文件
+    pub fn load_partials<P: AsRef<Path>>(mut self, dir: P) -> anyhow::Result<Self> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let ext = path.extension().and_then(|x| x.to_str()).unwrap_or_default();
+            if !path.is_file() || !PARTIAL_EXTENSIONS.contains(&ext) {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|x| x.to_str()) else {
+                continue;
+            };
+            self.partials.insert(name.to_string(), std::fs::read_to_string(&path)?);
+        }
+        Ok(self)
+    }
+    pub fn get(&self, name: &str) -> Option<String> {
+        if let Some(v) = self.vars.get(name) {
+            return Some(v.clone());
+        }
+        if self.disable_builtin {
+            return None;
+        }
+        builtin_var(name)
+    }
+}
+
+/// 内置变量的当前值
+pub fn builtin_var(name: &str) -> Option<String> {
+    let now = chrono::Local::now();
+    let value = match name {
+        "date" => now.format("%Y-%m-%d").to_string(),
+        "time" => now.format("%H:%M:%S").to_string(),
+        "datetime" => now.format("%Y-%m-%d %H:%M:%S").to_string(),
+        "weekday" => now.format("%A").to_string(),
+        "os" => std::env::consts::OS.to_string(),
+        "arch" => std::env::consts::ARCH.to_string(),
+        "user" => std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .ok()?,
+        "clipboard" => clipboard_text()?,
+        _ => return None,
+    };
+    Some(value)
+}
+
+#[cfg(feature = "clipboard")]
+fn clipboard_text() -> Option<String> {
+    arboard::Clipboard::new().and_then(|mut x| x.get_text()).ok()
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn clipboard_text() -> Option<String> {
+    None
+}
+
+/// 解析并渲染，适合只用一次的模板
+pub fn render_prompt(source: &str, env: &PromptEnv) -> anyhow::Result<String> {
+    PromptTemplate::parse(source)?.render(env)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::prompt::{render_prompt, PromptEnv};
+
+    #[test]
+    fn test_prompt_env() {
+        let env = PromptEnv::new().set_var("os", "plan9");
+        let text = render_prompt("{{os}} {{date}}", &env).unwrap();
+        assert!(text.starts_with("plan9 20"));
+        assert_eq!(text.len(), "plan9 2024-01-01".len());
+        let env = env.set_disable_builtin(true);
+        assert_eq!(render_prompt("[{{date}}]", &env).unwrap(), "[]");
+
+        let dir = std::env::temp_dir().join(wd_tools::uuid::v4());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("rules.md"), "be brief").unwrap();
+        std::fs::write(dir.join("image.png"), "").unwrap();
+        let env = env.load_partials(&dir).unwrap();
+        assert_eq!(env.partials.len(), 1);
+        assert_eq!(render_prompt("{{> rules}}", &env).unwrap(), "be brief");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::prompt::PromptEnv;
+use std::path::Path;
+use wd_tools::PFErr;
+
+/// partial互相引用的最大深度，防止循环引用
+const MAX_PARTIAL_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Var(String),
+    If {
+        name: String,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Partial(String),
+}
+
+#[derive(Debug)]
+enum Token {
+    Text(String),
+    Var(String),
+    If(String),
+    Else,
+    EndIf,
+    Partial(String),
+    Comment,
+}
+
+impl Token {
+    /// 独占一行的块标签渲染后不留空行
+    fn is_block(&self) -> bool {
+        matches!(self, Token::If(_) | Token::Else | Token::EndIf | Token::Comment)
+    }
+}
+
+/// 提示词模板，语法：
+/// - `{{name}}` 变量，未定义时为空
+/// - `{{#if name}}...{{else}}...{{/if}}` 变量非空且不为false/0时成立
+/// - `{{> name}}` 引用partial
+/// - `{{! comment}}` 注释
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptTemplate {
+    nodes: Vec<Node>,
+}
+
+impl PromptTemplate {
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut stack: Vec<(String, Vec<Node>, Option<Vec<Node>>)> = vec![];
+        let mut nodes = vec![];
+        for token in tokens {
+            let node = match token {
+                Token::Text(s) => Node::Text(s),
+                Token::Var(s) => Node::Var(s),
+                Token::Partial(s) => Node::Partial(s),
+                Token::Comment => continue,
+                Token::If(name) => {
+                    stack.push((name, std::mem::take(&mut nodes), None));
+                    continue;
+                }
+                Token::Else => {
+                    match stack.last_mut() {
+                        Some((_, _, then @ None)) => *then = Some(std::mem::take(&mut nodes)),
+                        Some(_) => return anyhow::anyhow!("duplicate {{{{else}}}} in template").err(),
+                        None => return anyhow::anyhow!("{{{{else}}}} without {{{{#if}}}}").err(),
+                    }
+                    continue;
+                }
+                Token::EndIf => {
+                    let Some((name, parent, then)) = stack.pop() else {
+                        return anyhow::anyhow!("{{{{/if}}}} without {{{{#if}}}}").err();
+                    };
+                    let body = std::mem::replace(&mut nodes, parent);
+                    match then {
+                        Some(then) => Node::If { name, then, otherwise: body },
+                        None => Node::If { name, then: body, otherwise: vec![] },
+                    }
+                }
+            };
+            nodes.push(node);
+        }
+        if let Some((name, _, _)) = stack.last() {
+            return anyhow::anyhow!("{{{{#if {name}}}}} is not closed").err();
+        }
+        Ok(Self { nodes })
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Self::parse(std::fs::read_to_string(path)?.as_str())
+    }
+    pub fn render(&self, env: &PromptEnv) -> anyhow::Result<String> {
+        let mut buf = String::new();
+        render_nodes(&self.nodes, env, &mut buf, 0)?;
+        Ok(buf)
+    }
+}
+
+fn render_nodes(nodes: &[Node], env: &PromptEnv, buf: &mut String, depth: usize) -> anyhow::Result<()> {
+    for node in nodes {
+        match node {
+            Node::Text(s) => buf.push_str(s),
+            Node::Var(name) => buf.push_str(env.get(name).unwrap_or_default().as_str()),
+            Node::If { name, then, otherwise } => {
+                let value = env.get(name).unwrap_or_default();
+                let truthy = !matches!(value.trim(), "" | "false" | "0");
+                render_nodes(if truthy { then } else { otherwise }, env, buf, depth)?;
+            }
+            Node::Partial(name) => {
+                if depth >= MAX_PARTIAL_DEPTH {
+                    return anyhow::anyhow!("partial[{name}] nested too deep, maybe a cycle").err();
+                }
+                let Some(source) = env.partials.get(name) else {
+                    return anyhow::anyhow!("partial[{name}] not found").err();
+                };
+                let partial = PromptTemplate::parse(source)
+                    .map_err(|e| anyhow::anyhow!("parse partial[{name}] failed: {e}"))?;
+                render_nodes(&partial.nodes, env, buf, depth + 1)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset].matches('\n').count() + 1
+}
+
+fn tokenize(source: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut rest = 0;
+    while let Some(start) = source[rest..].find("{{").map(|x| x + rest) {
+        let Some(end) = source[start..].find("}}").map(|x| x + start) else {
+            return anyhow::anyhow!("unclosed tag at line {}", line_of(source, start)).err();
+        };
+        if start > rest {
+            tokens.push(Token::Text(source[rest..start].to_string()));
+        }
+        let tag = source[start + 2..end].trim();
+        let token = if tag.starts_with('!') {
+            Token::Comment
+        } else if let Some(name) = tag.strip_prefix("#if ") {
+            Token::If(check_name(name.trim(), source, start)?)
+        } else if tag == "else" {
+            Token::Else
+        } else if tag == "/if" {
+            Token::EndIf
+        } else if let Some(name) = tag.strip_prefix('>') {
+            Token::Partial(check_name(name.trim(), source, start)?)
+        } else {
+            Token::Var(check_name(tag, source, start)?)
+        };
+        tokens.push(token);
+        rest = end + 2;
+    }
+    if rest < source.len() {
+        tokens.push(Token::Text(source[rest..].to_string()));
+    }
+    trim_standalone(&mut tokens);
+    Ok(tokens)
+}
+
+fn check_name(name: &str, source: &str, offset: usize) -> anyhow::Result<String> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if !valid {
+        return anyhow::anyhow!("invalid tag name[{name}] at line {}", line_of(source, offset)).err();
+    }
+    Ok(name.to_string())
+}
+
+/// 块标签前后只有空白并且独占一行时，去掉这一行
+///
+/// 先在原始的token上计算每段文本保留的范围，最后统一裁剪，避免相邻的块标签互相影响
+fn trim_standalone(tokens: &mut [Token]) {
+    let last = tokens.len().saturating_sub(1);
+    let mut keep = tokens
+        .iter()
+        .map(|x| match x {
+            Token::Text(s) => (0, s.len()),
+            _ => (0, 0),
+        })
+        .collect::<Vec<_>>();
+    for i in 0..tokens.len() {
+        if !tokens[i].is_block() {
+            continue;
+        }
+        //本行开头到标签之间只有空白，返回该行开始的位置
+        let before = match i.checked_sub(1).map(|x| &tokens[x]) {
+            None => Some(None),
+            Some(Token::Text(s)) => {
+                let line_start = s.rfind('\n').map(|x| x + 1);
+                let at_line_start = line_start.is_some() || i == 1;
+                let line_start = line_start.unwrap_or(0);
+                (at_line_start && s[line_start..].trim().is_empty()).then_some(Some(line_start))
+            }
+            Some(_) => None,
+        };
+        //标签到本行结尾之间只有空白，返回下一行开始的位置
+        let after = match tokens.get(i + 1) {
+            None => Some(None),
+            Some(Token::Text(s)) => {
+                let line_end = s.find('\n').map(|x| x + 1);
+                let at_line_end = line_end.is_some() || i + 1 == last;
+                let line_end = line_end.unwrap_or(s.len());
+                (at_line_end && s[..line_end].trim().is_empty()).then_some(Some(line_end))
+            }
+            Some(_) => None,
+        };
+        let (Some(before), Some(after)) = (before, after) else {
+            continue;
+        };
+        if let Some(end) = before {
+            keep[i - 1].1 = end;
+        }
+        if let Some(start) = after {
+            keep[i + 1].0 = start;
+        }
+    }
+    for (token, (start, end)) in tokens.iter_mut().zip(keep) {
+        if let Token::Text(s) = token {
+            *s = if start < end { s[start..end].to_string() } else { String::new() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::prompt::{PromptEnv, PromptTemplate};
+
+    #[test]
+    fn test_prompt_template() {
+        let env = PromptEnv::new()
+            .set_var("name", "wd")
+            .set_var("lang", "rust")
+            .set_var("debug", "false")
+            .set_partial("rules", "- answer in {{lang}}\n");
+        let source = "## role: {{ name }}\n{{! comment }}\n{{#if lang}}\nlang: {{lang}}\n{{else}}\nno lang\n{{/if}}\n{{#if debug}}debug{{/if}}rules:\n{{> rules}}";
+        let text = PromptTemplate::parse(source).unwrap().render(&env).unwrap();
+        assert_eq!(text, "## role: wd\nlang: rust\nrules:\n- answer in rust\n");
+
+        let text = PromptTemplate::parse("a{{#if missing}}b{{else}}c{{/if}}d{{missing}}")
+            .unwrap()
+            .render(&env)
+            .unwrap();
+        assert_eq!(text, "acd");
+    }
+
+    #[test]
+    fn test_prompt_template_error() {
+        assert!(PromptTemplate::parse("{{name").is_err());
+        assert!(PromptTemplate::parse("{{#if a}}x").is_err());
+        assert!(PromptTemplate::parse("x{{/if}}").is_err());
+        assert!(PromptTemplate::parse("{{bad name}}").is_err());
+        let env = PromptEnv::new().set_partial("a", "{{> b}}").set_partial("b", "{{> a}}");
+        assert!(PromptTemplate::parse("{{> a}}").unwrap().render(&env).is_err());
+        assert!(PromptTemplate::parse("{{> none}}").unwrap().render(&env).is_err());
+    }
+}
@@ -0,0 +1,433 @@
+use crate::model::embedding::normalize;
+use crate::store::vector::{check_dimension, dot, read_index, write_index};
+use crate::store::{VectorHit, VectorIndex, VectorRecord};
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Scored(f32, usize);
+impl Eq for Scored {}
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HnswNode {
+    record: VectorRecord,
+    /// 每一层的邻居，下标为层号
+    neighbors: Vec<Vec<usize>>,
+    //删除只做标记，节点仍参与图的连通
+    #[serde(default)]
+    deleted: bool,
+}
+
+/// 分层可导航小世界图(HNSW)，近似检索，适合大量数据
+///
+/// 删除只打标记，被删除的节点不会出现在结果中；删除比例超过rebuild_ratio时自动重建，
+/// 保存时丢弃已删除的节点
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HnswIndex {
+    dimension: usize,
+    /// 每个节点在1层及以上的最大邻居数，0层为2m
+    m: usize,
+    ef_construction: usize,
+    pub ef_search: usize,
+    /// 已删除节点占全部节点的比例超过该值时重建
+    #[serde(default = "default_rebuild_ratio")]
+    pub rebuild_ratio: f32,
+    nodes: Vec<HnswNode>,
+    entry: Option<usize>,
+    //随机层高使用的xorshift状态，保存后重建的结果可复现
+    seed: u64,
+    #[serde(skip)]
+    ids: HashMap<String, usize>,
+}
+
+fn default_rebuild_ratio() -> f32 {
+    0.3
+}
+
+impl Default for HnswIndex {
+    fn default() -> Self {
+        Self::new(16, 200)
+    }
+}
+
+impl HnswIndex {
+    pub fn new(m: usize, ef_construction: usize) -> Self {
+        Self {
+            dimension: 0,
+            m: m.max(2),
+            ef_construction: ef_construction.max(1),
+            ef_search: 64,
+            rebuild_ratio: default_rebuild_ratio(),
+            nodes: vec![],
+            entry: None,
+            seed: 0x2545f4914f6cdd1d,
+            ids: HashMap::new(),
+        }
+    }
+    pub fn set_ef_search(mut self, ef: usize) -> Self {
+        self.ef_search = ef.max(1);
+        self
+    }
+    pub fn set_rebuild_ratio(mut self, ratio: f32) -> Self {
+        self.rebuild_ratio = ratio;
+        self
+    }
+    /// 文件不存在时返回默认参数的空索引
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let Some(mut index) = read_index::<Self>(path.as_ref())? else {
+            return Ok(Self::default());
+        };
+        index.ids = index
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| !x.deleted)
+            .map(|(i, x)| (x.record.id.clone(), i))
+            .collect();
+        Ok(index)
+    }
+    /// 丢弃已删除的节点，重新建图
+    pub fn rebuild(&mut self) {
+        let records = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .filter(|x| !x.deleted)
+            .map(|x| x.record)
+            .collect::<Vec<_>>();
+        self.entry = None;
+        self.ids.clear();
+        for i in records {
+            self.link(i);
+        }
+    }
+    fn deleted(&self) -> usize {
+        self.nodes.len() - self.ids.len()
+    }
+    /// 去掉已删除的节点，原来经过它们的连接改为连到它们的邻居上，再按相似度裁剪，不用重新建图
+    fn compacted(&self) -> Self {
+        let mut map = vec![usize::MAX; self.nodes.len()];
+        for (new, old) in self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|x| !x.1.deleted)
+            .map(|x| x.0)
+            .enumerate()
+        {
+            map[old] = new;
+        }
+        let mut index = Self {
+            dimension: self.dimension,
+            m: self.m,
+            ef_construction: self.ef_construction,
+            ef_search: self.ef_search,
+            rebuild_ratio: self.rebuild_ratio,
+            nodes: Vec::with_capacity(self.ids.len()),
+            entry: None,
+            seed: self.seed,
+            ids: HashMap::with_capacity(self.ids.len()),
+        };
+        for (old, node) in self.nodes.iter().enumerate().filter(|x| !x.1.deleted) {
+            let mut neighbors = Vec::with_capacity(node.neighbors.len());
+            for (layer, list) in node.neighbors.iter().enumerate() {
+                let mut set = HashSet::new();
+                for n in list.iter() {
+                    if !self.nodes[*n].deleted {
+                        set.insert(*n);
+                        continue;
+                    }
+                    let around = self.nodes[*n].neighbors.get(layer).into_iter().flatten();
+                    set.extend(around.filter(|x| **x != old && !self.nodes[**x].deleted));
+                }
+                let mut list = set
+                    .into_iter()
+                    .map(|x| Scored(dot(&node.record.vector, &self.nodes[x].record.vector), x))
+                    .collect::<Vec<_>>();
+                list.sort_by(|a, b| b.cmp(a));
+                let max = self.max_neighbors(layer);
+                neighbors.push(list.into_iter().take(max).map(|x| map[x.1]).collect());
+            }
+            index.ids.insert(node.record.id.clone(), index.nodes.len());
+            index.nodes.push(HnswNode {
+                record: node.record.clone(),
+                neighbors,
+                deleted: false,
+            });
+        }
+        //入口被删除时换成层数最高的节点
+        index.entry = match self.entry {
+            Some(e) if !self.nodes[e].deleted => Some(map[e]),
+            _ => (0..index.nodes.len()).max_by_key(|x| index.nodes[*x].neighbors.len()),
+        };
+        index
+    }
+    fn random_level(&mut self) -> usize {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        let uniform = (self.seed >> 11) as f64 / (1u64 << 53) as f64;
+        let ml = 1.0 / (self.m as f64).ln();
+        (-(1.0 - uniform).ln() * ml) as usize
+    }
+    fn max_neighbors(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.m * 2
+        } else {
+            self.m
+        }
+    }
+    fn score(&self, query: &[f32], node: usize) -> f32 {
+        dot(query, &self.nodes[node].record.vector)
+    }
+    fn top_level(&self) -> usize {
+        self.entry.map(|x| self.nodes[x].neighbors.len() - 1).unwrap_or(0)
+    }
+    /// 在一层中做最佳优先搜索，返回最相似的ef个节点，从高到低
+    fn search_layer(&self, query: &[f32], entry: &[usize], ef: usize, layer: usize) -> Vec<Scored> {
+        let mut visited = entry.iter().copied().collect::<HashSet<_>>();
+        let mut candidates = BinaryHeap::new();
+        let mut found = BinaryHeap::new();
+        for i in entry.iter() {
+            let s = Scored(self.score(query, *i), *i);
+            candidates.push(s);
+            found.push(Reverse(s));
+        }
+        while let Some(current) = candidates.pop() {
+            let worst = found.peek().map(|x| x.0 .0).unwrap_or(f32::MIN);
+            if found.len() >= ef && current.0 < worst {
+                break;
+            }
+            for n in self.nodes[current.1].neighbors[layer].iter() {
+                if !visited.insert(*n) {
+                    continue;
+                }
+                let s = Scored(self.score(query, *n), *n);
+                let worst = found.peek().map(|x| x.0 .0).unwrap_or(f32::MIN);
+                if found.len() < ef || s.0 > worst {
+                    candidates.push(s);
+                    found.push(Reverse(s));
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        let mut list = found.into_iter().map(|x| x.0).collect::<Vec<_>>();
+        list.sort_by(|a, b| b.cmp(a));
+        list
+    }
+    /// 从入口逐层贪心下降到target层
+    fn descend(&self, query: &[f32], target: usize) -> Option<usize> {
+        let mut ep = self.entry?;
+        for layer in (target + 1..=self.top_level()).rev() {
+            ep = self.search_layer(query, &[ep], 1, layer)[0].1;
+        }
+        Some(ep)
+    }
+    fn link(&mut self, record: VectorRecord) {
+        let level = self.random_level();
+        let idx = self.nodes.len();
+        let query = record.vector.clone();
+        self.ids.insert(record.id.clone(), idx);
+        self.nodes.push(HnswNode {
+            record,
+            neighbors: vec![vec![]; level + 1],
+            deleted: false,
+        });
+        let top = self.top_level();
+        let Some(ep) = self.descend(&query, level) else {
+            self.entry = Some(idx);
+            return;
+        };
+        let mut eps = vec![ep];
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(&query, &eps, self.ef_construction, layer);
+            let max = self.max_neighbors(layer);
+            let neighbors = found.iter().take(max).map(|x| x.1).collect::<Vec<_>>();
+            for n in neighbors.iter() {
+                let n = *n;
+                self.nodes[n].neighbors[layer].push(idx);
+                if self.nodes[n].neighbors[layer].len() > max {
+                    self.shrink(n, layer, max);
+                }
+            }
+            self.nodes[idx].neighbors[layer] = neighbors;
+            eps = found.iter().map(|x| x.1).collect();
+        }
+        if level > top {
+            self.entry = Some(idx);
+        }
+    }
+    /// 邻居超出上限时只保留最相似的max个
+    fn shrink(&mut self, node: usize, layer: usize, max: usize) {
+        let base = self.nodes[node].record.vector.clone();
+        let mut list = self.nodes[node].neighbors[layer]
+            .iter()
+            .map(|x| Scored(self.score(&base, *x), *x))
+            .collect::<Vec<_>>();
+        list.sort_by(|a, b| b.cmp(a));
+        self.nodes[node].neighbors[layer] = list.into_iter().take(max).map(|x| x.1).collect();
+    }
+}
+
+impl VectorIndex for HnswIndex {
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+    fn insert(&mut self, mut record: VectorRecord) -> anyhow::Result<()> {
+        check_dimension(self.dimension, &mut record)?;
+        self.dimension = record.vector.len();
+        self.remove(record.id.as_str());
+        self.link(record);
+        Ok(())
+    }
+    fn remove(&mut self, id: &str) -> bool {
+        let Some(idx) = self.ids.remove(id) else {
+            return false;
+        };
+        self.nodes[idx].deleted = true;
+        if self.deleted() as f32 > self.nodes.len() as f32 * self.rebuild_ratio {
+            self.rebuild();
+        }
+        true
+    }
+    fn search(&self, query: &[f32], k: usize) -> Vec<VectorHit> {
+        let mut query = query.to_vec();
+        normalize(&mut query);
+        let Some(ep) = self.descend(&query, 0) else {
+            return vec![];
+        };
+        //被删除的节点占用了名额，按删除数放大搜索范围；删除比例有上限，超过时会重建
+        let ef = self.ef_search.max(k) + self.deleted().min(self.ef_search * 4);
+        self.search_layer(&query, &[ep], ef, 0)
+            .into_iter()
+            .filter(|x| !self.nodes[x.1].deleted)
+            .take(k)
+            .map(|x| VectorHit {
+                score: x.0,
+                record: self.nodes[x.1].record.clone(),
+            })
+            .collect()
+    }
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if self.deleted() == 0 {
+            return write_index(path, self);
+        }
+        write_index(path, &self.compacted())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::embedding::normalize;
+    use crate::store::{FlatIndex, HnswIndex, VectorIndex, VectorRecord};
+
+    fn vector(seed: &mut u64, dim: usize) -> Vec<f32> {
+        (0..dim)
+            .map(|_| {
+                *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                ((*seed >> 33) as f32 / (1u64 << 31) as f32) - 0.5
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_hnsw_index() {
+        let mut seed = 7;
+        let mut hnsw = HnswIndex::new(8, 64);
+        let mut flat = FlatIndex::new();
+        for i in 0..500 {
+            let v = vector(&mut seed, 16);
+            hnsw.insert(VectorRecord::new(i.to_string(), "", v.clone())).unwrap();
+            flat.insert(VectorRecord::new(i.to_string(), "", v)).unwrap();
+        }
+        assert_eq!(hnsw.len(), 500);
+
+        //与精确检索对比召回率
+        let mut hit = 0;
+        for _ in 0..20 {
+            let q = vector(&mut seed, 16);
+            let expect = flat.search(&q, 10).into_iter().map(|x| x.record.id).collect::<Vec<_>>();
+            let result = hnsw.search(&q, 10);
+            assert!(result.windows(2).all(|x| x[0].score >= x[1].score));
+            hit += result.iter().filter(|x| expect.contains(&x.record.id)).count();
+        }
+        assert!(hit >= 180, "recall too low: {hit}/200");
+
+        let mut q = vector(&mut seed, 16);
+        hnsw.insert(VectorRecord::new("target", "target", q.clone())).unwrap();
+        normalize(&mut q);
+        assert_eq!(hnsw.search(&q, 1)[0].record.id, "target");
+        assert!(hnsw.remove("target"));
+        assert_ne!(hnsw.search(&q, 1)[0].record.id, "target");
+
+        let path = std::env::temp_dir().join(format!("{}.json", wd_tools::uuid::v4()));
+        hnsw.save(path.as_path()).unwrap();
+        let mut load = HnswIndex::open(path.as_path()).unwrap();
+        assert_eq!(load.len(), 500);
+        //保存时丢弃已删除的节点
+        assert_eq!(load.nodes.len(), 500);
+        assert_eq!(load.search(&q, 3), hnsw.search(&q, 3));
+        load.rebuild();
+        assert_eq!(load.len(), 500);
+        assert!(load.search(&q, 1)[0].record.id != "target");
+
+        //删除比例超过阈值时自动重建
+        for i in 0..150 {
+            assert!(load.remove(i.to_string().as_str()));
+        }
+        assert_eq!(load.nodes.len(), 500);
+        assert!(load.remove("150"));
+        assert_eq!((load.len(), load.nodes.len()), (349, 349));
+        let q = vector(&mut seed, 16);
+        let expect = flat.search(&q, 200).into_iter().map(|x| x.record.id);
+        let expect = expect
+            .filter(|x| x.parse::<usize>().unwrap() > 150)
+            .take(5)
+            .collect::<Vec<_>>();
+        let result = load.search(&q, 5).into_iter().map(|x| x.record.id).collect::<Vec<_>>();
+        assert!(
+            result.iter().filter(|x| expect.contains(x)).count() >= 4,
+            "{result:?} {expect:?}"
+        );
+
+        //不自动重建时，保存后删除节点的邻居仍然连通
+        let mut hnsw = hnsw.set_rebuild_ratio(1.0);
+        for i in (0..500).step_by(2) {
+            hnsw.remove(i.to_string().as_str());
+        }
+        hnsw.save(path.as_path()).unwrap();
+        let load = HnswIndex::open(path.as_path()).unwrap();
+        let _ = std::fs::remove_file(path);
+        assert_eq!((load.len(), load.nodes.len()), (250, 250));
+        let mut hit = 0;
+        for _ in 0..20 {
+            let q = vector(&mut seed, 16);
+            let expect = flat.search(&q, 40).into_iter().map(|x| x.record.id);
+            let expect = expect
+                .filter(|x| x.parse::<usize>().unwrap() % 2 == 1)
+                .take(10)
+                .collect::<Vec<_>>();
+            hit += load
+                .search(&q, 10)
+                .iter()
+                .filter(|x| expect.contains(&x.record.id))
+                .count();
+        }
+        assert!(hit >= 180, "recall too low after compaction: {hit}/200");
+    }
+}
@@ -0,0 +1,147 @@
+use crate::model::Message;
+use crate::store::{check_id, now_millis, Conversation, ConversationStore};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct JsonConversationFile {
+    conversation: Conversation,
+    messages: Vec<Message>,
+}
+
+/// 每个会话一个json文件：`{dir}/{id}.json`
+#[derive(Debug)]
+pub struct JsonFileStore {
+    dir: PathBuf,
+    //读改写需要串行，避免并发追加时丢消息
+    lock: Mutex<()>,
+}
+
+impl JsonFileStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            lock: Mutex::new(()),
+        }
+    }
+    fn path(&self, id: &str) -> anyhow::Result<PathBuf> {
+        check_id(id)?;
+        Ok(self.dir.join(format!("{id}.json")))
+    }
+    async fn read(path: &Path) -> anyhow::Result<Option<JsonConversationFile>> {
+        match tokio::fs::read(path).await {
+            Ok(body) => Ok(Some(serde_json::from_slice(body.as_slice())?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+    async fn write(&self, path: &Path, file: &JsonConversationFile) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(self.dir.as_path()).await?;
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(file)?).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl ConversationStore for JsonFileStore {
+    async fn list(&self) -> anyhow::Result<Vec<Conversation>> {
+        let mut dir = match tokio::fs::read_dir(self.dir.as_path()).await {
+            Ok(o) => o,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut list = vec![];
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|x| x.to_str()) != Some("json") {
+                continue;
+            }
+            match Self::read(path.as_path()).await {
+                Ok(Some(file)) => list.push(file.conversation),
+                Ok(None) => {}
+                Err(e) => wd_log::log_field("path", path.display())
+                    .field("error", e.to_string())
+                    .warn("JsonFileStore.list skip a broken file"),
+            }
+        }
+        list.sort_by_key(|x| std::cmp::Reverse(x.updated_at));
+        Ok(list)
+    }
+
+    async fn load(&self, id: &str) -> anyhow::Result<Vec<Message>> {
+        let path = self.path(id)?;
+        let file = Self::read(path.as_path()).await?;
+        Ok(file.map(|x| x.messages).unwrap_or_default())
+    }
+
+    async fn append(&self, id: &str, messages: &[Message]) -> anyhow::Result<()> {
+        let path = self.path(id)?;
+        let _guard = self.lock.lock().await;
+        let mut file = match Self::read(path.as_path()).await? {
+            Some(o) => o,
+            None => JsonConversationFile {
+                conversation: Conversation::new(id, messages),
+                messages: vec![],
+            },
+        };
+        if file.conversation.title.is_empty() {
+            file.conversation.title = Conversation::new(id, messages).title;
+        }
+        file.conversation.updated_at = now_millis();
+        file.messages.extend_from_slice(messages);
+        self.write(path.as_path(), &file).await
+    }
+
+    async fn delete(&self, id: &str) -> anyhow::Result<()> {
+        let path = self.path(id)?;
+        let _guard = self.lock.lock().await;
+        match tokio::fs::remove_file(path).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::Message;
+    use crate::store::{ConversationStore, JsonFileStore};
+
+    #[tokio::test]
+    async fn test_json_file_store() {
+        let dir = std::env::temp_dir().join(wd_tools::uuid::v4());
+        let store = JsonFileStore::new(&dir);
+        assert!(store.list().await.unwrap().is_empty());
+        assert!(store.load("c1").await.unwrap().is_empty());
+
+        store
+            .append("c1", &[Message::new_user("hello"), Message::new_assistant("hi")])
+            .await
+            .unwrap();
+        store
+            .append("c1", &[Message::new_user("again")])
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        store.append("c2", &[Message::new_user("second")]).await.unwrap();
+
+        let list = store.load("c1").await.unwrap();
+        let list = list.iter().map(|x| x.content.as_str()).collect::<Vec<_>>();
+        assert_eq!(list, vec!["hello", "hi", "again"]);
+
+        let conversations = store.list().await.unwrap();
+        assert_eq!(conversations.len(), 2);
+        assert_eq!(conversations[0].id, "c2");
+        assert_eq!(conversations[1].title, "hello");
+
+        store.delete("c1").await.unwrap();
+        store.delete("c1").await.unwrap();
+        assert_eq!(store.list().await.unwrap().len(), 1);
+        assert!(store.load("../c2").await.is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,96 @@
+mod hnsw;
+mod json;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod vector;
+
+pub use hnsw::*;
+pub use json::*;
+#[cfg(feature = "sqlite")]
+pub use sqlite::*;
+pub use vector::*;
+
+use crate::model::Message;
+use serde::{Deserialize, Serialize};
+use wd_tools::PFErr;
+
+/// 会话的基本信息，时间为unix毫秒
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Conversation {
+    pub id: String,
+    /// 取第一条用户消息的开头
+    pub title: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl Conversation {
+    pub fn new<S: Into<String>>(id: S, first: &[Message]) -> Self {
+        let now = now_millis();
+        Self {
+            id: id.into(),
+            title: title_of(first),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+/// 会话持久化的后端
+#[async_trait::async_trait]
+pub trait ConversationStore: Send + Sync {
+    /// 按最近更新时间倒序
+    async fn list(&self) -> anyhow::Result<Vec<Conversation>>;
+    /// 会话不存在时返回空列表
+    async fn load(&self, id: &str) -> anyhow::Result<Vec<Message>>;
+    /// 在会话末尾追加消息，会话不存在时自动创建
+    async fn append(&self, id: &str, messages: &[Message]) -> anyhow::Result<()>;
+    /// 删除会话和其中的消息，会话不存在时视为成功
+    async fn delete(&self, id: &str) -> anyhow::Result<()>;
+}
+
+/// 同一个store可以被多个agent共享
+#[async_trait::async_trait]
+impl<T: ConversationStore + ?Sized> ConversationStore for std::sync::Arc<T> {
+    async fn list(&self) -> anyhow::Result<Vec<Conversation>> {
+        self.as_ref().list().await
+    }
+    async fn load(&self, id: &str) -> anyhow::Result<Vec<Message>> {
+        self.as_ref().load(id).await
+    }
+    async fn append(&self, id: &str, messages: &[Message]) -> anyhow::Result<()> {
+        self.as_ref().append(id, messages).await
+    }
+    async fn delete(&self, id: &str) -> anyhow::Result<()> {
+        self.as_ref().delete(id).await
+    }
+}
+
+/// id会拼到文件路径中，只允许安全的字符
+pub fn check_id(id: &str) -> anyhow::Result<()> {
+    let valid = !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return anyhow::anyhow!("invalid id[{id}]").err();
+    }
+    Ok(())
+}
+
+pub(crate) fn now_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+const CONVERSATION_TITLE_LEN: usize = 32;
+
+fn title_of(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .find(|x| matches!(x.role, crate::model::MessageType::User))
+        .map(|x| x.content.trim().chars().take(CONVERSATION_TITLE_LEN).collect())
+        .unwrap_or_default()
+}
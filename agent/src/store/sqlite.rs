@@ -0,0 +1,193 @@
+use crate::model::Message;
+use crate::store::{now_millis, Conversation, ConversationStore};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const SQLITE_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS conversation (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS message (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    conversation_id TEXT NOT NULL REFERENCES conversation(id) ON DELETE CASCADE,
+    body TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS message_conversation ON message(conversation_id, id);
+PRAGMA foreign_keys = ON;
+";
+
+/// 嵌入式sqlite存储，消息以json保存在message.body中
+///
+/// rusqlite是同步接口，所有操作都放到blocking线程中执行
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Self::init(Connection::open(path)?)
+    }
+    /// 不落盘，进程结束后丢失，用于测试
+    pub fn memory() -> anyhow::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+    fn init(conn: Connection) -> anyhow::Result<Self> {
+        conn.execute_batch(SQLITE_SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+    async fn call<T: Send + 'static>(
+        &self,
+        handle: impl FnOnce(&mut Connection) -> anyhow::Result<T> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|e| anyhow::anyhow!("sqlite connection poisoned: {e}"))?;
+            handle(&mut conn)
+        })
+        .await?
+    }
+}
+
+#[async_trait::async_trait]
+impl ConversationStore for SqliteStore {
+    async fn list(&self) -> anyhow::Result<Vec<Conversation>> {
+        self.call(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, title, created_at, updated_at FROM conversation ORDER BY updated_at DESC",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok(Conversation {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    created_at: row.get(2)?,
+                    updated_at: row.get(3)?,
+                })
+            })?;
+            Ok(rows.collect::<Result<Vec<_>, _>>()?)
+        })
+        .await
+    }
+
+    async fn load(&self, id: &str) -> anyhow::Result<Vec<Message>> {
+        let id = id.to_string();
+        self.call(move |conn| {
+            let mut stmt =
+                conn.prepare("SELECT body FROM message WHERE conversation_id = ?1 ORDER BY id")?;
+            let rows = stmt.query_map(params![id], |row| row.get::<_, String>(0))?;
+            let mut list = vec![];
+            for body in rows {
+                list.push(serde_json::from_str::<Message>(body?.as_str())?);
+            }
+            Ok(list)
+        })
+        .await
+    }
+
+    async fn append(&self, id: &str, messages: &[Message]) -> anyhow::Result<()> {
+        let conversation = Conversation::new(id, messages);
+        let bodies = messages
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let title = tx
+                .query_row(
+                    "SELECT title FROM conversation WHERE id = ?1",
+                    params![conversation.id],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?;
+            match title {
+                None => {
+                    tx.execute(
+                        "INSERT INTO conversation (id, title, created_at, updated_at) VALUES (?1, ?2, ?3, ?4)",
+                        params![
+                            conversation.id,
+                            conversation.title,
+                            conversation.created_at,
+                            conversation.updated_at
+                        ],
+                    )?;
+                }
+                Some(title) => {
+                    let title = if title.is_empty() { conversation.title } else { title };
+                    tx.execute(
+                        "UPDATE conversation SET title = ?2, updated_at = ?3 WHERE id = ?1",
+                        params![conversation.id, title, now_millis()],
+                    )?;
+                }
+            }
+            for body in bodies {
+                tx.execute(
+                    "INSERT INTO message (conversation_id, body) VALUES (?1, ?2)",
+                    params![conversation.id, body],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete(&self, id: &str) -> anyhow::Result<()> {
+        let id = id.to_string();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute("DELETE FROM message WHERE conversation_id = ?1", params![id])?;
+            tx.execute("DELETE FROM conversation WHERE id = ?1", params![id])?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::{Message, MessageType};
+    use crate::store::{ConversationStore, SqliteStore};
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let store = SqliteStore::memory().unwrap();
+        assert!(store.list().await.unwrap().is_empty());
+
+        store
+            .append("c1", &[Message::new_system("sys"), Message::new_user("hello")])
+            .await
+            .unwrap();
+        store
+            .append("c1", &[Message::new_assistant("hi")])
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        store.append("c2", &[Message::new_user("second")]).await.unwrap();
+
+        let list = store.load("c1").await.unwrap();
+        assert_eq!(list.len(), 3);
+        assert!(matches!(list[2].role, MessageType::Assistant));
+        assert_eq!(list[2].content, "hi");
+
+        let conversations = store.list().await.unwrap();
+        assert_eq!(conversations.len(), 2);
+        assert_eq!(conversations[0].id, "c2");
+        assert_eq!(conversations[1].title, "hello");
+
+        store.delete("c1").await.unwrap();
+        assert!(store.load("c1").await.unwrap().is_empty());
+        assert_eq!(store.list().await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::model::embedding::normalize;
+use crate::store::now_millis;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use wd_tools::PFErr;
+
+/// 向量索引中的一条记录，vector在写入时归一化
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorRecord {
+    pub id: String,
+    pub text: String,
+    pub vector: Vec<f32>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, String>,
+    pub created_at: i64,
+}
+
+impl VectorRecord {
+    pub fn new<I: Into<String>, T: Into<String>>(id: I, text: T, vector: Vec<f32>) -> Self {
+        Self {
+            id: id.into(),
+            text: text.into(),
+            vector,
+            metadata: HashMap::new(),
+            created_at: now_millis(),
+        }
+    }
+    pub fn set_metadata<K: Into<String>, V: Into<String>>(mut self, k: K, v: V) -> Self {
+        self.metadata.insert(k.into(), v.into());
+        self
+    }
+}
+
+/// 检索结果，score为余弦相似度
+#[derive(Debug, Clone, PartialEq)]
+pub struct VectorHit {
+    pub score: f32,
+    pub record: VectorRecord,
+}
+
+/// 进程内的向量索引
+pub trait VectorIndex: Send + Sync {
+    /// 向量维度，写入第一条记录之前为0
+    fn dimension(&self) -> usize;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// id已存在时覆盖，维度与索引不一致时返回错误
+    fn insert(&mut self, record: VectorRecord) -> anyhow::Result<()>;
+    /// 返回是否删除了记录
+    fn remove(&mut self, id: &str) -> bool;
+    /// 按相似度从高到低返回最多k条
+    fn search(&self, query: &[f32], k: usize) -> Vec<VectorHit>;
+    /// 持久化到文件，可以用对应实现的open恢复
+    fn save(&self, path: &Path) -> anyhow::Result<()>;
+}
+
+pub(crate) fn check_dimension(dimension: usize, record: &mut VectorRecord) -> anyhow::Result<()> {
+    if record.vector.is_empty() {
+        return anyhow::anyhow!("vector of record[{}] is empty", record.id).err();
+    }
+    if dimension != 0 && dimension != record.vector.len() {
+        return anyhow::anyhow!(
+            "vector dimension[{}] not match index dimension[{dimension}]",
+            record.vector.len()
+        )
+        .err();
+    }
+    normalize(&mut record.vector);
+    Ok(())
+}
+
+pub(crate) fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
+
+/// 先写临时文件再重命名
+pub(crate) fn write_index<T: Serialize>(path: &Path, index: &T) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec(index)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// 文件不存在时返回None
+pub(crate) fn read_index<T: DeserializeOwned>(path: &Path) -> anyhow::Result<Option<T>> {
+    match std::fs::read(path) {
+        Ok(body) => Ok(Some(serde_json::from_slice(body.as_slice())?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 暴力检索，结果精确，适合几万条以内的数据
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FlatIndex {
+    dimension: usize,
+    records: Vec<VectorRecord>,
+}
+
+impl FlatIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// 文件不存在时返回空索引
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Ok(read_index(path.as_ref())?.unwrap_or_default())
+    }
+}
+
+impl VectorIndex for FlatIndex {
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+    fn len(&self) -> usize {
+        self.records.len()
+    }
+    fn insert(&mut self, mut record: VectorRecord) -> anyhow::Result<()> {
+        check_dimension(self.dimension, &mut record)?;
+        self.dimension = record.vector.len();
+        self.remove(record.id.as_str());
+        self.records.push(record);
+        Ok(())
+    }
+    fn remove(&mut self, id: &str) -> bool {
+        let len = self.records.len();
+        self.records.retain(|x| x.id != id);
+        len != self.records.len()
+    }
+    fn search(&self, query: &[f32], k: usize) -> Vec<VectorHit> {
+        let mut query = query.to_vec();
+        normalize(&mut query);
+        let mut list = self
+            .records
+            .iter()
+            .map(|x| (dot(&query, &x.vector), x))
+            .collect::<Vec<_>>();
+        list.sort_by(|a, b| b.0.total_cmp(&a.0));
+        list.into_iter()
+            .take(k)
+            .map(|(score, record)| VectorHit {
+                score,
+                record: record.clone(),
+            })
+            .collect()
+    }
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        write_index(path, self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::store::{FlatIndex, VectorIndex, VectorRecord};
+
+    #[test]
+    fn test_flat_index() {
+        let mut index = FlatIndex::new();
+        index.insert(VectorRecord::new("a", "a", vec![1.0, 0.0])).unwrap();
+        index.insert(VectorRecord::new("b", "b", vec![1.0, 1.0])).unwrap();
+        index.insert(VectorRecord::new("c", "c", vec![0.0, 2.0])).unwrap();
+        assert!(index.insert(VectorRecord::new("d", "d", vec![1.0])).is_err());
+
+        let hits = index.search(&[2.0, 0.1], 2);
+        let ids = hits.iter().map(|x| x.record.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert!(hits[0].score > 0.99);
+
+        //覆盖同一个id
+        index.insert(VectorRecord::new("a", "a2", vec![0.0, 1.0])).unwrap();
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.search(&[0.0, 1.0], 1)[0].record.id, "c");
+
+        let path = std::env::temp_dir().join(format!("{}.json", wd_tools::uuid::v4()));
+        index.save(path.as_path()).unwrap();
+        let mut load = FlatIndex::open(path.as_path()).unwrap();
+        assert_eq!(load.len(), 3);
+        assert!(load.remove("c"));
+        assert!(!load.remove("c"));
+        assert_eq!(load.search(&[0.0, 1.0], 1)[0].record.text, "a2");
+        let _ = std::fs::remove_file(path);
+    }
+}
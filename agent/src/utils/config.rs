@@ -0,0 +1,52 @@
+use serde::de::DeserializeOwned;
+use std::fmt::Display;
+use std::path::Path;
+use wd_tools::PFErr;
+
+/// 配置文件支持的扩展名
+pub const CONFIG_EXTENSIONS: &[&str] = &["toml", "yaml", "yml"];
+
+/// 校验失败时指出出错的键，例如 `model.temperature: must be in [0, 2]`
+pub(crate) fn invalid<K: Display, M: Display>(key: K, msg: M) -> anyhow::Error {
+    anyhow::anyhow!("{key}: {msg}")
+}
+
+fn deserialize<'de, T, D>(de: D) -> anyhow::Result<T>
+where
+    T: DeserializeOwned,
+    D: serde::Deserializer<'de>,
+    D::Error: Display,
+{
+    serde_path_to_error::deserialize(de).map_err(|e| {
+        let path = e.path().to_string();
+        let msg = e.inner().to_string();
+        //yaml的错误信息本身已经带了路径
+        if path == "." || msg.starts_with(format!("{path}: ").as_str()) {
+            anyhow::anyhow!("{msg}")
+        } else {
+            invalid(path, msg)
+        }
+    })
+}
+
+/// 反序列化失败时错误信息以出错的键开头
+pub fn from_toml<T: DeserializeOwned>(source: &str) -> anyhow::Result<T> {
+    deserialize(toml::Deserializer::new(source))
+}
+
+pub fn from_yaml<T: DeserializeOwned>(source: &str) -> anyhow::Result<T> {
+    deserialize(serde_yaml::Deserializer::from_str(source))
+}
+
+/// 按扩展名选择格式，错误信息带上文件路径
+pub fn load_config<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> anyhow::Result<T> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path)?;
+    let ext = path.extension().and_then(|x| x.to_str()).unwrap_or_default();
+    let value = match ext {
+        "toml" => from_toml(source.as_str()),
+        "yaml" | "yml" => from_yaml(source.as_str()),
+        _ => return anyhow::anyhow!("{}: unsupported config format[{ext}]", path.display()).err(),
+    };
+    value.map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))
+}
@@ -1,44 +1,106 @@
-use bytes::Bytes;
+use crate::model::error::{retry_after, ModelError};
+use crate::utils::{LineDecoder, SseDecoder, SseEvent, StreamDecoder};
 use reqwest::{Method, RequestBuilder};
+use serde::de::DeserializeOwned;
 use std::future::Future;
-use wd_tools::AsBytes;
+use tokio::task::AbortHandle;
 
-pub async fn sse<F: Future<Output = bool> + Send,CTX:Send+'static>(
+/// 普通请求，非2xx状态码返回分类后的ModelError，否则将body按json解析
+pub async fn json<T: DeserializeOwned>(
     method: Method,
     url: &str,
     builder: impl FnOnce(RequestBuilder) -> RequestBuilder,
-    mut ctx:CTX,
-    stream_handle: impl Fn(&mut CTX,anyhow::Result<String>) -> F + Send + 'static,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<T> {
     let mut req = reqwest::Client::new().request(method, url);
     req = builder(req);
-    let mut resp = req.send().await?;
-    tokio::spawn(async move {
-        while let result = resp.chunk().await {
-            let opt = match result {
-                Ok(o) => o,
+    let resp = req.send().await.map_err(ModelError::from)?;
+    let status = resp.status();
+    let retry = retry_after(resp.headers());
+    let body = resp.bytes().await.map_err(ModelError::from)?;
+    let body = String::from_utf8_lossy(body.as_ref());
+    if !status.is_success() {
+        return Err(ModelError::from_status(status.as_u16(), retry, body.as_ref()).into());
+    }
+    let value = serde_json::from_str::<T>(body.as_ref()).map_err(|_| ModelError::from_body(body.as_ref()))?;
+    Ok(value)
+}
+
+/// 非2xx状态码时读取body并分类
+async fn check_status(resp: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let retry = retry_after(resp.headers());
+    let body = resp.text().await.unwrap_or_default();
+    Err(ModelError::from_status(status.as_u16(), retry, body.as_str()).into())
+}
+
+/// 以server-sent events方式读取响应，每个完整事件回调一次
+pub async fn sse<F: Future<Output = bool> + Send, CTX: Send + 'static>(
+    method: Method,
+    url: &str,
+    builder: impl FnOnce(RequestBuilder) -> RequestBuilder,
+    ctx: CTX,
+    stream_handle: impl Fn(&mut CTX, anyhow::Result<SseEvent>) -> F + Send + 'static,
+) -> anyhow::Result<AbortHandle> {
+    stream(method, url, builder, SseDecoder::default(), ctx, stream_handle).await
+}
+
+/// 以换行分隔的json(ndjson)方式读取响应，每行回调一次
+pub async fn ndjson<F: Future<Output = bool> + Send, CTX: Send + 'static>(
+    method: Method,
+    url: &str,
+    builder: impl FnOnce(RequestBuilder) -> RequestBuilder,
+    ctx: CTX,
+    stream_handle: impl Fn(&mut CTX, anyhow::Result<String>) -> F + Send + 'static,
+) -> anyhow::Result<AbortHandle> {
+    stream(method, url, builder, LineDecoder::default(), ctx, stream_handle).await
+}
+
+/// 发送请求后在后台读取body，交给decoder切分后逐条回调
+///
+/// 连接失败和非2xx状态码直接返回分类后的ModelError，此时还没有任何回调
+///
+/// stream_handle 返回false时停止读取；
+/// 如果body读完时handle仍未要求停止，说明服务端提前断开，会回调一个错误
+///
+/// 返回后台任务的AbortHandle，abort后连接随任务一起释放
+pub async fn stream<D: StreamDecoder, F: Future<Output = bool> + Send, CTX: Send + 'static>(
+    method: Method,
+    url: &str,
+    builder: impl FnOnce(RequestBuilder) -> RequestBuilder,
+    mut decoder: D,
+    mut ctx: CTX,
+    stream_handle: impl Fn(&mut CTX, anyhow::Result<D::Item>) -> F + Send + 'static,
+) -> anyhow::Result<AbortHandle> {
+    let mut req = reqwest::Client::new().request(method, url);
+    req = builder(req);
+    let resp = req.send().await.map_err(ModelError::from)?;
+    let mut resp = check_status(resp).await?;
+    let task = tokio::spawn(async move {
+        loop {
+            let bytes = match resp.chunk().await {
+                Ok(Some(o)) => o,
+                Ok(None) => break,
                 Err(e) => {
-                    stream_handle(&mut ctx,Err(anyhow::Error::from(e))).await;
-                    break;
+                    stream_handle(&mut ctx, Err(ModelError::from(e).into())).await;
+                    return;
                 }
             };
-
-            let resp_str = if let Some(bytes) = opt {
-                String::from_utf8_lossy(bytes.as_byte()).to_string()
-                // if !stream_handle(Ok(bytes)).await {
-                //     break;
-                // }
-            } else {
-                stream_handle(&mut ctx,Ok(String::new())).await;
-                break;
-            };
-            let list = resp_str.split("\n").collect::<Vec<&str>>();
-            for i in list {
-                if !stream_handle(&mut ctx,Ok(i.to_string())).await {
-                    return
+            for i in decoder.feed(bytes.as_ref()) {
+                if !stream_handle(&mut ctx, Ok(i)).await {
+                    return;
                 }
             }
         }
+        if let Some(i) = decoder.finish() {
+            if !stream_handle(&mut ctx, Ok(i)).await {
+                return;
+            }
+        }
+        let closed = ModelError::Network("stream closed before the response was completed".into());
+        stream_handle(&mut ctx, Err(closed.into())).await;
     });
-    Ok(())
+    Ok(task.abort_handle())
 }
@@ -0,0 +1,97 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// 测试用的本地http服务，对所有请求都回复同样的内容
+pub async fn mock_http_server(content_type: &'static str, body: &'static str) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("mock server bind failed");
+    let addr = listener.local_addr().expect("mock server addr failed");
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let _ = read_request(&mut stream).await;
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nConnection: close\r\n\r\n"
+                );
+                let _ = stream.write_all(head.as_bytes()).await;
+                let _ = stream.write_all(body.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+    addr
+}
+
+/// 按路径(不含query)回复不同的内容，没有匹配的路径回复404
+///
+/// 返回收到的请求，每一项为 `METHOD PATH BODY`
+pub async fn mock_http_routes(
+    routes: Vec<(&'static str, &'static str, &'static str)>,
+) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("mock server bind failed");
+    let addr = listener.local_addr().expect("mock server addr failed");
+    let requests = Arc::new(Mutex::new(vec![]));
+    let records = requests.clone();
+    let routes = Arc::new(routes);
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let (routes, records) = (routes.clone(), records.clone());
+            tokio::spawn(async move {
+                let Ok((head, body)) = read_request(&mut stream).await else {
+                    return;
+                };
+                let mut line = head.lines().next().unwrap_or_default().split(' ');
+                let method = line.next().unwrap_or_default();
+                let path = line.next().unwrap_or_default().split('?').next().unwrap_or_default();
+                let route = routes.iter().find(|x| x.0 == path);
+                records
+                    .lock()
+                    .unwrap()
+                    .push(format!("{method} {path} {}", String::from_utf8_lossy(body.as_slice())));
+                let head = match route {
+                    Some((_, content_type, _)) => {
+                        format!("HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nConnection: close\r\n\r\n")
+                    }
+                    None => "HTTP/1.1 404 Not Found\r\nConnection: close\r\n\r\n".to_string(),
+                };
+                let _ = stream.write_all(head.as_bytes()).await;
+                if let Some((_, _, body)) = route {
+                    let _ = stream.write_all(body.as_bytes()).await;
+                }
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+    (addr, requests)
+}
+
+/// 返回 (请求头, 请求体)
+async fn read_request(stream: &mut tokio::net::TcpStream) -> anyhow::Result<(String, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok((String::new(), buf));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+            continue;
+        };
+        let head = String::from_utf8_lossy(&buf[..pos]).to_string();
+        let length = head
+            .to_lowercase()
+            .lines()
+            .find_map(|l| l.strip_prefix("content-length:"))
+            .and_then(|l| l.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        if buf.len() >= pos + 4 + length {
+            return Ok((head, buf.split_off(pos + 4)));
+        }
+    }
+}
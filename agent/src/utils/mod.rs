@@ -1,3 +1,11 @@
+mod config;
 mod http_stream;
+#[cfg(test)]
+mod mock_server;
+mod sse;
 
+pub use config::*;
 pub use http_stream::*;
+#[cfg(test)]
+pub use mock_server::*;
+pub use sse::*;
@@ -0,0 +1,208 @@
+/// 一个完整的server-sent event
+///
+/// 参考 https://html.spec.whatwg.org/multipage/server-sent-events.html#event-stream-interpretation
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    /// 事件类型，未指定时为 `message`
+    pub event: String,
+    /// 多个data字段以 `\n` 连接
+    pub data: String,
+    pub id: Option<String>,
+    pub retry: Option<u64>,
+}
+
+/// 增量解码器，将任意切分的字节流还原为行或事件
+pub trait StreamDecoder: Send + 'static {
+    type Item: Send;
+    fn feed(&mut self, chunk: &[u8]) -> Vec<Self::Item>;
+    /// 流结束时取出残留的内容
+    fn finish(&mut self) -> Option<Self::Item>;
+}
+
+/// 按行切分，兼容 `\n` `\r\n` `\r` 三种换行，行内容跨chunk时会被缓存
+#[derive(Debug, Default)]
+pub struct LineDecoder {
+    buf: Vec<u8>,
+    //上一个chunk以\r结尾，下一个chunk开头的\n需要忽略
+    skip_lf: bool,
+}
+
+impl StreamDecoder for LineDecoder {
+    type Item = String;
+
+    fn feed(&mut self, chunk: &[u8]) -> Vec<String> {
+        let mut lines = vec![];
+        for &b in chunk {
+            if self.skip_lf {
+                self.skip_lf = false;
+                if b == b'\n' {
+                    continue;
+                }
+            }
+            match b {
+                b'\n' => lines.push(self.take_line()),
+                b'\r' => {
+                    lines.push(self.take_line());
+                    self.skip_lf = true;
+                }
+                _ => self.buf.push(b),
+            }
+        }
+        lines
+    }
+
+    fn finish(&mut self) -> Option<String> {
+        if self.buf.is_empty() {
+            None
+        } else {
+            Some(self.take_line())
+        }
+    }
+}
+impl LineDecoder {
+    fn take_line(&mut self) -> String {
+        let line = String::from_utf8_lossy(self.buf.as_slice()).to_string();
+        self.buf.clear();
+        line
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    lines: LineDecoder,
+    event: String,
+    data: String,
+    has_data: bool,
+    last_id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl StreamDecoder for SseDecoder {
+    type Item = SseEvent;
+
+    fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.lines
+            .feed(chunk)
+            .into_iter()
+            .filter_map(|line| self.process_line(line.as_str()))
+            .collect()
+    }
+
+    fn finish(&mut self) -> Option<SseEvent> {
+        if let Some(line) = self.lines.finish() {
+            if let Some(ev) = self.process_line(line.as_str()) {
+                return Some(ev);
+            }
+        }
+        //规范要求丢弃未以空行结束的事件，这里放宽为仍然派发，兼容不规范的服务端
+        self.dispatch()
+    }
+}
+
+impl SseDecoder {
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((f, v)) => (f, v.strip_prefix(' ').unwrap_or(v)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                if self.has_data {
+                    self.data.push('\n');
+                }
+                self.data.push_str(value);
+                self.has_data = true;
+            }
+            "id" if !value.contains('\0') => self.last_id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(r) = value.parse::<u64>() {
+                    self.retry = Some(r);
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = std::mem::take(&mut self.event);
+        if !self.has_data {
+            return None;
+        }
+        self.has_data = false;
+        let event = if event.is_empty() {
+            "message".to_string()
+        } else {
+            event
+        };
+        Some(SseEvent {
+            event,
+            data: std::mem::take(&mut self.data),
+            id: self.last_id.clone(),
+            retry: self.retry.take(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::{LineDecoder, SseDecoder, SseEvent, StreamDecoder};
+
+    fn decode_all(chunks: &[&[u8]]) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::default();
+        let mut list = vec![];
+        for i in chunks {
+            list.extend(decoder.feed(i));
+        }
+        list.extend(decoder.finish());
+        list
+    }
+
+    #[test]
+    fn test_sse_chunk_boundary() {
+        let body = "data: {\"text\":\"你好\"}\n\ndata: [DONE]\n\n".as_bytes();
+        //逐字节切分，中文字符也会被切开
+        let chunks = body.chunks(1).collect::<Vec<_>>();
+        let list = decode_all(chunks.as_slice());
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].data, "{\"text\":\"你好\"}");
+        assert_eq!(list[0].event, "message");
+        assert_eq!(list[1].data, "[DONE]");
+    }
+
+    #[test]
+    fn test_sse_fields() {
+        let body = b": comment\r\nevent: delta\r\nid: 7\r\nretry: 3000\r\ndata: a\r\ndata:b\r\n\r\nevent: empty\r\n\r\ndata\r\n\r\n";
+        let list = decode_all(&[&body[..10], &body[10..]]);
+        assert_eq!(list.len(), 2);
+        assert_eq!(
+            list[0],
+            SseEvent {
+                event: "delta".into(),
+                data: "a\nb".into(),
+                id: Some("7".into()),
+                retry: Some(3000),
+            }
+        );
+        //没有data的事件不派发，id会沿用
+        assert_eq!(list[1].event, "message");
+        assert_eq!(list[1].data, "");
+        assert_eq!(list[1].id, Some("7".into()));
+    }
+
+    #[test]
+    fn test_line_decoder() {
+        let mut decoder = LineDecoder::default();
+        let mut lines = decoder.feed(b"{\"a\":1}\r");
+        lines.extend(decoder.feed(b"\n{\"a\""));
+        lines.extend(decoder.feed(b":2}"));
+        lines.extend(decoder.finish());
+        assert_eq!(lines, vec!["{\"a\":1}", "{\"a\":2}"]);
+    }
+}
@@ -1 +1,26 @@
 pub const CHAT_WINDOW_INIT_SIZE: (f32, f32) = (800.0, 600.0);
+/// 存在时优先使用该文件作为助手的提示词模板
+pub const ASSISTANT_PROMPT_FILE: &str = "./data/prompt/assistant.md";
+/// 提示词模板引用的partial所在目录
+pub const ASSISTANT_PARTIAL_DIR: &str = "./data/prompt/partials";
+pub const ASSISTANT_PROMPT: &str = "## ROLE: you are a ai assistant.
+- 当前时间：{{datetime}} {{weekday}}
+- 操作系统：{{os}}
+{{#if user}}
+- 用户：{{user}}
+{{/if}}";
+/// agent定义文件(toml/yaml)所在目录，id为default的定义会替换内置的助手
+pub const AGENT_DEFINE_DIR: &str = "./data/agents";
+/// 模型实例的注册文件，agent定义中可以用model.instance引用
+pub const MODEL_DEFINE_FILE: &str = "./data/models.toml";
+/// 模型价格表(toml/yaml)，与内置的价格合并，同名时以文件为准
+pub const PRICE_TABLE_FILE: &str = "./data/price.toml";
+/// 按天汇总的token用量
+pub const USAGE_LEDGER_FILE: &str = "./data/usage.json";
+/// 内置的dashscope价格，单位为元/百万token
+pub const DEFAULT_PRICES: &[(&str, f64, f64)] = &[
+    ("qwen-turbo", 0.3, 0.6),
+    ("qwen-plus", 0.8, 2.0),
+    ("qwen-max", 2.4, 9.6),
+    ("qwen-long", 0.5, 2.0),
+];
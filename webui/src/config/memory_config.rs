@@ -1,6 +1,19 @@
+use crate::config::const_config::{
+    AGENT_DEFINE_DIR, ASSISTANT_PARTIAL_DIR, ASSISTANT_PROMPT, ASSISTANT_PROMPT_FILE, DEFAULT_PRICES,
+    MODEL_DEFINE_FILE, PRICE_TABLE_FILE, USAGE_LEDGER_FILE,
+};
+use crate::pkg::AsyncRT;
+use agent::define::GlobalModel;
+use agent::embedding::DashScopeEmbedding;
+use agent::price::{ModelPrice, PriceTable};
 use agent::qwen::QwenModel;
-use agent::{ChatRespStream, SingleAgent};
+use agent::retry::RetryPolicy;
+use agent::{
+    AgentBuilder, ChatRespStream, JsonFileStore, KnowledgeBase, PromptEnv, SingleAgent, Usage, UsageLedger,
+};
 use std::ptr;
+use std::sync::Arc;
+use wd_tools::sync::Am;
 
 #[derive(Default, Eq, PartialEq, Clone)]
 pub enum WindowMode {
@@ -15,24 +28,207 @@ pub struct MemoryConfig {
     pub last_window_mode: WindowMode,
 
     pub assistant: SingleAgent,
+    //从配置目录加载的其他agent，切换时与assistant交换
+    pub agents: Vec<SingleAgent>,
     pub chat_stream_resp: Option<ChatRespStream>,
     pub assistant_msg: String,
+    //正在生成的思考过程
+    pub assistant_reasoning: String,
+
+    pub knowledge: Arc<KnowledgeBase>,
+    pub knowledge_dir: String,
+    //知识库导入的进度和片段总数，由导入任务更新，界面每帧只读这个字符串
+    pub knowledge_status: Arc<Am<String>>,
+
+    pub prices: PriceTable,
+    pub ledger: Arc<UsageLedger>,
+    //最近一轮回复的用量
+    pub turn_usage: Option<Usage>,
+    pub usage_summary: String,
 }
 impl Default for MemoryConfig {
     fn default() -> Self {
-        Self {
+        let knowledge = Arc::new(Self::open_knowledge());
+        let knowledge_status = format!("片段数: {}", AsyncRT::block_on(knowledge.len()));
+        let ledger = Arc::new(Self::open_ledger());
+        Self::load_models();
+        let mut agents = Self::load_agents(knowledge.clone());
+        let mut assistant = SingleAgent::new(QwenModel::default())
+            .set_knowledge(knowledge.clone())
+            .set_id("default")
+            .set_store(JsonFileStore::new("./data/conversation"))
+            .set_model_config(|cfg| cfg.name = "qwen-turbo".into())
+            .set_retry_policy(RetryPolicy::default())
+            .set_prompt(Self::assistant_prompt())
+            .cove_prompt_env(Self::assistant_prompt_env());
+        if let Some(i) = agents.iter().position(|x| x.id == assistant.id) {
+            assistant = agents.remove(i);
+        }
+        for agent in std::iter::once(&mut assistant).chain(agents.iter_mut()) {
+            agent.ledger = Some(ledger.clone());
+        }
+        //恢复上次关闭前的对话
+        for agent in std::iter::once(&assistant).chain(agents.iter()) {
+            if let Err(e) = AsyncRT::block_on(agent.load_history()) {
+                wd_log::log_field("id", agent.id.as_str())
+                    .field("error", e)
+                    .error("MemoryConfig.default load chat history failed");
+            }
+        }
+        let mut cfg = Self {
             assistant_msg: Default::default(),
+            assistant_reasoning: Default::default(),
             chat_stream_resp: None,
             window_mode: Default::default(),
             last_window_mode: Default::default(),
-            assistant: SingleAgent::new(QwenModel::default())
-                .set_model_config(|cfg| cfg.name = "qwen-turbo".into())
-                .set_prompt("## ROLE: you are a ai assistant."),
-        }
+            assistant,
+            agents,
+            knowledge,
+            knowledge_dir: String::new(),
+            knowledge_status: Arc::new(Am::new(knowledge_status)),
+            prices: Self::load_prices(),
+            ledger,
+            turn_usage: None,
+            usage_summary: String::new(),
+        };
+        cfg.refresh_usage_summary();
+        cfg
     }
 }
 
 impl MemoryConfig {
+    //用户自定义的提示词模板优先
+    fn assistant_prompt() -> String {
+        std::fs::read_to_string(ASSISTANT_PROMPT_FILE).unwrap_or_else(|_| ASSISTANT_PROMPT.to_string())
+    }
+    fn assistant_prompt_env() -> PromptEnv {
+        let env = PromptEnv::new();
+        if !std::path::Path::new(ASSISTANT_PARTIAL_DIR).is_dir() {
+            return env;
+        }
+        env.load_partials(ASSISTANT_PARTIAL_DIR).unwrap_or_else(|e| {
+            wd_log::log_field("error", e).error("MemoryConfig.assistant_prompt_env load partials failed");
+            PromptEnv::new()
+        })
+    }
+    //注册配置文件中的模型实例，需要在加载agent之前
+    fn load_models() {
+        if !std::path::Path::new(MODEL_DEFINE_FILE).is_file() {
+            return;
+        }
+        if let Err(e) = GlobalModel::load(MODEL_DEFINE_FILE) {
+            wd_log::log_field("error", e).error("MemoryConfig.load_models failed");
+        }
+    }
+    //价格文件有错时只使用内置的价格
+    fn load_prices() -> PriceTable {
+        let table = DEFAULT_PRICES.iter().fold(PriceTable::new("¥"), |t, (name, input, output)| {
+            t.set_price(*name, ModelPrice::new(*input, *output))
+        });
+        if !std::path::Path::new(PRICE_TABLE_FILE).is_file() {
+            return table;
+        }
+        match PriceTable::load(PRICE_TABLE_FILE) {
+            Ok(o) => table.merge(o),
+            Err(e) => {
+                wd_log::log_field("error", e).error("MemoryConfig.load_prices failed");
+                table
+            }
+        }
+    }
+    fn open_ledger() -> UsageLedger {
+        UsageLedger::open(USAGE_LEDGER_FILE).unwrap_or_else(|e| {
+            wd_log::log_field("error", e).error("MemoryConfig.open_ledger failed");
+            UsageLedger::new()
+        })
+    }
+    //回复结束、切换助手后刷新，避免每帧都去读账本
+    pub fn refresh_usage_summary(&mut self) {
+        let model = self.assistant.model_config.name.as_str();
+        let mut list = vec![];
+        if let Some(usage) = self.turn_usage {
+            list.push(format!("本轮: {}", self.usage_text(model, &usage)));
+        }
+        let usage = AsyncRT::block_on(self.assistant.usage());
+        list.push(format!("本会话: {}", self.usage_text(model, &usage)));
+        let today = AsyncRT::block_on(self.ledger.day(UsageLedger::today().as_str()));
+        let tokens = today.values().map(|x| x.total_tokens).sum::<usize>();
+        let cost = self.prices.total_cost(today.iter());
+        list.push(format!("今日: {tokens} tokens {}", self.prices.format(cost)));
+        self.usage_summary = list.join(" | ");
+    }
+    fn usage_text(&self, model: &str, usage: &Usage) -> String {
+        match self.prices.cost(model, usage) {
+            Some(cost) => format!("{} tokens {}", usage.total_tokens, self.prices.format(cost)),
+            None => format!("{} tokens", usage.total_tokens),
+        }
+    }
+    //单个文件加载失败只记录日志
+    fn load_agents(knowledge: Arc<KnowledgeBase>) -> Vec<SingleAgent> {
+        if !std::path::Path::new(AGENT_DEFINE_DIR).is_dir() {
+            return vec![];
+        }
+        let builder = AgentBuilder::new().set_knowledge(knowledge);
+        let report = match builder.load_dir(AGENT_DEFINE_DIR) {
+            Ok(o) => o,
+            Err(e) => {
+                wd_log::log_field("error", e).error("MemoryConfig.load_agents failed");
+                return vec![];
+            }
+        };
+        for (file, error) in report.failed {
+            wd_log::log_field("file", file)
+                .field("error", error)
+                .error("MemoryConfig.load_agents load agent define failed");
+        }
+        report.agents
+    }
+    //回复中不允许切换
+    pub fn switch_assistant(&mut self, index: usize) {
+        if index >= self.agents.len() || !self.assistant.status_is_usable() {
+            return;
+        }
+        std::mem::swap(&mut self.assistant, &mut self.agents[index]);
+        self.chat_stream_resp = None;
+        self.assistant_msg = String::new();
+        self.assistant_reasoning = String::new();
+        self.turn_usage = None;
+        self.refresh_usage_summary();
+    }
+    //没有配置dashscope的key时只使用关键词检索
+    fn open_knowledge() -> KnowledgeBase {
+        let kb = KnowledgeBase::open("./data/knowledge").unwrap_or_else(|e| {
+            wd_log::log_field("error", e).error("MemoryConfig.open_knowledge failed");
+            KnowledgeBase::new().set_dir("./data/knowledge")
+        });
+        match std::env::var("DASHSCOPE_API_KEY") {
+            Ok(key) if !key.is_empty() => kb.set_embedding(DashScopeEmbedding::new(key)),
+            _ => kb,
+        }
+    }
+    //在后台把目录导入知识库
+    pub fn add_knowledge_dir(&mut self) {
+        let dir = std::mem::take(&mut self.knowledge_dir).trim().to_string();
+        if dir.is_empty() {
+            return;
+        }
+        let kb = self.knowledge.clone();
+        let status = self.knowledge_status.clone();
+        *status.synchronize() = format!("导入中: {dir}");
+        AsyncRT::spawn(async move {
+            let msg = match kb.add_dir(dir.as_str()).await {
+                Ok(r) => format!(
+                    "导入完成: {}个文件, {}个片段, {}个失败",
+                    r.files,
+                    r.chunks,
+                    r.failed.len()
+                ),
+                Err(e) => format!("导入失败: {e}"),
+            };
+            let total = kb.len().await;
+            *status.lock().await = format!("{msg}\n片段数: {total}");
+        });
+    }
     //是否切换了窗口
     pub fn check_window_mode_change(&mut self) -> bool {
         let result = self.window_mode != self.last_window_mode;
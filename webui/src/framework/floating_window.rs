@@ -1,24 +1,61 @@
 use crate::config::Config;
 use crate::pkg::AsyncRT;
-use agent::{Agent, MessageType};
+use agent::content::{mime_type, ContentPart, MediaSource};
+use agent::error::ModelError;
+use agent::{Agent, AgentStatus, MessageType, ResponseEvent};
 use eframe::egui::{
-    CentralPanel, Context, Id, PointerButton, ScrollArea, Sense, SidePanel, TopBottomPanel, Ui,
+    CentralPanel, CollapsingHeader, Context, Id, PointerButton, ScrollArea, Sense, SidePanel, TopBottomPanel, Ui,
     Vec2, ViewportCommand,
 };
 use eframe::{egui, Frame};
+use std::path::PathBuf;
+use wd_tools::Base64StdEncode;
 
 #[derive(Default)]
 pub struct FloatingWindow {
     input: String,
+    //拖入或粘贴的图片和文件，随下一次提问发送
+    attachments: Vec<ContentPart>,
 }
 
 impl FloatingWindow {
     fn show_assistant_info(&mut self, ctx: &Context, ui: &mut Ui, cfg: &mut Config) {
         ui.horizontal_top(|ui| {
             let status = cfg.memory_cfg.assistant.get_status();
-            let status = format!("status:{status}");
-            ui.label(status);
+            if !cfg.memory_cfg.agents.is_empty() {
+                let mut switch = None;
+                ui.add_enabled_ui(status == AgentStatus::Usable, |ui| {
+                    egui::ComboBox::from_id_salt("FloatingWindow.show_assistant_info.agent")
+                        .selected_text(cfg.memory_cfg.assistant.id.as_str())
+                        .show_ui(ui, |ui| {
+                            for (i, agent) in cfg.memory_cfg.agents.iter().enumerate() {
+                                if ui.selectable_label(false, agent.id.as_str()).clicked() {
+                                    switch = Some(i);
+                                }
+                            }
+                        });
+                });
+                if let Some(i) = switch {
+                    cfg.memory_cfg.switch_assistant(i);
+                }
+            }
+            ui.label(format!("status:{status}"));
+            if status == AgentStatus::Replying && ui.button("停止").clicked() {
+                AsyncRT::block_on(cfg.memory_cfg.assistant.cancel());
+            }
         });
+        CollapsingHeader::new("知识库")
+            .default_open(false)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut cfg.memory_cfg.knowledge_dir).hint_text("目录路径"));
+                    if ui.button("添加目录").clicked() {
+                        cfg.memory_cfg.add_knowledge_dir();
+                    }
+                });
+                ui.label(cfg.memory_cfg.knowledge_status.synchronize().as_str());
+            });
+        ui.label(cfg.memory_cfg.usage_summary.as_str());
         ui.separator();
         // TopBottomPanel::top("FloatingWindow.show_assistant_info.Top").show(ctx,|ui|{
         //
@@ -32,7 +69,7 @@ impl FloatingWindow {
             });
             //渲染历史消息
             let lock = cfg.memory_cfg.assistant.history.synchronize();
-            for (_i, e) in lock.iter().enumerate() {
+            for (i, e) in lock.iter().enumerate() {
                 match e.role {
                     MessageType::SYSTEM => {
                         ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
@@ -43,8 +80,16 @@ impl FloatingWindow {
                         ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
                             ui.add(egui::Label::new(egui::RichText::new(format!("User: \n{}", e.content)).color(egui::Color32::WHITE).background_color(egui::Color32::GREEN)).wrap_mode(egui::TextWrapMode::Wrap));
                         });
+                        if !e.parts.is_empty() {
+                            ui.horizontal_wrapped(|ui| {
+                                for part in e.parts.iter() {
+                                    show_part(ui, part);
+                                }
+                            });
+                        }
                     }
                     MessageType::Assistant => {
+                        show_reasoning(ui, i, e.reasoning.as_str());
                         ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
                             ui.add(egui::Label::new(egui::RichText::new(format!("Assistant: \n{}", e.content)).color(egui::Color32::WHITE).background_color(egui::Color32::BLUE)).wrap_mode(egui::TextWrapMode::Wrap))
                         });
@@ -54,6 +99,7 @@ impl FloatingWindow {
                 }
             }
             //渲染最新的消息
+            show_reasoning(ui, "FloatingWindow.show_history.reasoning", cfg.memory_cfg.assistant_reasoning.as_str());
             if !cfg.memory_cfg.assistant_msg.is_empty() {
                 ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
                     ui.add(egui::Label::new(egui::RichText::new(format!("Assistant: \n{}",cfg.memory_cfg.assistant_msg)).color(egui::Color32::WHITE).background_color(egui::Color32::BLUE)).wrap_mode(egui::TextWrapMode::Wrap))
@@ -61,42 +107,100 @@ impl FloatingWindow {
             }
         });
     }
+    //拖入的文件，以及粘贴的图片路径或链接，粘贴的内容被识别为附件时不再写入输入框
+    fn collect_attachments(&mut self, ctx: &Context) {
+        let dropped = ctx.input(|i| i.raw.dropped_files.clone());
+        for file in dropped {
+            if let Some(path) = file.path {
+                self.attachments.push(attachment_from_path(path));
+            } else if let Some(bytes) = file.bytes {
+                let mime = mime_type(std::path::Path::new(file.name.as_str()));
+                if mime.starts_with("image/") {
+                    self.attachments.push(ContentPart::image_base64(mime, bytes.base64_encode_std()));
+                }
+            }
+        }
+        ctx.input_mut(|i| {
+            i.events.retain(|e| {
+                let egui::Event::Paste(text) = e else {
+                    return true;
+                };
+                match attachment_from_text(text) {
+                    Some(part) => {
+                        self.attachments.push(part);
+                        false
+                    }
+                    None => true,
+                }
+            })
+        });
+    }
+    fn show_attachments(&mut self, ui: &mut Ui) {
+        if self.attachments.is_empty() {
+            return;
+        }
+        let mut remove = None;
+        ui.horizontal_wrapped(|ui| {
+            for (i, part) in self.attachments.iter().enumerate() {
+                show_part(ui, part);
+                if ui.small_button("✕").clicked() {
+                    remove = Some(i);
+                }
+            }
+        });
+        if let Some(i) = remove {
+            self.attachments.remove(i);
+        }
+    }
     fn input(&mut self, ctx: &Context, ui: &mut Ui, cfg: &mut Config) {
+        self.collect_attachments(ctx);
         ui.with_layout(egui::Layout::bottom_up(egui::Align::Min),|ui|{
             ui.horizontal(|ui| {
                 let resp = ui.add(egui::TextEdit::multiline(&mut self.input));
                 if resp.has_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
-                    if self.input.is_empty() {
+                    if self.input.is_empty() && self.attachments.is_empty() {
                         return;
                     }
                     cfg.memory_cfg.assistant_msg = String::new();
+                    cfg.memory_cfg.assistant_reasoning = String::new();
                     let input = std::mem::take(&mut self.input);
-                    let fut = cfg.memory_cfg.assistant.chat(input);
+                    let parts = std::mem::take(&mut self.attachments);
+                    let fut = cfg.memory_cfg.assistant.chat_with_parts(input, parts);
                     match AsyncRT::block_on(fut) {
                         Ok(o) => cfg.memory_cfg.chat_stream_resp = Some(o),
                         Err(e) => {
-                            cfg.memory_cfg.assistant_msg = e.to_string();
+                            cfg.memory_cfg.assistant_msg = error_message(&e);
                         }
                     };
                 }
             });
+            self.show_attachments(ui);
         });
         //刷新消息
         if let Some(ref mut resp) = cfg.memory_cfg.chat_stream_resp {
-            match resp.next() {
-                Ok(o) => {
-                    if let Some(msg) = o {
-                        if msg.is_empty() {
-                            cfg.memory_cfg.chat_stream_resp = None;
-                            cfg.memory_cfg.assistant_msg = String::new();
-                        } else {
-                            cfg.memory_cfg.assistant_msg.push_str(msg.as_str());
-                        }
-                    }
+            match resp.try_recv() {
+                Some(ResponseEvent::Text(msg)) => {
+                    cfg.memory_cfg.assistant_msg.push_str(msg.as_str());
+                }
+                Some(ResponseEvent::Reasoning(msg)) => {
+                    cfg.memory_cfg.assistant_reasoning.push_str(msg.as_str());
+                }
+                Some(ResponseEvent::Usage(usage)) => {
+                    cfg.memory_cfg.turn_usage = Some(usage);
+                }
+                Some(ResponseEvent::Finish(_)) => {
+                    cfg.memory_cfg.chat_stream_resp = None;
+                    cfg.memory_cfg.assistant_msg = String::new();
+                    cfg.memory_cfg.assistant_reasoning = String::new();
+                    cfg.memory_cfg.refresh_usage_summary();
                 }
-                Err(e) => {
-                    cfg.memory_cfg.assistant_msg = e.to_string();
+                Some(ResponseEvent::Error(e)) => {
+                    cfg.memory_cfg.chat_stream_resp = None;
+                    cfg.memory_cfg.assistant_msg = error_message(&e);
+                    cfg.memory_cfg.assistant_reasoning = String::new();
+                    cfg.memory_cfg.refresh_usage_summary();
                 }
+                _ => {}
             };
         }
         //强制刷新
@@ -141,3 +245,74 @@ impl super::Window for FloatingWindow {
         });
     }
 }
+
+//图片显示缩略图，其他内容显示占位文本
+fn show_part(ui: &mut Ui, part: &ContentPart) {
+    let uri = match part {
+        ContentPart::Image {
+            source: MediaSource::Path { path },
+        } => format!("file://{}", path.display()),
+        ContentPart::Image {
+            source: MediaSource::Url { url },
+        } => url.clone(),
+        _ => {
+            ui.label(part.placeholder());
+            return;
+        }
+    };
+    ui.add(egui::Image::new(uri).max_height(64.0).max_width(64.0));
+}
+
+fn attachment_from_path(path: PathBuf) -> ContentPart {
+    if mime_type(path.as_path()).starts_with("image/") {
+        ContentPart::image_path(path)
+    } else {
+        ContentPart::file_path(path)
+    }
+}
+
+//粘贴的内容是单个图片文件的路径或者图片链接时作为附件
+fn attachment_from_text(text: &str) -> Option<ContentPart> {
+    let text = text.trim();
+    if text.contains('\n') {
+        return None;
+    }
+    if text.starts_with("http://") || text.starts_with("https://") {
+        let source = MediaSource::Url { url: text.to_string() };
+        let is_image = source.mime_type().is_some_and(|x| x.starts_with("image/"));
+        return is_image.then_some(ContentPart::Image { source });
+    }
+    let path = PathBuf::from(text.strip_prefix("file://").unwrap_or(text));
+    if path.is_file() && mime_type(path.as_path()).starts_with("image/") {
+        return Some(ContentPart::image_path(path));
+    }
+    None
+}
+
+//思考过程默认折叠，没有时不显示
+fn show_reasoning(ui: &mut Ui, id: impl std::hash::Hash, reasoning: &str) {
+    if reasoning.is_empty() {
+        return;
+    }
+    CollapsingHeader::new("思考过程").id_salt(id).default_open(false).show(ui, |ui| {
+        ui.add(egui::Label::new(egui::RichText::new(reasoning).color(egui::Color32::GRAY)).wrap_mode(egui::TextWrapMode::Wrap));
+    });
+}
+
+//按错误分类给出提示，方便区分是密钥、额度还是网络的问题
+fn error_message(e: &anyhow::Error) -> String {
+    let Some(err) = ModelError::classify(e) else {
+        return e.to_string();
+    };
+    let label = match err {
+        ModelError::Auth(_) => "鉴权失败",
+        ModelError::RateLimit { .. } => "限流",
+        ModelError::QuotaExceeded(_) => "额度不足",
+        ModelError::ContextLength(_) => "上下文超长",
+        ModelError::ContentFilter(_) => "内容被拦截",
+        ModelError::Network(_) => "网络错误",
+        ModelError::Server { .. } => "服务异常",
+        ModelError::Provider { .. } => "模型错误",
+    };
+    format!("[{label}] {e}")
+}
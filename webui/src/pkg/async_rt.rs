@@ -18,4 +18,10 @@ impl AsyncRT {
     pub fn block_on<F: Future>(fut: F) -> F::Output {
         AsyncRT::unsafe_mut_ptr(|x| x.rt.block_on(async move { fut.await }))
     }
+    //在后台执行，不阻塞界面
+    pub fn spawn<F: Future<Output = ()> + Send + 'static>(fut: F) {
+        AsyncRT::unsafe_mut_ptr(|x| {
+            x.rt.spawn(fut);
+        })
+    }
 }